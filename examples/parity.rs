@@ -0,0 +1,123 @@
+//! Runs the same initial state through the CPU and GPU backends and reports
+//! how far apart density, pressure, force and position drift each step.
+//!
+//! cargo run --release --example parity -- [--steps N] [--particles N]
+//!     [--scene FILE] [--tolerance X] [--mouse STRENGTH] [--hardware]
+//!
+//! The GPU side uses a fallback (software) adapter unless `--hardware` is
+//! given. Exits with a non-zero status if any step exceeds the tolerances,
+//! those of `Tolerances::for_solver` unless `--tolerance` sets all four.
+use fluid_simulation::Simulation;
+use fluid_simulation::constants::{ParamEnum, SimulationParams, Solver};
+use fluid_simulation::cpu::simulation::CpuSimulation;
+use fluid_simulation::gpu::simulation::GpuSimulation;
+use fluid_simulation::parity::{self, Divergence, Tolerances};
+use fluid_simulation::scene::Scene;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let mut steps = 5;
+    let mut params = SimulationParams {
+        no_particles: 4096,
+        width: 400.0,
+        height: 300.0,
+        ..Default::default()
+    };
+    let mut tolerance = None;
+    let mut force_fallback_adapter = true;
+    let mut colliders = Vec::new();
+    let mut bodies = Vec::new();
+    let mut materials = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().expect("missing value for flag");
+        match arg.as_str() {
+            "--steps" => steps = value().parse().expect("--steps takes an integer"),
+            "--scene" => {
+                let path = value();
+                let scene = Scene::load(&path)
+                    .unwrap_or_else(|e| panic!("failed to load scene {}: {}", path, e));
+                params = scene.params;
+                colliders = scene.colliders;
+                bodies = scene.bodies;
+                materials = scene.materials;
+            }
+            "--particles" => {
+                params.no_particles = value().parse().expect("--particles takes an integer")
+            }
+            "--tolerance" => {
+                tolerance = Some(value().parse().expect("--tolerance takes a number"));
+            }
+            "--mouse" => {
+                // park the mouse in the middle of the block so
+                // mouse_delta_vel/IOInteraction::delta_vel get exercised
+                params.mouse_strength = value().parse().expect("--mouse takes a number");
+                params.mouse_pos = [params.width / 2.0, params.height / 2.0];
+            }
+            "--hardware" => force_fallback_adapter = false,
+            other => panic!("unknown argument {}", other),
+        }
+    }
+
+    if let Err(e) = params.validate() {
+        panic!("{}", e);
+    }
+    // one fixed step, the same the front-ends take
+    params.dt = params.fixed_dt();
+    let tolerances = match tolerance {
+        Some(tolerance) => Tolerances {
+            density: tolerance,
+            pressure: tolerance,
+            force: tolerance,
+            position: tolerance,
+        },
+        None => Tolerances::for_solver(Solver::from_u32(params.solver)),
+    };
+    let mut cpu = CpuSimulation::new(params);
+    let mut gpu = pollster::block_on(GpuSimulation::headless(params, force_fallback_adapter));
+    cpu.set_colliders(colliders.clone());
+    gpu.set_colliders(colliders);
+    cpu.set_bodies(bodies.clone());
+    gpu.set_bodies(bodies);
+    cpu.set_materials(materials.clone());
+    gpu.set_materials(materials);
+    let report = parity::run(&mut cpu, &mut gpu, steps, tolerances);
+
+    let show = |d: &Divergence| format!("{:>10.3e} ({:>9.2e})", d.max_abs, d.max_rel);
+    println!(
+        "{:>5} | {:^22} | {:^22} | {:^22} | {:^22}",
+        "step", "density", "pressure", "force", "position"
+    );
+    for step in &report.steps {
+        println!(
+            "{:>5} | {} | {} | {} | {} {}",
+            step.step,
+            show(&step.density),
+            show(&step.pressure),
+            show(&step.force),
+            show(&step.position),
+            if step.within(&report.tolerances) {
+                ""
+            } else {
+                "<- exceeds tolerance"
+            }
+        );
+    }
+    println!("max absolute divergence (relative to the CPU field's largest magnitude)");
+
+    match report.first_failure() {
+        None => ExitCode::SUCCESS,
+        Some(step) => {
+            eprintln!(
+                "backends diverged past tolerance at step {} (worst particle: density {}, pressure {}, force {}, position {})",
+                step.step,
+                step.density.worst_particle,
+                step.pressure.worst_particle,
+                step.force.worst_particle,
+                step.position.worst_particle
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
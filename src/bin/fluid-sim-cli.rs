@@ -0,0 +1,329 @@
+//! Runs the solver without a window.
+//!
+//! fluid-sim-cli [--backend cpu|gpu] [--fallback-adapter]
+//!     [--steps N | --seconds T] [--scene FILE] [--resume SNAPSHOT]
+//!     [--print-every N] [--output DIR --output-every N]
+//!     [--<parameter> VALUE]...
+//!
+//! Every `SimulationParams` field is accepted as a flag with dashes instead of
+//! underscores, e.g. `--gas-constant 90000 --no-particles 20000 --gravity 0,300`,
+//! except `dt`: the step length is 1/`--physics-rate`, as in the windowed app.
+use fluid_simulation::Simulation;
+use fluid_simulation::constants::SimulationParams;
+use fluid_simulation::cpu::simulation::CpuSimulation;
+use fluid_simulation::gpu::simulation::GpuSimulation;
+use fluid_simulation::material;
+use fluid_simulation::rigid_body;
+use fluid_simulation::scene::Scene;
+use fluid_simulation::snapshot::Snapshot;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const DEFAULT_STEPS: u64 = 1000;
+
+enum Backend {
+    Cpu,
+    Gpu,
+}
+
+enum Budget {
+    Steps(u64),
+    Seconds(f64),
+}
+
+struct Options {
+    backend: Backend,
+    force_fallback_adapter: bool,
+    budget: Budget,
+    scene: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    print_every: u64,
+    output: Option<PathBuf>,
+    output_every: u64,
+    overrides: Vec<(String, String)>,
+}
+
+fn usage() -> String {
+    let defaults = SimulationParams::default();
+    let mut usage = String::from(
+        "usage: fluid-sim-cli [options] [--<parameter> VALUE]...\n\n\
+         options:\n  \
+           --backend cpu|gpu      solver backend (default gpu)\n  \
+           --fallback-adapter     run the GPU backend on a software adapter\n  \
+           --steps N              number of steps to run (default 1000)\n  \
+           --seconds T            run until T simulated seconds instead\n  \
+           --scene FILE           load parameters from a TOML scene file\n  \
+           --resume FILE          start from a snapshot, a scene or flags given here override its parameters\n  \
+           --print-every N        print a summary line every N steps (default 100, 0 = never)\n  \
+           --output DIR           write snapshots into DIR\n  \
+           --output-every N       steps between snapshots (default 100)\n\n\
+         parameters (default):\n",
+    );
+    for name in SimulationParams::field_names() {
+        if name == "dt" {
+            continue;
+        }
+        usage.push_str(&format!(
+            "  --{:<24} {}\n",
+            name.replace('_', "-"),
+            defaults.get_field(&name).unwrap_or_default()
+        ));
+    }
+    usage
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        backend: Backend::Gpu,
+        force_fallback_adapter: false,
+        budget: Budget::Steps(DEFAULT_STEPS),
+        scene: None,
+        resume: None,
+        print_every: 100,
+        output: None,
+        output_every: 100,
+        overrides: Vec::new(),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(format!("unexpected argument '{}'", arg));
+        };
+        if flag == "help" {
+            print!("{}", usage());
+            std::process::exit(0);
+        }
+        if flag == "fallback-adapter" {
+            options.force_fallback_adapter = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for --{}", flag))?;
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("--{} takes a whole number, got '{}'", flag, value))
+        };
+        match flag {
+            "backend" => {
+                options.backend = match value.as_str() {
+                    "cpu" => Backend::Cpu,
+                    "gpu" => Backend::Gpu,
+                    _ => return Err(format!("unknown backend '{}', expected cpu or gpu", value)),
+                }
+            }
+            "steps" => options.budget = Budget::Steps(number(&value)?),
+            "seconds" => {
+                options.budget = Budget::Seconds(
+                    value
+                        .parse()
+                        .map_err(|_| format!("--seconds takes a number, got '{}'", value))?,
+                )
+            }
+            "scene" => options.scene = Some(PathBuf::from(value)),
+            "resume" => options.resume = Some(PathBuf::from(value)),
+            "print-every" => options.print_every = number(&value)?,
+            "output" => options.output = Some(PathBuf::from(value)),
+            "output-every" => options.output_every = number(&value)?.max(1),
+            "dt" => {
+                return Err(
+                    "--dt is not accepted, every step is 1/--physics-rate seconds long".to_string(),
+                );
+            }
+            _ => options.overrides.push((flag.replace('-', "_"), value)),
+        }
+    }
+    Ok(options)
+}
+
+fn print_summary(sim: &dyn Simulation, step: u64, start: std::time::Instant) {
+    let particles = sim.read_particles();
+    let mean_density =
+        particles.iter().map(|p| p.density).sum::<f32>() / particles.len().max(1) as f32;
+    let max_speed = particles
+        .iter()
+        .map(|p| glam::Vec2::from(p.vel).length())
+        .fold(0.0f32, f32::max);
+    let solver = match sim.solver_stats() {
+        Some(stats) if stats.divergence_iterations > 0 => format!(
+            " | {} iterations, density error {:.3}% | {} divergence iterations, divergence error {:.3}%",
+            stats.iterations,
+            stats.density_error * 100.0,
+            stats.divergence_iterations,
+            stats.divergence_error * 100.0
+        ),
+        Some(stats) => format!(
+            " | {} iterations, density error {:.3}%",
+            stats.iterations,
+            stats.density_error * 100.0
+        ),
+        None => String::new(),
+    };
+    println!(
+        "step {:>7} | t = {:.4}s | dt = {:.5}s | mean density {:.5} | max speed {:.2}{} | {:.2?} elapsed",
+        step,
+        sim.time(),
+        sim.params().dt,
+        mean_density,
+        max_speed,
+        solver,
+        start.elapsed()
+    );
+}
+
+// How many steps from `step` until the next one that prints, writes a
+// snapshot or ends the run.
+fn steps_to_next_stop(options: &Options, sim: &dyn Simulation, step: u64) -> u32 {
+    let until = |every: u64| every - step % every;
+    let mut steps = match options.budget {
+        Budget::Steps(steps) => steps - step,
+        Budget::Seconds(seconds) => {
+            let dt = sim.params().dt as f64;
+            if dt > 0.0 {
+                ((seconds - sim.time()) / dt).ceil().max(1.0) as u64
+            } else {
+                1
+            }
+        }
+    };
+    if options.print_every > 0 {
+        steps = steps.min(until(options.print_every));
+    }
+    if options.output.is_some() {
+        steps = steps.min(until(options.output_every));
+    }
+    steps.min(u32::MAX as u64) as u32
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let snapshot = match &options.resume {
+        Some(path) => Some(
+            Snapshot::load(path)
+                .map_err(|e| format!("failed to load snapshot {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
+    // defaults < snapshot < scene file < individual flags
+    let mut params = match &snapshot {
+        Some(snapshot) => snapshot.params,
+        None => SimulationParams::default(),
+    };
+    let (mut colliders, mut bodies) = match &snapshot {
+        Some(snapshot) => (snapshot.colliders.clone(), snapshot.bodies.clone()),
+        None => (Vec::new(), Vec::new()),
+    };
+    // a snapshot's materials are restored with its particles' phases, a
+    // scene's are set afterwards and split the particles by index
+    let mut materials = None;
+    if let Some(path) = &options.scene {
+        let scene = Scene::load(path)
+            .map_err(|e| format!("failed to load scene {}: {}", path.display(), e))?;
+        params = scene.params;
+        colliders = scene.colliders;
+        bodies = scene.bodies;
+        materials = Some(scene.materials);
+    }
+    for (name, value) in &options.overrides {
+        params.set_field(name, value)?;
+    }
+    // a --solver flag can pick one the scene's materials do not run on
+    let live_materials: &[_] = match (&materials, &snapshot) {
+        (Some(materials), _) => &materials[..],
+        (None, Some(snapshot)) => &snapshot.materials[..],
+        (None, None) => &[],
+    };
+    material::check_solver(live_materials, &params)?;
+    rigid_body::check_params(&bodies, &params)?;
+    // the step length comes from physics_rate, the same as in the windowed
+    // app, so a scene advances the same way in both front-ends
+    params.dt = params.fixed_dt();
+    if let Some(snapshot) = &snapshot {
+        // buffers are sized from params, so the snapshot's particles must fit
+        params.no_particles = params.no_particles.min(snapshot.particles.len() as u32);
+    }
+
+    let mut sim: Box<dyn Simulation> = match options.backend {
+        Backend::Cpu => Box::new(CpuSimulation::new(params)),
+        Backend::Gpu => Box::new(pollster::block_on(GpuSimulation::headless(
+            params,
+            options.force_fallback_adapter,
+        ))),
+    };
+    if let Some(snapshot) = &snapshot {
+        sim.restore(snapshot);
+        sim.set_params(params);
+    }
+    sim.set_colliders(colliders);
+    sim.set_bodies(bodies);
+    if let Some(materials) = materials {
+        sim.set_materials(materials);
+    }
+
+    if let Some(dir) = &options.output {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    }
+
+    println!(
+        "Running {} particles on the {} backend",
+        sim.params().no_particles,
+        match options.backend {
+            Backend::Cpu => "CPU",
+            Backend::Gpu => "GPU",
+        }
+    );
+
+    let start = std::time::Instant::now();
+    let mut step = 0u64;
+    loop {
+        let done = match options.budget {
+            Budget::Steps(steps) => step >= steps,
+            Budget::Seconds(seconds) => sim.time() >= seconds,
+        };
+        if done {
+            break;
+        }
+        // every adaptive step is sized from the one before it, fixed steps
+        // go to the backend together up to the next one that is looked at
+        let steps = match sim.cfl_dt() {
+            Some(dt) => {
+                let mut params = *sim.params();
+                params.dt = dt;
+                sim.set_params(params);
+                1
+            }
+            None => steps_to_next_stop(&options, sim.as_ref(), step),
+        };
+        sim.step_many(steps);
+        step += steps as u64;
+
+        if options.print_every > 0 && step.is_multiple_of(options.print_every) {
+            print_summary(sim.as_ref(), step, start);
+        }
+        if let Some(dir) = &options.output
+            && step.is_multiple_of(options.output_every)
+        {
+            let path = dir.join(format!("snapshot_{:07}.bin", step));
+            sim.snapshot()
+                .save(&path)
+                .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        }
+    }
+    if options.print_every > 0 && !step.is_multiple_of(options.print_every) {
+        print_summary(sim.as_ref(), step, start);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().and_then(run);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::constants::SimulationParams;
+use crate::cpu::kernels::smoothing_kernel;
+use crate::cpu::search;
+use crate::gpu::particle::GpuBoundaryParticle;
+use crate::rigid_body::{BodyShape, MAX_BODY_SAMPLES, NO_BODY, RigidBody};
+use glam::Vec2;
+
+// wall particles per influence radius, dense enough that a fluid particle
+// sees a smooth wall through the kernel rather than a row of bumps
+pub(crate) const SAMPLES_PER_INFLUENCE_RADIUS: f32 = 4.0;
+
+/// The static wall particles of Akinci et al. 2012, one row along each of the
+/// four walls, shared by both backends. They are sorted by the same cell hash
+/// as the fluid so the density and force passes find them with the usual 3x3
+/// cell search.
+///
+/// Each one carries the volume 1/Σ W of its own row, so a dense stretch of
+/// wall does not push harder than a sparse one. The fluid treats a wall
+/// particle as mass ρ₀V.
+///
+/// Rigid bodies are sampled the same way along their surfaces and added by
+/// `set_bodies` every step, so the fluid pushes off them like off the walls.
+pub struct BoundaryParticles {
+    pub particles: Vec<GpuBoundaryParticle>,
+    // the wall particles alone, volumes included
+    walls: Vec<GpuBoundaryParticle>,
+    // each body's shape and its surface particles in its own frame, which
+    // only change with the shape
+    body_samples: Vec<(BodyShape, Vec<(Vec2, f32)>)>,
+    // (cell id, particle index) sorted by cell and (start, count) per cell,
+    // the same layout `Particles::update` builds for the fluid
+    cells: Vec<(u32, usize)>,
+    lookups: Vec<(usize, usize)>,
+    // width, height, cell_size, influence_radius and kernel the walls were
+    // built for
+    built_for: [f32; 5],
+}
+
+impl BoundaryParticles {
+    pub fn new(params: &SimulationParams) -> Self {
+        let world_size = Vec2::new(params.width, params.height);
+        let spacing = params.influence_radius / SAMPLES_PER_INFLUENCE_RADIUS;
+        let columns = (world_size.x / spacing).ceil() as u32;
+        let rows = (world_size.y / spacing).ceil() as u32;
+
+        let mut positions = Vec::new();
+        // top and bottom walls, corners included
+        for i in 0..=columns {
+            let x = (i as f32 * spacing).min(world_size.x);
+            positions.push(Vec2::new(x, 0.0));
+            positions.push(Vec2::new(x, world_size.y));
+        }
+        // left and right walls between them
+        for j in 1..rows {
+            let y = j as f32 * spacing;
+            positions.push(Vec2::new(0.0, y));
+            positions.push(Vec2::new(world_size.x, y));
+        }
+
+        let mut boundary = Self {
+            particles: positions
+                .into_iter()
+                .map(|pos| GpuBoundaryParticle {
+                    pos: pos.into(),
+                    volume: 0.0,
+                    body: NO_BODY,
+                    force: [0.0, 0.0],
+                    _padding: [0.0, 0.0],
+                })
+                .collect(),
+            walls: Vec::new(),
+            body_samples: Vec::new(),
+            cells: Vec::new(),
+            lookups: Vec::new(),
+            built_for: Self::key(params),
+        };
+        boundary.sort(params);
+
+        let volumes: Vec<f32> = boundary
+            .particles
+            .iter()
+            .map(|particle| {
+                let pos = Vec2::from(particle.pos);
+                let mut kernel_sum = 0.0;
+                boundary.for_each_neighbour(pos, params, |other| {
+                    kernel_sum += smoothing_kernel(pos, Vec2::from(other.pos), params);
+                });
+                // the particle itself is always counted, so the sum is never 0
+                1.0 / kernel_sum
+            })
+            .collect();
+        for (particle, volume) in boundary.particles.iter_mut().zip(volumes) {
+            particle.volume = volume;
+        }
+        boundary.walls = boundary.particles.clone();
+        boundary
+    }
+
+    // Sorts the particles by the same cell hash as the fluid and rebuilds
+    // the cell ranges
+    fn sort(&mut self, params: &SimulationParams) {
+        let world_size = Vec2::new(params.width, params.height);
+        let grid_width = (world_size.x / params.cell_size).floor() as usize;
+        let grid_height = (world_size.y / params.cell_size).floor() as usize;
+
+        // the same clamp as the fluid's hash, the walls sit on the grid's edge
+        let mut keyed: Vec<(u32, GpuBoundaryParticle)> = self
+            .particles
+            .iter()
+            .map(|&particle| {
+                let clamped_pos = Vec2::from(particle.pos).clamp(Vec2::ZERO, world_size - 0.1);
+                let grid_coord = search::grid_coord(clamped_pos, params);
+                (search::hash(grid_coord, world_size, params), particle)
+            })
+            .collect();
+        keyed.sort_by_key(|k| k.0);
+
+        self.cells = keyed
+            .iter()
+            .enumerate()
+            .map(|(i, &(cell_id, _))| (cell_id, i))
+            .collect();
+        self.lookups = vec![(0usize, 0usize); grid_width * grid_height];
+        search::find_cell_start(&mut self.lookups, &self.cells);
+        self.particles = keyed.into_iter().map(|(_, particle)| particle).collect();
+    }
+
+    /// Replaces the body particles with ones along `bodies` where they are
+    /// now. Returns false if there were none before and are none now, so
+    /// nothing needs uploading. Past `MAX_BODY_SAMPLES` the rest are dropped.
+    pub fn set_bodies(&mut self, bodies: &[RigidBody], params: &SimulationParams) -> bool {
+        if bodies.is_empty() && self.particles.len() == self.walls.len() {
+            return false;
+        }
+        self.body_samples.truncate(bodies.len());
+        for (i, body) in bodies.iter().enumerate() {
+            if self
+                .body_samples
+                .get(i)
+                .is_none_or(|(shape, _)| *shape != body.shape)
+            {
+                let samples = (body.shape.clone(), body.local_boundary_particles(params));
+                if i < self.body_samples.len() {
+                    self.body_samples[i] = samples;
+                } else {
+                    self.body_samples.push(samples);
+                }
+            }
+        }
+
+        self.particles = self.walls.clone();
+        for (i, (body, (_, samples))) in bodies.iter().zip(&self.body_samples).enumerate() {
+            self.particles
+                .extend(body.place_boundary_particles(i as u32, samples));
+        }
+        self.particles.truncate(self.walls.len() + MAX_BODY_SAMPLES);
+        self.sort(params);
+        true
+    }
+
+    fn key(params: &SimulationParams) -> [f32; 5] {
+        [
+            params.width,
+            params.height,
+            params.cell_size,
+            params.influence_radius,
+            params.kernel as f32,
+        ]
+    }
+
+    /// Whether the domain or kernel changed since these walls were built.
+    pub fn is_stale(&self, params: &SimulationParams) -> bool {
+        self.built_for != Self::key(params)
+    }
+
+    /// Calls `f` with every wall particle in the 3x3 block of cells around
+    /// `pos`, in the same order as the WGSL passes.
+    pub fn for_each_neighbour(
+        &self,
+        pos: Vec2,
+        params: &SimulationParams,
+        mut f: impl FnMut(&GpuBoundaryParticle),
+    ) {
+        search::for_each_neighbour(pos, &self.cells, &self.lookups, params, |k| {
+            f(&self.particles[k])
+        });
+    }
+
+    /// The per-cell ranges as `[start, end)` pairs, the `Lookup` layout the
+    /// shaders read.
+    pub fn gpu_lookups(&self) -> Vec<[u32; 2]> {
+        self.lookups
+            .iter()
+            .map(|&(start, count)| [start as u32, (start + count) as u32])
+            .collect()
+    }
+}
@@ -0,0 +1,414 @@
+use crate::constants::SimulationParams;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+// the uniform the shaders read is fixed size, see GpuColliders
+pub const MAX_COLLIDERS: usize = 16;
+pub const MAX_COLLIDER_POINTS: usize = 128;
+
+// central difference step for the SDF gradient, in pixels
+const GRADIENT_STEP: f32 = 0.1;
+
+// Colliders live in scene files next to the parameters, one table each, e.g.
+//
+//     [[colliders]]
+//     shape = "circle"
+//     center = [850.0, 600.0]
+//     radius = 80.0
+//
+//     [[colliders]]
+//     shape = "box"
+//     center = [850.0, 600.0]
+//     half_size = [40.0, 200.0]
+//     operation = "subtract"
+//
+// They are combined in order, starting from empty space. Any of them can
+// also set a `temperature` to heat or cool the fluid touching it.
+
+/// A solid shape, described by its signed distance: negative inside, zero on
+/// the surface, positive outside.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum Shape {
+    Circle {
+        center: [f32; 2],
+        radius: f32,
+    },
+    /// A rectangle rotated by `angle` radians around its centre.
+    Box {
+        center: [f32; 2],
+        half_size: [f32; 2],
+        #[serde(default)]
+        angle: f32,
+    },
+    /// Every point within `radius` of the segment from `start` to `end`.
+    Capsule {
+        start: [f32; 2],
+        end: [f32; 2],
+        radius: f32,
+    },
+    /// A closed polygon, either winding, at least three points.
+    Polygon {
+        points: Vec<[f32; 2]>,
+    },
+}
+
+/// How a collider combines with the ones before it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Adds the shape to the solid.
+    #[default]
+    Union = 0,
+    /// Carves the shape out of the solid built so far.
+    Subtract = 1,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Collider {
+    #[serde(flatten)]
+    pub shape: Shape,
+    #[serde(default)]
+    pub operation: Operation,
+    /// Turns the shape inside out, so the solid is everything outside it and
+    /// the fluid is kept in.
+    #[serde(default)]
+    pub container: bool,
+    /// Makes the collider a heat source or sink: fluid within the influence
+    /// radius of its surface is pulled towards this temperature at
+    /// `heat_transfer` per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+}
+
+// the part of a scene file this module reads, the parameters are ignored
+#[derive(Default, Serialize, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    colliders: Vec<Collider>,
+}
+
+impl Collider {
+    /// Signed distance from `pos` to this collider on its own, containers
+    /// already inverted.
+    pub fn signed_distance(&self, pos: Vec2) -> f32 {
+        let distance = match &self.shape {
+            Shape::Circle { center, radius } => (pos - Vec2::from(*center)).length() - radius,
+            Shape::Box {
+                center,
+                half_size,
+                angle,
+            } => {
+                let local = Vec2::from_angle(-angle).rotate(pos - Vec2::from(*center));
+                let d = local.abs() - Vec2::from(*half_size);
+                d.max(Vec2::ZERO).length() + d.x.max(d.y).min(0.0)
+            }
+            Shape::Capsule { start, end, radius } => {
+                let start = Vec2::from(*start);
+                let pa = pos - start;
+                let ba = Vec2::from(*end) - start;
+                let length_squared = ba.length_squared();
+                let h = if length_squared > 0.0 {
+                    (pa.dot(ba) / length_squared).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                (pa - ba * h).length() - radius
+            }
+            Shape::Polygon { points } => polygon_distance(points, pos),
+        };
+        if self.container { -distance } else { distance }
+    }
+
+    /// Every collider of a scene file, none if it has no `[[colliders]]`.
+    pub fn load_scene(path: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Vec<Self>, String> {
+        let scene: Scene = toml::from_str(text).map_err(|e| e.to_string())?;
+        validate(&scene.colliders)?;
+        Ok(scene.colliders)
+    }
+
+    /// The `[[colliders]]` tables for `colliders`, to append to a scene file.
+    pub fn to_toml(colliders: &[Self]) -> String {
+        // nothing rather than an empty `colliders = []`, which would land in
+        // whichever table comes before it
+        if colliders.is_empty() {
+            return String::new();
+        }
+        toml::to_string(&Scene {
+            colliders: colliders.to_vec(),
+        })
+        .expect("colliders always serialize")
+    }
+}
+
+/// Checks that `colliders` fit in the GPU's fixed size uniform and that every
+/// polygon is closed.
+pub fn validate(colliders: &[Collider]) -> Result<(), String> {
+    if colliders.len() > MAX_COLLIDERS {
+        return Err(format!(
+            "{} colliders, at most {} are supported",
+            colliders.len(),
+            MAX_COLLIDERS
+        ));
+    }
+    let mut point_count = 0;
+    for collider in colliders {
+        if let Shape::Polygon { points } = &collider.shape {
+            if points.len() < 3 {
+                return Err(format!(
+                    "a polygon needs at least 3 points, got {}",
+                    points.len()
+                ));
+            }
+            point_count += points.len();
+        }
+    }
+    if point_count > MAX_COLLIDER_POINTS {
+        return Err(format!(
+            "{} polygon points, at most {} are supported",
+            point_count, MAX_COLLIDER_POINTS
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn polygon_distance(points: &[[f32; 2]], pos: Vec2) -> f32 {
+    // Inigo Quilez's polygon SDF: the distance to the closest edge, negated
+    // when a ray from pos crosses the edges an odd number of times
+    let Some(first) = points.first() else {
+        return f32::MAX;
+    };
+    let mut distance_squared = (pos - Vec2::from(*first)).length_squared();
+    let mut sign = 1.0;
+    let mut j = points.len() - 1;
+    for (i, point) in points.iter().enumerate() {
+        let vi = Vec2::from(*point);
+        let vj = Vec2::from(points[j]);
+        let e = vj - vi;
+        let w = pos - vi;
+        let b = w - e * (w.dot(e) / e.length_squared()).clamp(0.0, 1.0);
+        distance_squared = distance_squared.min(b.length_squared());
+        let above = pos.y >= vi.y;
+        let below = pos.y < vj.y;
+        let left = e.x * w.y > e.y * w.x;
+        // the edge crosses pos's row on the side the ray goes, either direction
+        if above == below && below == left {
+            sign = -sign;
+        }
+        j = i;
+    }
+    sign * distance_squared.sqrt()
+}
+
+/// Signed distance from `pos` to the solid `colliders` make up, combined in
+/// order. `f32::MAX` when there are none.
+pub fn signed_distance(colliders: &[Collider], pos: Vec2) -> f32 {
+    colliders
+        .iter()
+        .fold(f32::MAX, |distance, collider| match collider.operation {
+            Operation::Union => distance.min(collider.signed_distance(pos)),
+            Operation::Subtract => distance.max(-collider.signed_distance(pos)),
+        })
+}
+
+/// How fast the colliders with a temperature change the temperature of a
+/// particle at `pos`: every one whose surface is within the influence radius
+/// pulls it towards its own temperature at `heat_transfer` per second, each
+/// on its own regardless of how the colliders combine.
+pub fn heating(
+    colliders: &[Collider],
+    pos: Vec2,
+    temperature: f32,
+    params: &SimulationParams,
+) -> f32 {
+    colliders
+        .iter()
+        .filter_map(|collider| collider.temperature.map(|source| (collider, source)))
+        .filter(|(collider, _)| collider.signed_distance(pos) < params.influence_radius)
+        .map(|(_, source)| params.heat_transfer * (source - temperature))
+        .sum()
+}
+
+/// Pushes a particle that overlaps the colliders back out along the SDF
+/// gradient, then reflects the velocity into it with `collider_restitution`
+/// and slows the velocity along the surface by `collider_friction`.
+pub fn collide(colliders: &[Collider], pos: &mut Vec2, vel: &mut Vec2, params: &SimulationParams) {
+    if colliders.is_empty() {
+        return;
+    }
+    let distance = signed_distance(colliders, *pos);
+    if distance >= params.radius {
+        return;
+    }
+    let gradient = Vec2::new(
+        signed_distance(colliders, *pos + Vec2::X * GRADIENT_STEP)
+            - signed_distance(colliders, *pos - Vec2::X * GRADIENT_STEP),
+        signed_distance(colliders, *pos + Vec2::Y * GRADIENT_STEP)
+            - signed_distance(colliders, *pos - Vec2::Y * GRADIENT_STEP),
+    );
+    let length = gradient.length();
+    if length < 0.00001 {
+        return;
+    }
+    let normal = gradient / length;
+    *pos += normal * (params.radius - distance);
+
+    let normal_speed = vel.dot(normal);
+    if normal_speed < 0.0 {
+        let tangent_vel = *vel - normal * normal_speed;
+        *vel = tangent_vel * (1.0 - params.collider_friction)
+            - normal * normal_speed * params.collider_restitution;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collider(shape: Shape) -> Collider {
+        Collider {
+            shape,
+            operation: Operation::Union,
+            container: false,
+            temperature: None,
+        }
+    }
+
+    fn shapes() -> Vec<Shape> {
+        vec![
+            Shape::Circle {
+                center: [10.0, 20.0],
+                radius: 5.0,
+            },
+            Shape::Box {
+                center: [10.0, 20.0],
+                half_size: [8.0, 4.0],
+                angle: 0.3,
+            },
+            Shape::Capsule {
+                start: [4.0, 20.0],
+                end: [16.0, 20.0],
+                radius: 3.0,
+            },
+            // clockwise on screen, the other winding is checked below
+            Shape::Polygon {
+                points: vec![[4.0, 14.0], [16.0, 14.0], [16.0, 26.0], [4.0, 26.0]],
+            },
+        ]
+    }
+
+    // the same central difference collide pushes particles out along
+    fn gradient(colliders: &[Collider], pos: Vec2) -> Vec2 {
+        Vec2::new(
+            signed_distance(colliders, pos + Vec2::X * GRADIENT_STEP)
+                - signed_distance(colliders, pos - Vec2::X * GRADIENT_STEP),
+            signed_distance(colliders, pos + Vec2::Y * GRADIENT_STEP)
+                - signed_distance(colliders, pos - Vec2::Y * GRADIENT_STEP),
+        ) / (2.0 * GRADIENT_STEP)
+    }
+
+    #[test]
+    fn negative_inside_and_positive_outside() {
+        let centre = Vec2::new(10.0, 20.0);
+        for shape in shapes() {
+            let collider = collider(shape);
+            assert!(collider.signed_distance(centre) < 0.0, "{:?}", collider);
+            assert!(
+                collider.signed_distance(Vec2::new(40.0, 20.0)) > 0.0,
+                "{:?}",
+                collider
+            );
+            let container = Collider {
+                container: true,
+                ..collider.clone()
+            };
+            assert_eq!(
+                container.signed_distance(centre),
+                -collider.signed_distance(centre)
+            );
+        }
+        let reversed = collider(Shape::Polygon {
+            points: vec![[4.0, 26.0], [16.0, 26.0], [16.0, 14.0], [4.0, 14.0]],
+        });
+        assert!((reversed.signed_distance(Vec2::new(10.0, 20.0)) + 6.0).abs() < 1e-5);
+        assert!((reversed.signed_distance(Vec2::new(20.0, 20.0)) - 4.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn distances_are_exact_along_the_axes() {
+        let circle = collider(shapes().remove(0));
+        assert!((circle.signed_distance(Vec2::new(18.0, 20.0)) - 3.0).abs() < 1e-5);
+        let capsule = collider(shapes().remove(2));
+        assert!((capsule.signed_distance(Vec2::new(10.0, 25.0)) - 2.0).abs() < 1e-5);
+        assert!((capsule.signed_distance(Vec2::new(21.0, 20.0)) - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn gradients_point_out_of_the_solid() {
+        // where a ray from the centre along +x leaves each shape
+        let surfaces = [15.0, 10.0 + 8.0 / 0.3f32.cos(), 19.0, 16.0];
+        for (shape, surface) in shapes().into_iter().zip(surfaces) {
+            let colliders = [collider(shape)];
+            // outside, on the surface and inside, clear of the corners
+            for x in [surface + 1.5, surface, surface - 0.5] {
+                let pos = Vec2::new(x, 20.0);
+                let gradient = gradient(&colliders, pos);
+                assert!(
+                    (gradient.length() - 1.0).abs() < 1e-2,
+                    "{:?} at {}: |∇d| = {}",
+                    colliders[0].shape,
+                    pos,
+                    gradient.length()
+                );
+                assert!(gradient.x > 0.0, "{:?} at {}", colliders[0].shape, pos);
+            }
+            let on_surface = signed_distance(&colliders, Vec2::new(surface, 20.0));
+            assert!(on_surface.abs() < 1e-4, "{:?}", colliders[0].shape);
+        }
+    }
+
+    #[test]
+    fn subtract_carves_and_containers_keep_the_fluid_in() {
+        let walls = Collider {
+            container: true,
+            ..collider(Shape::Box {
+                center: [50.0, 50.0],
+                half_size: [50.0, 50.0],
+                angle: 0.0,
+            })
+        };
+        let hole = Collider {
+            operation: Operation::Subtract,
+            ..collider(Shape::Circle {
+                center: [0.0, 50.0],
+                radius: 10.0,
+            })
+        };
+        let colliders = [walls, hole];
+        // inside the walls is free, outside them is solid
+        assert!(signed_distance(&colliders, Vec2::new(50.0, 50.0)) > 0.0);
+        assert!(signed_distance(&colliders, Vec2::new(50.0, -5.0)) < 0.0);
+        // where the hole cuts through the wall is free again
+        assert!(signed_distance(&colliders, Vec2::new(-2.0, 50.0)) > 0.0);
+        assert!(gradient(&colliders, Vec2::new(50.0, 3.0)).y > 0.0);
+        assert_eq!(signed_distance(&[], Vec2::ZERO), f32::MAX);
+    }
+
+    #[test]
+    fn collide_moves_particles_out_to_their_radius() {
+        let params = SimulationParams::default();
+        let colliders = [collider(shapes().remove(0))];
+        let mut pos = Vec2::new(14.0, 20.0);
+        let mut vel = Vec2::new(-10.0, 0.0);
+        collide(&colliders, &mut pos, &mut vel, &params);
+        assert!((signed_distance(&colliders, pos) - params.radius).abs() < 1e-2);
+        assert!(vel.x > 0.0, "{}", vel);
+    }
+}
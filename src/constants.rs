@@ -1,9 +1,23 @@
+use crate::StepLimits;
 use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
 
 pub const NO_PARTICLES: u32 = 100000;
+pub const DEFAULT_SCENE_PATH: &str = "scene.toml";
 
+// Scene files are plain TOML with one key per field, e.g.
+//
+//     no_particles = 20000
+//     gas_constant = 90000.0
+//     gravity = [0.0, 300.0]
+//
+// Missing keys fall back to SimulationParams::default(), so a scene only
+// needs the fields it changes.
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SimulationParams {
     pub width: f32,                  //offset 0
     pub height: f32,                 //offset 4
@@ -21,13 +35,67 @@ pub struct SimulationParams {
     pub mouse_pos: [f32; 2],         //offset 56 (vec2 align 8, 56%8==0)
     pub mouse_strength: f32,         //offset 64 (signed: <0 repel, >0 attract, ==0 none)
     pub mouse_influence_radius: f32, //offset 68
-    pub _padding: [f32; 2],          //offset 72
-                                     // https://www.w3.org/TR/WGSL/#address-space-layout-constraints
-                                     // because this is going to be a uniform buffer
-                                     // i.e. roundUp(16, AlignOf(S))
-                                     // AlignOf(S) = max(AlignOfMember(S,0), max(AlignOfMember(S,1), ... , AlignOfMember(S,N)) = 8 here
-                                     // so roundUp(16,8) = 16  meaning that the struct is aligned to 16 bytes
-                                     // 80 bytes in total which is divisible by 16 so we are okay
+    pub viscosity: f32,              //offset 72
+    #[serde(
+        serialize_with = "param_enum_name::serialize::<EquationOfState, _>",
+        deserialize_with = "param_enum_name::deserialize::<EquationOfState, _>"
+    )]
+    pub equation_of_state: u32, //offset 76 (EquationOfState discriminant)
+    pub gamma: f32,                  //offset 80 (Tait exponent)
+    pub xsph: f32,                   //offset 84 (XSPH velocity smoothing, 0 disables the pass)
+    pub surface_tension: f32,        //offset 88 (Akinci cohesion + curvature, 0 disables)
+    #[serde(
+        serialize_with = "param_enum_name::serialize::<Solver, _>",
+        deserialize_with = "param_enum_name::deserialize::<Solver, _>"
+    )]
+    pub solver: u32, //offset 92 (Solver discriminant)
+    pub ddr_stiffness: f32,          //offset 96 (double density relaxation k)
+    pub ddr_near_stiffness: f32,     //offset 100 (double density relaxation k_near)
+    pub ddr_rest_density: f32,       //offset 104 (double density relaxation ρ₀, unitless)
+    pub max_density_error: f32, //offset 108 (iterative solvers stop below this mean relative error)
+    pub min_solver_iterations: u32, //offset 112
+    pub max_solver_iterations: u32, //offset 116
+    pub max_divergence_error: f32, //offset 120 (DFSPH divergence solve, relative compression per step)
+    pub pbf_relaxation: f32,       //offset 124 (PBF constraint force mixing ε)
+    pub artificial_pressure: f32,  //offset 128 (PBF tensile instability correction k)
+    pub vorticity_confinement: f32, //offset 132 (PBF vorticity confinement ε, 0 disables the pass)
+    #[serde(
+        serialize_with = "param_enum_name::serialize::<Boundary, _>",
+        deserialize_with = "param_enum_name::deserialize::<Boundary, _>"
+    )]
+    pub boundary: u32, //offset 136 (Boundary discriminant)
+    pub collider_restitution: f32, //offset 140 (share of the normal velocity kept when hitting a collider)
+    pub collider_friction: f32, //offset 144 (share of the tangential velocity lost when hitting a collider)
+    pub thermal_diffusivity: f32, //offset 148 (heat diffusion α, 0 keeps every particle's temperature to itself)
+    pub thermal_expansion: f32,   //offset 152 (Boussinesq β, 0 disables buoyancy)
+    pub ambient_temperature: f32, //offset 156 (T₀, what particles spawn at and feel no buoyancy at)
+    pub heat_transfer: f32, //offset 160 (rate at which heat sources pull nearby particles to their temperature)
+    pub cfl_number: f32, //offset 164 (λᵥ of the adaptive timestep, 0 keeps dt as the front-end sets it)
+    pub cfl_force_factor: f32, //offset 168 (λ_f of the adaptive timestep's acceleration limit)
+    pub min_dt: f32,     //offset 172
+    pub physics_rate: f32, //offset 176 (fixed steps per simulated second, the adaptive timestep's longest step)
+    pub max_substeps: u32, //offset 180 (steps a frame may take before the front-end falls behind real time)
+    #[serde(
+        serialize_with = "param_enum_name::serialize::<Integrator, _>",
+        deserialize_with = "param_enum_name::deserialize::<Integrator, _>"
+    )]
+    pub integrator: u32, //offset 184 (Integrator discriminant)
+    #[serde(
+        serialize_with = "param_enum_name::serialize::<Kernel, _>",
+        deserialize_with = "param_enum_name::deserialize::<Kernel, _>"
+    )]
+    pub kernel: u32, //offset 188 (Kernel discriminant)
+    pub cohesion_density: f32, //offset 192 (the ρ₀ in Akinci's cohesion mass ρ₀V, water's 1000 by default)
+    pub pcisph_relaxation: f32, //offset 196 (fraction of the lattice δ PCISPH corrects with)
+    pub dfsph_relaxation: f32, //offset 200 (damping of DFSPH's Jacobi sweeps)
+    #[serde(skip)]
+    pub _padding: f32, //offset 204
+                               // https://www.w3.org/TR/WGSL/#address-space-layout-constraints
+                               // because this is going to be a uniform buffer
+                               // i.e. roundUp(16, AlignOf(S))
+                               // AlignOf(S) = max(AlignOfMember(S,0), max(AlignOfMember(S,1), ... , AlignOfMember(S,N)) = 8 here
+                               // so roundUp(16,8) = 16  meaning that the struct is aligned to 16 bytes
+                               // 208 bytes in total which is divisible by 16 so we are okay
 }
 impl Default for SimulationParams {
     fn default() -> Self {
@@ -48,17 +116,657 @@ impl Default for SimulationParams {
             mouse_pos: [0.0, 0.0],
             mouse_strength: 0.0,
             mouse_influence_radius: 70.0,
-            _padding: [0.0; 2],
+            viscosity: 0.0,
+            equation_of_state: EquationOfState::Linear as u32,
+            gamma: 7.0,
+            xsph: 0.0,
+            surface_tension: 0.0,
+            solver: Solver::Sph as u32,
+            ddr_stiffness: 500.0,
+            ddr_near_stiffness: 2000.0,
+            ddr_rest_density: 2.0,
+            max_density_error: 0.01,
+            min_solver_iterations: 3,
+            max_solver_iterations: 50,
+            max_divergence_error: 0.001,
+            pbf_relaxation: 0.0001,
+            artificial_pressure: 0.1,
+            vorticity_confinement: 0.0,
+            boundary: Boundary::Particles as u32,
+            collider_restitution: 0.3,
+            collider_friction: 0.1,
+            thermal_diffusivity: 0.0,
+            thermal_expansion: 0.0,
+            ambient_temperature: 20.0,
+            heat_transfer: 5.0,
+            cfl_number: 0.0,
+            cfl_force_factor: 0.25,
+            min_dt: 0.0001,
+            physics_rate: 120.0,
+            max_substeps: 8,
+            integrator: Integrator::Trapezoidal as u32,
+            kernel: Kernel::Muller as u32,
+            cohesion_density: 1000.0,
+            pcisph_relaxation: 0.5,
+            dfsph_relaxation: 0.3,
+            _padding: 0.0,
         }
     }
 }
+
+/// An enum carried in the uniform buffer as its `u32` discriminant and in
+/// scene files and on the command line by name. The shaders match on the
+/// same numbers.
+pub trait ParamEnum: Copy + PartialEq + 'static {
+    /// Every variant, the first one is what unknown discriminants fall back to,
+    /// same as the `default` case of the matching WGSL switch.
+    const ALL: &'static [Self];
+    /// What the enum selects, for error messages.
+    const WHAT: &'static str;
+
+    fn to_u32(self) -> u32;
+
+    /// The name used in scene files and on the command line.
+    fn name(self) -> &'static str;
+
+    fn label(self) -> &'static str;
+
+    fn from_u32(value: u32) -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|variant| variant.to_u32() == value)
+            .unwrap_or(Self::ALL[0])
+    }
+
+    fn from_name(name: &str) -> Result<Self, String> {
+        let name = name.trim().replace('-', "_");
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|variant| variant.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|variant| variant.name()).collect();
+                format!(
+                    "unknown {} '{}', expected one of {}",
+                    Self::WHAT,
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How pressure follows density.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EquationOfState {
+    /// k(ρ - ρ₀), the ideal gas law. Below rest density it pulls particles together.
+    Linear = 0,
+    /// max(k(ρ - ρ₀), 0), only ever pushes, so no clumping from negative pressure.
+    ClampedLinear = 1,
+    /// B((ρ/ρ₀)^γ - 1) with B = kρ₀/γ, which has the same slope as the
+    /// linear law at rest density but stiffens quickly under compression.
+    Tait = 2,
+}
+
+impl ParamEnum for EquationOfState {
+    const ALL: &'static [Self] = &[Self::Linear, Self::ClampedLinear, Self::Tait];
+    const WHAT: &'static str = "equation of state";
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::ClampedLinear => "clamped_linear",
+            Self::Tait => "tait",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Linear => "Linear",
+            Self::ClampedLinear => "Clamped linear",
+            Self::Tait => "Tait",
+        }
+    }
+}
+
+/// How a step turns densities into motion.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Solver {
+    /// Pressure, viscosity and surface tension forces, then integration.
+    Sph = 0,
+    /// Clavet et al. 2005 double density relaxation: positions are advanced,
+    /// then pushed apart directly by a pressure and a near-pressure that
+    /// keeps particles from clumping. Only gravity, the mouse and the
+    /// boundaries act on top of it.
+    DoubleDensity = 1,
+    /// Solenthaler and Pajarola 2009 predictive-corrective incompressible
+    /// SPH: the pressure is iterated on predicted positions until the mean
+    /// density error drops below `max_density_error`, instead of coming from
+    /// the equation of state.
+    Pcisph = 2,
+    /// Bender and Koschier 2015 divergence-free SPH: a divergence solve keeps
+    /// the velocity field from compressing the fluid, then a density solve
+    /// corrects the velocities until the predicted density error drops below
+    /// `max_density_error`. Both use per-particle α factors so each iteration
+    /// is a single Jacobi sweep.
+    Dfsph = 3,
+    /// Macklin and Müller 2013 position based fluids: positions are
+    /// predicted, then moved by Jacobi iterations of per-particle density
+    /// constraints until the mean compression drops below `max_density_error`,
+    /// and the velocity is whatever moved the particle. Stable at any `dt`.
+    PositionBased = 4,
+}
+
+impl Solver {
+    /// Whether the pressure comes from an iterative solve instead of the
+    /// equation of state, leaving it out of the force pass.
+    pub fn iterates_pressure(self) -> bool {
+        matches!(self, Self::Pcisph | Self::Dfsph)
+    }
+
+    /// Whether the step runs an iteration loop bounded by
+    /// `min_solver_iterations`, `max_solver_iterations` and `max_density_error`.
+    pub fn is_iterative(self) -> bool {
+        self.iterates_pressure() || self == Self::PositionBased
+    }
+
+    /// Whether the step runs the force pass, which is also where heat is
+    /// diffused and buoyancy applied. The position-based solvers skip it.
+    pub fn has_force_pass(self) -> bool {
+        !matches!(self, Self::DoubleDensity | Self::PositionBased)
+    }
+}
+
+impl ParamEnum for Solver {
+    const ALL: &'static [Self] = &[
+        Self::Sph,
+        Self::DoubleDensity,
+        Self::Pcisph,
+        Self::Dfsph,
+        Self::PositionBased,
+    ];
+    const WHAT: &'static str = "solver";
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sph => "sph",
+            Self::DoubleDensity => "double_density",
+            Self::Pcisph => "pcisph",
+            Self::Dfsph => "dfsph",
+            Self::PositionBased => "pbf",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Sph => "SPH forces",
+            Self::DoubleDensity => "Double density relaxation",
+            Self::Pcisph => "PCISPH",
+            Self::Dfsph => "DFSPH",
+            Self::PositionBased => "Position based fluids",
+        }
+    }
+}
+
+/// What keeps the fluid inside the domain.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    /// Particles past a wall are put back on it and their velocity is
+    /// flipped and damped.
+    Clamp = 0,
+    /// Akinci et al. 2012: static particles along the walls add to the density
+    /// of the fluid next to them and push it back with its own pressure, so a
+    /// wall holds the fluid the way the fluid holds itself. The clamp stays as
+    /// a fallback for anything that still gets through.
+    Particles = 1,
+}
+
+impl ParamEnum for Boundary {
+    const ALL: &'static [Self] = &[Self::Clamp, Self::Particles];
+    const WHAT: &'static str = "boundary";
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Clamp => "clamp",
+            Self::Particles => "particles",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Clamp => "Clamp at walls",
+            Self::Particles => "Boundary particles",
+        }
+    }
+}
+
+/// How the SPH solver steps positions and velocities forward. PCISPH and
+/// DFSPH always use symplectic Euler, which their pressure solves assume,
+/// and the position based solvers move the particles themselves.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Integrator {
+    /// The velocity takes the whole acceleration and the position the mean
+    /// of the old and new velocity. The forces are evaluated where the
+    /// particles would be after another step at their current velocity.
+    Trapezoidal = 0,
+    /// v += a dt, then x += v dt with the new velocity, forces evaluated at
+    /// the current positions. First order, but symplectic.
+    SymplecticEuler = 1,
+    /// Kick-drift-kick: half a kick with the last step's acceleration, a
+    /// drift with that half-step velocity, half a kick with the acceleration
+    /// there. The walls, the mouse and the velocity cap act on the half-step
+    /// velocity. Second order and symplectic.
+    Leapfrog = 2,
+    /// x += v dt + ½a dt², then v += ½(a + a') dt with the acceleration a'
+    /// at the new positions. The same scheme as leapfrog written the other
+    /// way around, so it only differs in where the walls, the mouse and the
+    /// velocity cap come in: on the full-step velocity.
+    VelocityVerlet = 3,
+    /// Heun's method: an Euler step predicts the positions and velocities,
+    /// the forces are evaluated a second time there and the step is redone
+    /// with the mean of both accelerations. Second order but not symplectic,
+    /// and twice the cost per step.
+    Rk2 = 4,
+}
+
+impl Integrator {
+    /// Whether the step evaluates the forces twice, once for the predictor.
+    pub fn is_two_stage(self) -> bool {
+        self == Self::Rk2
+    }
+}
+
+impl ParamEnum for Integrator {
+    const ALL: &'static [Self] = &[
+        Self::Trapezoidal,
+        Self::SymplecticEuler,
+        Self::Leapfrog,
+        Self::VelocityVerlet,
+        Self::Rk2,
+    ];
+    const WHAT: &'static str = "integrator";
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Trapezoidal => "trapezoidal",
+            Self::SymplecticEuler => "symplectic_euler",
+            Self::Leapfrog => "leapfrog",
+            Self::VelocityVerlet => "velocity_verlet",
+            Self::Rk2 => "rk2",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Trapezoidal => "Trapezoidal",
+            Self::SymplecticEuler => "Symplectic Euler",
+            Self::Leapfrog => "Leapfrog (KDK)",
+            Self::VelocityVerlet => "Velocity Verlet",
+            Self::Rk2 => "RK2 (Heun)",
+        }
+    }
+}
+
+/// The smoothing kernel the density, the pressure and every other pass
+/// that weighs neighbours by distance use. The viscosity laplacian, the
+/// cohesion kernel and double density relaxation's own kernels are left as
+/// they are. All of them have a support of one influence radius and are
+/// normalised for 2D, see `cpu/kernels.rs`.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kernel {
+    /// Müller et al. 2003: poly6 for the density and spiky for the
+    /// pressure, whose gradient does not vanish as particles close in.
+    Muller = 0,
+    /// Monaghan's M4 B-spline, the classic SPH kernel.
+    CubicSpline = 1,
+    /// Wendland C2, smooth and immune to the pairing instability.
+    WendlandC2 = 2,
+    /// Wendland C4, smoother still and wider in effect for the same radius.
+    WendlandC4 = 3,
+    /// Morris's M6 quintic spline, closer to a Gaussian than the cubic.
+    Quintic = 4,
+}
+
+impl ParamEnum for Kernel {
+    const ALL: &'static [Self] = &[
+        Self::Muller,
+        Self::CubicSpline,
+        Self::WendlandC2,
+        Self::WendlandC4,
+        Self::Quintic,
+    ];
+    const WHAT: &'static str = "kernel";
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Muller => "muller",
+            Self::CubicSpline => "cubic_spline",
+            Self::WendlandC2 => "wendland_c2",
+            Self::WendlandC4 => "wendland_c4",
+            Self::Quintic => "quintic",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Muller => "Poly6 / spiky (Müller)",
+            Self::CubicSpline => "Cubic spline",
+            Self::WendlandC2 => "Wendland C2",
+            Self::WendlandC4 => "Wendland C4",
+            Self::Quintic => "Quintic spline",
+        }
+    }
+}
+
+// scene files store ParamEnum fields by name rather than as a number
+mod param_enum_name {
+    use super::ParamEnum;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<E: ParamEnum, S: Serializer>(
+        value: &u32,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(E::from_u32(*value).name())
+    }
+
+    pub fn deserialize<'de, E: ParamEnum, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<u32, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        E::from_name(&name)
+            .map(|variant| variant.to_u32())
+            .map_err(D::Error::custom)
+    }
+}
+
+fn enum_combo<E: ParamEnum>(ui: &mut egui::Ui, id: &str, value: &mut u32) -> E {
+    let mut selected = E::from_u32(*value);
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(selected.label())
+        .show_ui(ui, |ui| {
+            for &option in E::ALL {
+                ui.selectable_value(&mut selected, option, option.label());
+            }
+        });
+    *value = selected.to_u32();
+    selected
+}
+
 impl SimulationParams {
-    pub fn ui(&mut self, ui: &mut egui::Ui) {
+    /// Every user-facing field, as scene files and `set_field` name them.
+    /// Padding is left out.
+    pub fn field_names() -> Vec<String> {
+        Self::default().to_table().keys().cloned().collect()
+    }
+
+    /// Whether the walls act through boundary particles this step. Only the
+    /// SPH force solver reads them, the others keep clamping at the walls.
+    pub fn boundary_particles(&self) -> bool {
+        Boundary::from_u32(self.boundary) == Boundary::Particles
+            && Solver::from_u32(self.solver) == Solver::Sph
+    }
+
+    /// The integrator `integrate` steps with: the selected one for the SPH
+    /// solver, symplectic Euler for the rest, which PCISPH's and DFSPH's
+    /// pressure solves assume and the position based solvers never reach.
+    pub fn active_integrator(&self) -> Integrator {
+        if Solver::from_u32(self.solver) == Solver::Sph {
+            Integrator::from_u32(self.integrator)
+        } else {
+            Integrator::SymplecticEuler
+        }
+    }
+
+    /// The length of one step at `physics_rate`, which the front-ends step
+    /// with whatever the display's frame rate.
+    pub fn fixed_dt(&self) -> f32 {
+        1.0 / self.physics_rate.max(1.0)
+    }
+
+    /// The longest `dt` the CFL condition allows after a step with `limits`:
+    /// no particle may cross more than `cfl_number` influence radii,
+    /// λᵥh/|v|ₘₐₓ, and the largest acceleration may not move one more than
+    /// `cfl_force_factor` of one, λ_f√(h/|a|ₘₐₓ). Clamped between `min_dt`
+    /// and the fixed step.
+    pub fn cfl_dt(&self, limits: StepLimits) -> f32 {
+        let h = self.influence_radius;
+        let max_dt = self.fixed_dt();
+        let mut dt = max_dt;
+        if limits.max_speed > 0.0 {
+            dt = dt.min(self.cfl_number * h / limits.max_speed);
+        }
+        if limits.max_acceleration > 0.0 {
+            dt = dt.min(self.cfl_force_factor * (h / limits.max_acceleration).sqrt());
+        }
+        dt.max(self.min_dt.min(max_dt))
+    }
+
+    /// Sets a field from its name and a textual value, e.g. `("gas_constant", "1e5")`.
+    /// Vector fields take `x,y` and enum fields their name, as in scene files.
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let mut table = self.to_table();
+        if !table.contains_key(name) {
+            return Err(format!("unknown parameter '{}'", name));
+        }
+        // the value is read as the TOML a scene file would have after
+        // `name =`, bare words standing for strings and x,y for an array
+        let value = value.trim();
+        let fragment = if value.contains(',') && !value.starts_with('[') {
+            format!("value = [{}]", value)
+        } else {
+            format!("value = {}", value)
+        };
+        let value = match toml::from_str::<toml::Table>(&fragment) {
+            Ok(mut fragment) => fragment.remove("value").expect("the fragment sets value"),
+            Err(_) => toml::Value::String(value.to_string()),
+        };
+        table.insert(name.to_string(), value);
+        let params: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| format!("invalid value for {}: {}", name, e.message()))?;
+        params.validate()?;
+        *self = params;
+        Ok(())
+    }
+
+    /// Checks what the backends cannot run with: the GPU sizes its particle
+    /// and sort buffers from `no_particles`, and neither can be empty.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.no_particles == 0 {
+            return Err("no_particles must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// The textual form of a field, in the format `set_field` accepts.
+    pub fn get_field(&self, name: &str) -> Option<String> {
+        // every float field is an f32, shown without the digits its f64
+        // form in the table gains
+        fn text(value: &toml::Value) -> String {
+            match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Float(value) => (*value as f32).to_string(),
+                toml::Value::Array(values) => values.iter().map(text).collect::<Vec<_>>().join(","),
+                value => value.to_string(),
+            }
+        }
+        self.to_table().get(name).map(text)
+    }
+
+    // the fields as scene files hold them
+    fn to_table(&self) -> toml::Table {
+        match toml::Value::try_from(self).expect("SimulationParams always serializes") {
+            toml::Value::Table(table) => table,
+            _ => unreachable!("a struct serializes to a table"),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("SimulationParams always serializes")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_toml())
+    }
+
+    /// The parameters panel. Returns what the Save or Load button under it
+    /// asked for, which the front-end carries out, as a scene holds more than
+    /// the parameters.
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<SceneAction> {
         egui::Grid::new("sim_params_grid")
             .num_columns(2)
             .spacing([40.0, 4.0])
             .striped(true)
             .show(ui, |ui| {
+                ui.label("Solver");
+                let solver: Solver = enum_combo(ui, "solver", &mut self.solver);
+                ui.end_row();
+
+                if solver == Solver::DoubleDensity {
+                    ui.label("Relaxation Stiffness");
+                    ui.add(
+                        egui::DragValue::new(&mut self.ddr_stiffness)
+                            .speed(10.0)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+
+                    ui.label("Near Stiffness");
+                    ui.add(
+                        egui::DragValue::new(&mut self.ddr_near_stiffness)
+                            .speed(10.0)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+
+                    ui.label("Relaxation Rest Density");
+                    ui.add(
+                        egui::DragValue::new(&mut self.ddr_rest_density)
+                            .speed(0.01)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+                }
+
+                if solver.is_iterative() {
+                    ui.label("Max Density Error");
+                    ui.add(
+                        egui::DragValue::new(&mut self.max_density_error)
+                            .speed(0.001)
+                            .max_decimals(4)
+                            .range(0.0..=1.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("Solver Iterations");
+                    ui.horizontal(|ui| {
+                        ui.label("Min");
+                        ui.add(
+                            egui::DragValue::new(&mut self.min_solver_iterations)
+                                .range(1..=self.max_solver_iterations),
+                        );
+                        ui.label("Max");
+                        ui.add(
+                            egui::DragValue::new(&mut self.max_solver_iterations)
+                                .range(self.min_solver_iterations..=1000),
+                        );
+                    });
+                    ui.end_row();
+                }
+
+                if solver == Solver::Pcisph {
+                    ui.label("PCISPH Relaxation");
+                    ui.add(
+                        egui::DragValue::new(&mut self.pcisph_relaxation)
+                            .speed(0.01)
+                            .range(0.0..=1.0),
+                    );
+                    ui.end_row();
+                }
+
+                if solver == Solver::Dfsph {
+                    ui.label("Max Divergence Error");
+                    ui.add(
+                        egui::DragValue::new(&mut self.max_divergence_error)
+                            .speed(0.0001)
+                            .max_decimals(5)
+                            .range(0.0..=1.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("DFSPH Relaxation");
+                    ui.add(
+                        egui::DragValue::new(&mut self.dfsph_relaxation)
+                            .speed(0.01)
+                            .range(0.0..=1.0),
+                    );
+                    ui.end_row();
+                }
+
+                if solver == Solver::PositionBased {
+                    ui.label("Constraint Relaxation");
+                    ui.add(
+                        egui::DragValue::new(&mut self.pbf_relaxation)
+                            .speed(0.00001)
+                            .max_decimals(6)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+
+                    ui.label("Artificial Pressure");
+                    ui.add(
+                        egui::DragValue::new(&mut self.artificial_pressure)
+                            .speed(0.001)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+
+                    ui.label("Vorticity Confinement");
+                    ui.add(
+                        egui::DragValue::new(&mut self.vorticity_confinement)
+                            .speed(0.01)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+                }
+
                 ui.label("Max Velocity");
                 ui.add(egui::DragValue::new(&mut self.max_vel).speed(10.0));
                 ui.end_row();
@@ -96,14 +804,80 @@ impl SimulationParams {
                 ui.add(egui::DragValue::new(&mut self.gas_constant).speed(1000.0));
                 ui.end_row();
 
+                ui.label("Equation of State");
+                let eos: EquationOfState =
+                    enum_combo(ui, "equation_of_state", &mut self.equation_of_state);
+                ui.end_row();
+
+                if eos == EquationOfState::Tait {
+                    ui.label("Gamma");
+                    ui.add(
+                        egui::DragValue::new(&mut self.gamma)
+                            .speed(0.1)
+                            .range(1.0..=f32::MAX),
+                    );
+                    ui.end_row();
+                }
+
                 ui.label("Influence Radius");
                 ui.add(egui::DragValue::new(&mut self.influence_radius).speed(1.0));
                 ui.end_row();
 
+                if solver != Solver::DoubleDensity {
+                    ui.label("Kernel");
+                    enum_combo::<Kernel>(ui, "kernel", &mut self.kernel);
+                    ui.end_row();
+                }
+
                 ui.label("Cell Size");
                 ui.add(egui::DragValue::new(&mut self.cell_size).speed(1.0));
                 ui.end_row();
 
+                ui.label("Viscosity");
+                ui.add(
+                    egui::DragValue::new(&mut self.viscosity)
+                        .speed(0.01)
+                        .range(0.0..=f32::MAX),
+                );
+                ui.end_row();
+
+                ui.label("XSPH");
+                ui.add(
+                    egui::DragValue::new(&mut self.xsph)
+                        .speed(0.01)
+                        .range(0.0..=1.0),
+                );
+                ui.end_row();
+
+                ui.label("Surface Tension");
+                ui.add(
+                    egui::DragValue::new(&mut self.surface_tension)
+                        .speed(0.001)
+                        .max_decimals(4)
+                        .range(0.0..=f32::MAX),
+                );
+                ui.end_row();
+
+                if self.surface_tension > 0.0 {
+                    ui.label("Cohesion Density");
+                    ui.add(
+                        egui::DragValue::new(&mut self.cohesion_density)
+                            .speed(10.0)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+                }
+
+                if solver == Solver::Sph {
+                    ui.label("Boundary");
+                    enum_combo::<Boundary>(ui, "boundary", &mut self.boundary);
+                    ui.end_row();
+
+                    ui.label("Integrator");
+                    enum_combo::<Integrator>(ui, "integrator", &mut self.integrator);
+                    ui.end_row();
+                }
+
                 ui.label("Damping");
                 ui.add(
                     egui::DragValue::new(&mut self.damping)
@@ -112,9 +886,214 @@ impl SimulationParams {
                 );
                 ui.end_row();
 
+                ui.label("Collider Restitution");
+                ui.add(
+                    egui::DragValue::new(&mut self.collider_restitution)
+                        .speed(0.01)
+                        .range(0.0..=1.0),
+                );
+                ui.end_row();
+
+                ui.label("Collider Friction");
+                ui.add(
+                    egui::DragValue::new(&mut self.collider_friction)
+                        .speed(0.01)
+                        .range(0.0..=1.0),
+                );
+                ui.end_row();
+
+                if solver.has_force_pass() {
+                    ui.label("Thermal Diffusivity");
+                    ui.add(
+                        egui::DragValue::new(&mut self.thermal_diffusivity)
+                            .speed(0.01)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+
+                    ui.label("Thermal Expansion");
+                    ui.add(
+                        egui::DragValue::new(&mut self.thermal_expansion)
+                            .speed(0.0001)
+                            .max_decimals(5)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+
+                    ui.label("Ambient Temperature");
+                    ui.add(egui::DragValue::new(&mut self.ambient_temperature).speed(0.5));
+                    ui.end_row();
+
+                    ui.label("Heat Transfer");
+                    ui.add(
+                        egui::DragValue::new(&mut self.heat_transfer)
+                            .speed(0.1)
+                            .range(0.0..=f32::MAX),
+                    );
+                    ui.end_row();
+                }
+
                 ui.label("Mouse Radius");
                 ui.add(egui::DragValue::new(&mut self.mouse_influence_radius).speed(1.0));
                 ui.end_row();
+
+                ui.label("Physics Rate");
+                ui.add(
+                    egui::DragValue::new(&mut self.physics_rate)
+                        .speed(1.0)
+                        .suffix(" Hz")
+                        .range(1.0..=2000.0),
+                );
+                ui.end_row();
+
+                ui.label("Max Steps per Frame");
+                ui.add(egui::DragValue::new(&mut self.max_substeps).range(1..=64));
+                ui.end_row();
+
+                ui.label("CFL Number");
+                ui.add(
+                    egui::DragValue::new(&mut self.cfl_number)
+                        .speed(0.01)
+                        .range(0.0..=1.0),
+                );
+                ui.end_row();
+
+                if self.cfl_number > 0.0 {
+                    ui.label("CFL Force Factor");
+                    ui.add(
+                        egui::DragValue::new(&mut self.cfl_force_factor)
+                            .speed(0.01)
+                            .range(0.0..=1.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("Min dt");
+                    ui.add(
+                        egui::DragValue::new(&mut self.min_dt)
+                            .speed(0.00001)
+                            .max_decimals(5)
+                            .range(0.00001..=self.fixed_dt()),
+                    );
+                    ui.end_row();
+                }
             });
+
+        ui.separator();
+        scene_ui(ui)
+    }
+
+    /// The parameters of a scene being loaded into a running front-end. The
+    /// domain follows the window and dt and the mouse are driven by the frame
+    /// loop, so those keep their live values.
+    pub fn loaded_from(&self, scene: SimulationParams) -> Self {
+        Self {
+            width: self.width,
+            height: self.height,
+            dt: self.dt,
+            mouse_pos: self.mouse_pos,
+            mouse_strength: self.mouse_strength,
+            ..scene
+        }
+    }
+
+    /// Shows `status` under the Save and Load buttons until the next one.
+    pub fn set_scene_status(ctx: &egui::Context, status: String) {
+        ctx.data_mut(|d| d.insert_temp(scene_status_id(), status));
+    }
+}
+
+/// A scene file the user asked the parameters panel to write or read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneAction {
+    Save(String),
+    Load(String),
+}
+
+fn scene_status_id() -> egui::Id {
+    egui::Id::new("scene_status")
+}
+
+fn scene_ui(ui: &mut egui::Ui) -> Option<SceneAction> {
+    // SimulationParams is a Pod uniform, so the path and the last result
+    // live in egui's temp storage instead of on the struct
+    let path_id = ui.make_persistent_id("scene_path");
+    let mut path = ui.data_mut(|d| {
+        d.get_temp_mut_or_insert_with(path_id, || DEFAULT_SCENE_PATH.to_string())
+            .clone()
+    });
+    let mut action = None;
+
+    ui.horizontal(|ui| {
+        ui.label("Scene");
+        ui.text_edit_singleline(&mut path);
+        if ui.button("Save").clicked() {
+            action = Some(SceneAction::Save(path.clone()));
+        }
+        if ui.button("Load").clicked() {
+            action = Some(SceneAction::Load(path.clone()));
+        }
+    });
+
+    ui.data_mut(|d| d.insert_temp(path_id, path));
+    if let Some(status) = ui.data(|d| d.get_temp::<String>(scene_status_id())) {
+        ui.label(status);
+    }
+    action
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_fields_from_their_text() {
+        let mut params = SimulationParams::default();
+        params.set_field("gas_constant", "1e5").unwrap();
+        params.set_field("no_particles", "2048").unwrap();
+        params.set_field("gravity", "1.5, -300").unwrap();
+        params.set_field("solver", "pcisph").unwrap();
+        params.set_field("boundary", "\"clamp\"").unwrap();
+        assert_eq!(params.gas_constant, 1e5);
+        assert_eq!(params.no_particles, 2048);
+        assert_eq!(params.gravity, [1.5, -300.0]);
+        assert_eq!(Solver::from_u32(params.solver), Solver::Pcisph);
+        assert_eq!(Boundary::from_u32(params.boundary), Boundary::Clamp);
+    }
+
+    #[test]
+    fn get_field_reads_back_through_set_field() {
+        let params = SimulationParams {
+            gas_constant: 0.1,
+            gravity: [0.3, -9.81],
+            equation_of_state: EquationOfState::Tait.to_u32(),
+            ..Default::default()
+        };
+        assert_eq!(params.get_field("gas_constant").as_deref(), Some("0.1"));
+        assert_eq!(
+            params.get_field("equation_of_state").as_deref(),
+            Some("tait")
+        );
+        let mut copy = SimulationParams::default();
+        for name in SimulationParams::field_names() {
+            let value = params.get_field(&name).unwrap();
+            copy.set_field(&name, &value)
+                .unwrap_or_else(|e| panic!("{} = {}: {}", name, value, e));
+        }
+        assert_eq!(bytemuck::bytes_of(&copy), bytemuck::bytes_of(&params));
+    }
+
+    #[test]
+    fn rejects_bad_fields_and_keeps_the_old_values() {
+        let mut params = SimulationParams::default();
+        assert!(params.set_field("no_such_field", "1").is_err());
+        assert!(params.get_field("no_such_field").is_none());
+        assert!(params.set_field("_padding", "1").is_err());
+        assert!(params.set_field("gas_constant", "fast").is_err());
+        assert!(params.set_field("solver", "magic").is_err());
+        assert!(params.set_field("no_particles", "0").is_err());
+        assert_eq!(
+            bytemuck::bytes_of(&params),
+            bytemuck::bytes_of(&SimulationParams::default())
+        );
     }
 }
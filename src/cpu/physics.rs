@@ -1,16 +1,27 @@
-use super::kernels::{poly_kernel, spiky_kernel_gradient};
-use crate::Vec2;
-use crate::constants::SimulationParams;
-
-pub fn calculate_pressure(density: f32, params: &SimulationParams) -> f32 {
-    let pressure = params.gas_constant * (density - params.rest_density);
-    pressure
-    // pressure.max(0.0)
-    // let gamma = 7.0;
-    // let B = params.gas_constant * params.rest_density / gamma;
-    //
-    // // Tait equation: Non-linear response (stiff)
-    // B * ((density / params.rest_density).powf(gamma) - 1.0)
+use super::kernels::{
+    cohesion_kernel, pressure_kernel_gradient, smoothing_kernel, smoothing_kernel_gradient,
+    viscosity_kernel_laplacian,
+};
+use crate::constants::{EquationOfState, ParamEnum, SimulationParams};
+use crate::gpu::particle::GpuMaterial;
+use glam::Vec2;
+
+// Macklin and Müller's artificial pressure: Δq as a fraction of the
+// influence radius and the exponent n
+const ARTIFICIAL_PRESSURE_RADIUS: f32 = 0.2;
+const ARTIFICIAL_PRESSURE_EXPONENT: i32 = 4;
+
+pub fn calculate_pressure(density: f32, rest_density: f32, params: &SimulationParams) -> f32 {
+    // rest_density is the particle's own phase's
+    match EquationOfState::from_u32(params.equation_of_state) {
+        EquationOfState::Linear => params.gas_constant * (density - rest_density),
+        EquationOfState::ClampedLinear => (params.gas_constant * (density - rest_density)).max(0.0),
+        EquationOfState::Tait => {
+            // Tait equation: Non-linear response (stiff)
+            let b = params.gas_constant * rest_density / params.gamma;
+            b * ((density / rest_density).powf(params.gamma) - 1.0)
+        }
+    }
 }
 
 pub fn calculate_pressure_force(
@@ -20,17 +31,453 @@ pub fn calculate_pressure_force(
     pressure_other: f32,
     // density: f32,
     density_other: f32,
+    mass_other: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    let grad_pressure = pressure_kernel_gradient(pos, pos_other, params);
+
+    mass_other * ((pressure + pressure_other) / (2.0 * density_other)) * grad_pressure
+    // MASS * ((pressure / density.powi(2)) + (pressure_other / density_other.powi(2))) * grad_pressure
+}
+
+pub fn calculate_boundary_pressure_force(
+    pos: Vec2,
+    boundary_pos: Vec2,
+    boundary_volume: f32,
+    pressure: f32,
+    density: f32,
+    rest_density: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // Akinci et al. 2012: the wall particle mirrors the fluid particle's own
+    // pressure and density and has mass ρ₀V, which turns the symmetric term
+    // above into ρ₀V p/ρ ∇W. Negative pressure would glue the fluid to the
+    // wall, so walls only ever push.
+    let grad_pressure = pressure_kernel_gradient(pos, boundary_pos, params);
+
+    rest_density * boundary_volume * (pressure.max(0.0) / density) * grad_pressure
+}
+
+pub fn calculate_boundary_reaction_force(
+    pos: Vec2,
+    boundary_pos: Vec2,
+    boundary_volume: f32,
+    pressure: f32,
+    density: f32,
+    material: &GpuMaterial,
+    params: &SimulationParams,
+) -> Vec2 {
+    // what the fluid particle at pos pushes a body particle with: the force
+    // above is per volume, m/ρ turns it into the particle's own, and the
+    // body feels it with the opposite sign
+    material.mass / density
+        * calculate_boundary_pressure_force(
+            pos,
+            boundary_pos,
+            boundary_volume,
+            pressure,
+            density,
+            material.rest_density,
+            params,
+        )
+}
+
+pub fn calculate_viscosity_force(
+    pos: Vec2,
+    pos_other: Vec2,
+    vel: Vec2,
+    vel_other: Vec2,
+    volume_other: f32,
+    viscosity: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // pulls the velocity towards the neighbours' velocities, μ * Vⱼ * (vⱼ - vᵢ) * ∇²W
+    // with Vⱼ = mⱼ/ρⱼ and μ the mean of both phases' viscosities
+    let laplacian = viscosity_kernel_laplacian(pos, pos_other, params);
+
+    viscosity * volume_other * (vel_other - vel) * laplacian
+}
+
+pub fn calculate_heat_diffusion(
+    pos: Vec2,
+    pos_other: Vec2,
+    temperature: f32,
+    temperature_other: f32,
+    volume_other: f32,
+    params: &SimulationParams,
+) -> f32 {
+    // one neighbour's share of the heat equation's dT/dt = α ∇²T, discretised
+    // like viscosity: α * Vⱼ * (Tⱼ - Tᵢ) * ∇²W. Symmetric, so heat is only
+    // moved between particles, never made or lost.
+    let laplacian = viscosity_kernel_laplacian(pos, pos_other, params);
+
+    params.thermal_diffusivity * volume_other * (temperature_other - temperature) * laplacian
+}
+
+pub fn calculate_xsph_correction(
+    pos: Vec2,
+    pos_other: Vec2,
+    vel: Vec2,
+    vel_other: Vec2,
+    mean_density: f32,
+    mass_other: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // mⱼ * (vⱼ - vᵢ) / ρ̄ᵢⱼ * W, scaled by the XSPH coefficient by the caller
+    mass_other * (vel_other - vel) / mean_density * smoothing_kernel(pos, pos_other, params)
+}
+
+pub fn calculate_surface_normal(
+    pos: Vec2,
+    pos_other: Vec2,
+    density_other: f32,
+    mass_other: f32,
     params: &SimulationParams,
 ) -> Vec2 {
-    let grad_spiky = spiky_kernel_gradient(pos, pos_other, &params);
+    // one term of the colour-field normal nᵢ = h Σ mⱼ/ρⱼ ∇W, it points into
+    // the fluid at the surface and is zero deep inside it
+    params.influence_radius * mass_other / density_other
+        * smoothing_kernel_gradient(pos, pos_other, params)
+}
+
+pub fn calculate_surface_tension_force(
+    offset: Vec2,
+    normal_difference: Vec2,
+    density: f32,
+    density_other: f32,
+    material: &GpuMaterial,
+    material_other: &GpuMaterial,
+    params: &SimulationParams,
+) -> Vec2 {
+    // Akinci et al. 2013: Kᵢⱼ * (cohesion + curvature), with
+    // cohesion = -γ mᵢ mⱼ C(r) r̂ and curvature = -γ mᵢ (nᵢ - nⱼ).
+    // That is a per-particle force, so it is scaled by ρᵢ/mᵢ into the force
+    // density the rest of the force pass accumulates.
+    //
+    // The curvature term pushes surface particles back out and only works
+    // while cohesion dominates it, which Akinci gets from mⱼ = ρ₀Vⱼ with
+    // water's ρ₀ = 1000. Our densities are in pixel units, so mⱼ is replaced
+    // by cohesion_density * mⱼ/ρ₀ⱼ to keep that balance, 1000 by default.
+    // `offset` is pᵢ - pⱼ and `normal_difference` nᵢ - nⱼ.
+    let r = offset.length();
+    // symmetric correction that boosts the particles with too few neighbours
+    let correction = 2.0 * material.rest_density / (density + density_other);
+    let cohesion_mass = params.cohesion_density * material_other.mass / material_other.rest_density;
+    let cohesion = if r > 0.00001 {
+        -cohesion_mass * cohesion_kernel(r, params) * offset / r
+    } else {
+        Vec2::ZERO
+    };
+    let curvature = -normal_difference;
 
-    params.mass * ((pressure + pressure_other) / (2.0 * density_other)) * grad_spiky
-    // MASS * ((pressure / density.powi(2)) + (pressure_other / density_other.powi(2))) * grad_spiky
+    params.surface_tension * density * correction * (cohesion + curvature)
 }
 
 pub fn calculate_gravity_force(density: f32, params: &SimulationParams) -> Vec2 {
-    density * params.gravity
+    density * Vec2::from(params.gravity)
+}
+
+pub fn calculate_buoyancy_force(density: f32, temperature: f32, params: &SimulationParams) -> Vec2 {
+    // Boussinesq: the density only changes with temperature in the gravity
+    // term, ρ(1 - β(T - T₀))g, so this is the -ρβ(T - T₀)g on top of
+    // calculate_gravity_force. Warmer than ambient rises, colder sinks.
+    -density
+        * params.thermal_expansion
+        * (temperature - params.ambient_temperature)
+        * Vec2::from(params.gravity)
+}
+pub fn calculate_density(
+    pos: Vec2,
+    pos_other: Vec2,
+    mass_other: f32,
+    params: &SimulationParams,
+) -> f32 {
+    mass_other * smoothing_kernel(pos, pos_other, params)
+}
+
+pub fn calculate_boundary_density(
+    pos: Vec2,
+    boundary_pos: Vec2,
+    boundary_volume: f32,
+    rest_density: f32,
+    params: &SimulationParams,
+) -> f32 {
+    rest_density * boundary_volume * smoothing_kernel(pos, boundary_pos, params)
+}
+
+pub fn calculate_relaxation_density(
+    pos: Vec2,
+    pos_other: Vec2,
+    params: &SimulationParams,
+) -> (f32, f32) {
+    // one neighbour's share of Clavet's density (1 - q)² and near-density
+    // (1 - q)³, q = r/h. Both are unitless and exclude the particle itself.
+    let q = (pos.distance(pos_other) / params.influence_radius).min(1.0);
+    ((1.0 - q).powi(2), (1.0 - q).powi(3))
+}
+
+pub fn calculate_relaxation_pressure(density: f32, params: &SimulationParams) -> f32 {
+    params.ddr_stiffness * (density - params.ddr_rest_density)
+}
+
+pub fn calculate_relaxation_displacement(
+    pos: Vec2,
+    pos_other: Vec2,
+    pressure: f32,
+    pressure_other: f32,
+    near_pressure: f32,
+    near_pressure_other: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // Clavet et al. 2005 push each pair apart along r̂ᵢⱼ by
+    // dt² (P(1 - q) + Pⁿᵉᵃʳ(1 - q)²), half to each side. Using the pair's mean
+    // pressures makes that symmetric so it can run as a Jacobi pass in any
+    // order. Returned without the dt², integrate_relaxed applies it.
+    let delta = pos_other - pos;
+    let r = delta.length();
+    if r < 0.00001 || r >= params.influence_radius {
+        return Vec2::ZERO;
+    }
+    let q = r / params.influence_radius;
+    let magnitude = (pressure + pressure_other) * (1.0 - q)
+        + (near_pressure + near_pressure_other) * (1.0 - q).powi(2);
+    0.5 * magnitude * delta / r
+}
+
+pub fn pcisph_delta(material: &GpuMaterial, params: &SimulationParams) -> f32 {
+    // Solenthaler and Pajarola's δ: the pressure per unit of density error for
+    // a particle with a full neighbourhood, from a square lattice at rest
+    // spacing. 1/(β (Σ∇W·Σ∇Wₚ + Σ∇W·∇Wₚ)) with β = 2dt²m²/ρ₀², where ∇W is the
+    // density kernel's gradient and ∇Wₚ the pressure kernel's. Each phase has
+    // its own, from a lattice of its own particles.
+    let spacing = (material.mass / material.rest_density).sqrt();
+    let extent = (params.influence_radius / spacing).ceil() as i32;
+    let mut sum_grad = Vec2::ZERO;
+    let mut sum_grad_pressure = Vec2::ZERO;
+    let mut sum_dot = 0.0;
+    for y in -extent..=extent {
+        for x in -extent..=extent {
+            let offset = Vec2::new(x as f32, y as f32) * spacing;
+            let r = offset.length();
+            if r == 0.0 || r > params.influence_radius {
+                continue;
+            }
+            let grad = smoothing_kernel_gradient(Vec2::ZERO, offset, params);
+            let grad_pressure = pressure_kernel_gradient(Vec2::ZERO, offset, params);
+            sum_grad += grad;
+            sum_grad_pressure += grad_pressure;
+            sum_dot += grad.dot(grad_pressure);
+        }
+    }
+    let beta = 2.0 * (params.dt * material.mass / material.rest_density).powi(2);
+    let denominator = beta * (sum_grad.dot(sum_grad_pressure) + sum_dot);
+    if denominator <= 0.0 {
+        return 0.0;
+    }
+    // The lattice δ assumes a full, regular neighbourhood. Real neighbourhoods
+    // are irregular and the Jacobi corrections of neighbours add up, so the
+    // full δ overshoots after a few iterations. pcisph_relaxation (a half by
+    // default) trades a few more iterations for convergence.
+    params.pcisph_relaxation / denominator
+}
+
+pub fn calculate_pcisph_pressure_accel(
+    pos: Vec2,
+    pos_other: Vec2,
+    pressure: f32,
+    pressure_other: f32,
+    material: &GpuMaterial,
+    material_other: &GpuMaterial,
+    params: &SimulationParams,
+) -> Vec2 {
+    // mⱼ (pᵢ/ρ₀ᵢ² + pⱼ/ρ₀ⱼ²) ∇W, with each phase's ρ₀ in place of the actual
+    // densities as in the PCISPH paper so δ stays valid; subtracted by the
+    // caller
+    let term = pressure / (material.rest_density * material.rest_density);
+    let term_other = pressure_other / (material_other.rest_density * material_other.rest_density);
+    material_other.mass * (term + term_other) * pressure_kernel_gradient(pos, pos_other, params)
+}
+
+pub fn calculate_dfsph_alpha(grad_sum: Vec2, grad_square_sum: f32, density: f32) -> f32 {
+    // Bender and Koschier's factor αᵢ = ρᵢ / (|Σ mⱼ∇W|² + Σ |mⱼ∇W|²), what a
+    // particle's own pressure does to its density change. Zero for particles
+    // without neighbours, which have nothing to push against.
+    let denominator = grad_sum.length_squared() + grad_square_sum;
+    if denominator <= 0.0 {
+        return 0.0;
+    }
+    density / denominator
+}
+
+pub fn calculate_dfsph_gradient(
+    pos: Vec2,
+    pos_other: Vec2,
+    mass_other: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // mⱼ∇W, one neighbour's term of both sums in α. DFSPH uses the pressure
+    // kernel's gradient throughout, the solves are only consistent with one
+    // kernel.
+    mass_other * pressure_kernel_gradient(pos, pos_other, params)
+}
+
+pub fn calculate_density_derivative(
+    pos: Vec2,
+    pos_other: Vec2,
+    vel: Vec2,
+    vel_other: Vec2,
+    mass_other: f32,
+    params: &SimulationParams,
+) -> f32 {
+    // one neighbour's share of Dρ/Dt = Σ mⱼ (vᵢ - vⱼ)·∇W
+    mass_other * (vel - vel_other).dot(pressure_kernel_gradient(pos, pos_other, params))
+}
+
+pub fn calculate_dfsph_stiffness(
+    density: f32,
+    density_derivative: f32,
+    alpha: f32,
+    divergence: bool,
+    rest_density: f32,
+    params: &SimulationParams,
+) -> (f32, f32) {
+    // The pressure coefficient κ of one DFSPH Jacobi sweep and the particle's
+    // relative compression. The divergence solve drives Dρ/Dt to zero,
+    // κ = (Dρ/Dt)/Δt α, the density solve drives the predicted density
+    // ρ + Δt Dρ/Dt to ρ₀, κ = (ρ* - ρ₀)/Δt² α. Both errors are compressions
+    // over one step, so the two tolerances are comparable. ρ₀ is the
+    // particle's own phase's.
+    //
+    // α is the inverse of the diagonal of the pressure system, but in 2D the
+    // neighbours' terms outweigh it, so undamped Jacobi sweeps diverge after
+    // a few iterations. IISPH damps them for the same reason, here by
+    // dfsph_relaxation (0.3 by default).
+    let alpha = params.dfsph_relaxation * alpha;
+    if divergence {
+        let stiffness = density_derivative / params.dt * alpha;
+        let error = density_derivative.max(0.0) * params.dt / rest_density;
+        (stiffness, error)
+    } else {
+        let density_error = density + params.dt * density_derivative - rest_density;
+        let stiffness = density_error / (params.dt * params.dt) * alpha;
+        (stiffness, density_error.max(0.0) / rest_density)
+    }
+}
+
+pub fn calculate_dfsph_pressure_accel(
+    pos: Vec2,
+    pos_other: Vec2,
+    pressure_term: f32,
+    pressure_term_other: f32,
+    mass_other: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // mⱼ (pᵢ/ρᵢ² + pⱼ/ρⱼ²) ∇W with p = κρ, i.e. DFSPH's mⱼ (κᵢ/ρᵢ + κⱼ/ρⱼ) ∇W,
+    // where the terms are each particle's p/ρ²; subtracted by the caller
+    mass_other
+        * (pressure_term + pressure_term_other)
+        * pressure_kernel_gradient(pos, pos_other, params)
+}
+
+pub fn calculate_pbf_gradient(
+    pos: Vec2,
+    pos_other: Vec2,
+    mass_other: f32,
+    rest_density: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // mⱼ/ρ₀ᵢ ∇W, one neighbour's term of the constraint gradient ∇C
+    mass_other / rest_density * pressure_kernel_gradient(pos, pos_other, params)
+}
+
+pub fn calculate_pbf_lambda(
+    density: f32,
+    grad_sum: Vec2,
+    grad_square_sum: f32,
+    material: &GpuMaterial,
+    params: &SimulationParams,
+) -> f32 {
+    // Macklin and Müller's λᵢ = -Cᵢ / (Σₖ |∇ₖCᵢ|²/mₖ + ε) for the density
+    // constraint Cᵢ = ρᵢ/ρ₀ᵢ - 1, weighted by inverse mass so a heavier
+    // phase gives way less and sinks through a lighter one. `grad_square_sum`
+    // is the neighbours' Σ |∇ₖCᵢ|²/mₖ, the particle's own gradient is
+    // `grad_sum`. ε softens the constraint so particles with few neighbours
+    // do not get huge corrections.
+    let constraint = density / material.rest_density - 1.0;
+    -constraint
+        / (grad_sum.length_squared() / material.mass + grad_square_sum + params.pbf_relaxation)
+}
+
+pub fn calculate_pbf_displacement(
+    pos: Vec2,
+    pos_other: Vec2,
+    lambda: f32,
+    lambda_other: f32,
+    material: &GpuMaterial,
+    material_other: &GpuMaterial,
+    params: &SimulationParams,
+) -> Vec2 {
+    // one neighbour's share of Δpᵢ = Σ (λᵢ mⱼ/(mᵢρ₀ᵢ) + λⱼ/ρ₀ⱼ) ∇W, the
+    // inverse mass weighted move along ∇ᵢCᵢ and ∇ᵢCⱼ, plus the artificial
+    // pressure s_corr mⱼ/ρ₀ᵢ ∇W. s_corr = -k (W(r)/W(Δq))ⁿ is a small
+    // repulsion that keeps particles with too few neighbours from clumping.
+    // With one phase of mass m this is Δpᵢ = 1/ρ₀ Σ (λᵢ + λⱼ + m s_corr) ∇W.
+    let reference = smoothing_kernel(
+        Vec2::ZERO,
+        Vec2::new(ARTIFICIAL_PRESSURE_RADIUS * params.influence_radius, 0.0),
+        params,
+    );
+    let correction = if reference > 0.0 {
+        -params.artificial_pressure
+            * (smoothing_kernel(pos, pos_other, params) / reference)
+                .powi(ARTIFICIAL_PRESSURE_EXPONENT)
+    } else {
+        0.0
+    };
+    let gradient = pressure_kernel_gradient(pos, pos_other, params);
+    ((lambda / material.mass + correction) * material_other.mass / material.rest_density
+        + lambda_other / material_other.rest_density)
+        * gradient
 }
-pub fn calculate_density(pos: Vec2, pos_other: Vec2, params: &SimulationParams) -> f32 {
-    params.mass * poly_kernel(pos, pos_other, &params)
+
+pub fn calculate_vorticity(
+    pos: Vec2,
+    pos_other: Vec2,
+    vel: Vec2,
+    vel_other: Vec2,
+    params: &SimulationParams,
+) -> f32 {
+    // one neighbour's share of ωᵢ = Σ (vⱼ - vᵢ) × ∇ⱼW, the z component of
+    // the curl, positive for counter-clockwise rotation in a y-up frame
+    (vel_other - vel).perp_dot(pressure_kernel_gradient(pos_other, pos, params))
+}
+
+pub fn calculate_vorticity_gradient(
+    pos: Vec2,
+    pos_other: Vec2,
+    vorticity: f32,
+    vorticity_other: f32,
+    volume_other: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // one neighbour's share of η = ∇|ω| = Σ mⱼ/ρⱼ (|ωⱼ| - |ωᵢ|) ∇W
+    volume_other
+        * (vorticity_other.abs() - vorticity.abs())
+        * pressure_kernel_gradient(pos, pos_other, params)
+}
+
+pub fn calculate_vorticity_confinement(
+    vorticity_gradient: Vec2,
+    vorticity: f32,
+    params: &SimulationParams,
+) -> Vec2 {
+    // ε (N × ω) with N = η/|η| pointing towards the vortex centre, which puts
+    // back the rotation the solver damps out. ω is along z, so
+    // N × ω = (N.y ω, -N.x ω).
+    let length = vorticity_gradient.length();
+    if length < 0.00001 {
+        return Vec2::ZERO;
+    }
+    let normal = vorticity_gradient / length;
+    params.vorticity_confinement * Vec2::new(normal.y * vorticity, -normal.x * vorticity)
 }
@@ -1,6 +1,5 @@
 use crate::constants::SimulationParams;
-use crate::glam::{UVec2, Vec2};
-use crate::uvec2;
+use glam::{UVec2, Vec2, uvec2};
 
 pub fn grid_coord(pos: Vec2, params: &SimulationParams) -> UVec2 {
     uvec2(
@@ -16,18 +15,21 @@ pub fn hash(grid_coord: UVec2, world_size: Vec2, params: &SimulationParams) -> u
 }
 
 pub fn neighbours() -> [(i32, i32); 9] {
+    // same row-major order as neighbours() in update.wgsl, so both backends
+    // sum neighbour contributions in the same order
     [
-        (0, 0),
+        (-1, -1),
+        (0, -1),
+        (1, -1),
         (-1, 0),
+        (0, 0),
         (1, 0),
-        (0, -1),
+        (-1, 1),
         (0, 1),
         (1, 1),
-        (-1, -1),
-        (1, -1),
-        (-1, 1),
     ]
 }
+
 pub fn find_cell_start(lookups: &mut [(usize, usize)], cells: &[(u32, usize)]) {
     for (i, &(cell_id, _particle_id)) in cells.iter().enumerate() {
         let data = &mut lookups[cell_id as usize];
@@ -39,3 +41,35 @@ pub fn find_cell_start(lookups: &mut [(usize, usize)], cells: &[(u32, usize)]) {
         data.1 += 1;
     }
 }
+
+/// Calls `f` with the index of every particle in the 3x3 block of cells around
+/// `pos`, the particle itself included, visiting them in the same order as the
+/// WGSL passes. `cells` and `lookups` are the sorted grid built by
+/// `Particles::update`.
+pub fn for_each_neighbour(
+    pos: Vec2,
+    cells: &[(u32, usize)],
+    lookups: &[(usize, usize)],
+    params: &SimulationParams,
+    mut f: impl FnMut(usize),
+) {
+    let world_size = Vec2::new(params.width, params.height);
+    let grid_width = (world_size.x / params.cell_size).floor() as i32;
+    let grid_height = (world_size.y / params.cell_size).floor() as i32;
+    let grid_coord = grid_coord(pos, params);
+
+    for (offset_x, offset_y) in neighbours() {
+        let neighbor_x = grid_coord.x as i32 + offset_x;
+        let neighbor_y = grid_coord.y as i32 + offset_y;
+        if neighbor_x >= 0 && neighbor_x < grid_width && neighbor_y >= 0 && neighbor_y < grid_height
+        {
+            let valid_coord = uvec2(neighbor_x as u32, neighbor_y as u32);
+            let cell_key = hash(valid_coord, world_size, params);
+
+            let (start_index, count) = lookups[cell_key as usize];
+            for &(_, particle_idx) in &cells[start_index..start_index + count] {
+                f(particle_idx);
+            }
+        }
+    }
+}
@@ -1,12 +1,25 @@
+use crate::boundary::BoundaryParticles;
+use crate::collider::{self, Collider};
 use crate::constants::*;
-use crate::engine::physics::{
-    calculate_density, calculate_gravity_force, calculate_pressure, calculate_pressure_force,
+use crate::cpu::physics::{
+    calculate_boundary_density, calculate_boundary_pressure_force,
+    calculate_boundary_reaction_force, calculate_buoyancy_force, calculate_density,
+    calculate_density_derivative, calculate_dfsph_alpha, calculate_dfsph_gradient,
+    calculate_dfsph_pressure_accel, calculate_dfsph_stiffness, calculate_gravity_force,
+    calculate_heat_diffusion, calculate_pbf_displacement, calculate_pbf_gradient,
+    calculate_pbf_lambda, calculate_pcisph_pressure_accel, calculate_pressure,
+    calculate_pressure_force, calculate_relaxation_density, calculate_relaxation_displacement,
+    calculate_relaxation_pressure, calculate_surface_normal, calculate_surface_tension_force,
+    calculate_viscosity_force, calculate_vorticity, calculate_vorticity_confinement,
+    calculate_vorticity_gradient, calculate_xsph_correction, pcisph_delta,
 };
-use crate::engine::search;
-use crate::glam::Vec2;
-use crate::uvec2;
+use crate::cpu::search;
+use crate::gpu::particle::{GpuBoundaryParticle, GpuMaterials, GpuParticle};
+use crate::material::{self, Material};
+use crate::rigid_body::{self, NO_BODY, RigidBody};
+use crate::{Simulation, SolverStats, StepLimits};
+use glam::Vec2;
 use rayon::prelude::*;
-use rdxsort::*;
 
 pub type ParticleVector = Vec2;
 pub type ParticleScalar = f32;
@@ -26,6 +39,9 @@ pub struct Particle {
     pub density: ParticleScalar,
     pub pressure: ParticleScalar,
     pub force: ParticleVector,
+    pub phase: u32,
+    pub temperature: ParticleScalar,
+    pub accel: ParticleVector,
 }
 
 pub struct Particles {
@@ -35,11 +51,55 @@ pub struct Particles {
     pub density: Vec<ParticleScalar>,
     pub pressure: Vec<ParticleScalar>,
     pub force: Vec<ParticleVector>,
+    pub phase: Vec<u32>,
+    pub temperature: Vec<ParticleScalar>,
+    pub accel: Vec<ParticleVector>,
     cells: Vec<(u32, usize)>,
     lookups: Vec<(usize, usize)>,
+    xsph_vel: Vec<ParticleVector>,
+    normals: Vec<ParticleVector>,
+    near_density: Vec<ParticleScalar>,
+    solver_pos: Vec<ParticleVector>,
+    pressure_accel: Vec<ParticleVector>,
+    alpha: Vec<ParticleScalar>,
+    solver_vel: Vec<ParticleVector>,
+    lambda: Vec<ParticleScalar>,
+    position_delta: Vec<ParticleVector>,
+    vorticity: Vec<ParticleScalar>,
+    heating: Vec<ParticleScalar>,
+    rk2_vel: Vec<ParticleVector>,
+    // built on the first step that needs it and whenever the domain changes
+    boundary: Option<BoundaryParticles>,
+}
+
+/// The CPU backend behind the `Simulation` trait: a `Particles` set plus the
+/// parameters it is stepped with.
+pub struct CpuSimulation {
+    pub particles: Particles,
+    params: SimulationParams,
+    colliders: Vec<Collider>,
+    bodies: Vec<RigidBody>,
+    // what reset puts the bodies back to
+    initial_bodies: Vec<RigidBody>,
+    materials: Vec<Material>,
+    time: f64,
+    solver_stats: Option<SolverStats>,
+    step_limits: Option<StepLimits>,
 }
 
 impl IOInteraction {
+    pub fn from_params(params: &SimulationParams) -> Self {
+        // same sign convention as mouse_strength in the uniform buffer:
+        // > 0 attracts, < 0 repels, == 0 disables
+        if params.mouse_strength > 0.0 {
+            IOInteraction::Attract(params.mouse_strength)
+        } else if params.mouse_strength < 0.0 {
+            IOInteraction::Repel(-params.mouse_strength)
+        } else {
+            IOInteraction::None
+        }
+    }
+
     pub fn delta_vel(&self, particle_pos: Vec2, io_pos: Vec2, params: &SimulationParams) -> Vec2 {
         match self {
             IOInteraction::None => Vec2::ZERO,
@@ -71,6 +131,12 @@ impl IOInteraction {
     }
 }
 
+impl Default for Particles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Particles {
     pub fn new() -> Self {
         Self {
@@ -80,9 +146,57 @@ impl Particles {
             density: Vec::new(),
             pressure: Vec::new(),
             force: Vec::new(),
+            phase: Vec::new(),
+            temperature: Vec::new(),
+            accel: Vec::new(),
             cells: Vec::new(),
             lookups: Vec::new(),
+            xsph_vel: Vec::new(),
+            normals: Vec::new(),
+            near_density: Vec::new(),
+            solver_pos: Vec::new(),
+            pressure_accel: Vec::new(),
+            alpha: Vec::new(),
+            solver_vel: Vec::new(),
+            lambda: Vec::new(),
+            position_delta: Vec::new(),
+            vorticity: Vec::new(),
+            heating: Vec::new(),
+            rk2_vel: Vec::new(),
+            boundary: None,
+        }
+    }
+
+    /// Seeds `params.no_particles` particles in the same block layout and
+    /// phases the GPU path starts from, so both backends begin from identical state.
+    pub fn from_params(params: &SimulationParams, materials: &[Material]) -> Self {
+        let initial_particles = GpuParticle::spawn_particles(
+            params,
+            materials,
+            params.width as u32,
+            params.height as u32,
+        );
+        let initial_predicted_pos: Vec<[f32; 2]> =
+            initial_particles.iter().map(|p| p.pos).collect();
+        Self::from_gpu_particles(&initial_particles, &initial_predicted_pos)
+    }
+
+    pub fn from_gpu_particles(particles: &[GpuParticle], predicted_pos: &[[f32; 2]]) -> Self {
+        let mut cpu_particles = Self::new();
+        for (particle, predicted_pos) in particles.iter().zip(predicted_pos) {
+            cpu_particles.spawn(Particle {
+                pos: Vec2::from(particle.pos),
+                predicted_pos: Vec2::from(*predicted_pos),
+                vel: Vec2::from(particle.vel),
+                density: particle.density,
+                pressure: particle.pressure,
+                force: Vec2::from(particle.force),
+                phase: particle.phase,
+                temperature: particle.temperature,
+                accel: Vec2::from(particle.accel),
+            });
         }
+        cpu_particles
     }
 
     pub fn spawn(&mut self, particle: Particle) {
@@ -92,9 +206,21 @@ impl Particles {
         self.density.push(particle.density);
         self.pressure.push(particle.pressure);
         self.force.push(particle.force);
+        self.phase.push(particle.phase);
+        self.temperature.push(particle.temperature);
+        self.accel.push(particle.accel);
     }
 
-    pub fn boundaries(world_size: Vec2, pos: &mut Vec2, vel: &mut Vec2, params: &SimulationParams) {
+    /// Keeps a particle inside the window, then pushes it out of `colliders`
+    /// and `bodies`, mirroring boundaries() in update.wgsl.
+    pub fn boundaries(
+        world_size: Vec2,
+        colliders: &[Collider],
+        bodies: &[RigidBody],
+        pos: &mut Vec2,
+        vel: &mut Vec2,
+        params: &SimulationParams,
+    ) {
         let world_width = world_size.x;
         let world_height = world_size.y;
 
@@ -115,142 +241,1176 @@ impl Particles {
             vel.y = -vel.y * params.damping;
             pos.y = particle_radius_m;
         }
+
+        collider::collide(colliders, pos, vel, params);
+        rigid_body::collide(bodies, pos, vel, params);
     }
 
-    pub fn integrate(
+    /// One full solver step, in the same order as `GpuSimulation::compute`:
+    /// neighbour search, density/pressure, forces, XSPH, then integration.
+    /// With the double density relaxation solver `update` leaves position
+    /// corrections in `force` and `integrate_relaxed` applies them instead.
+    /// With PCISPH the pressure is iterated by `correct_pressure` before
+    /// integrating, with DFSPH by `solve_dfsph`, and their stats are returned.
+    /// Position based fluids solve positions in `solve_pbf` and finish with
+    /// `integrate_pbf` instead. The bodies are only read, `rigid_body::step`
+    /// moves them afterwards with the forces `calculate_body_forces` left.
+    /// `materials` gives every phase's mass, rest density and viscosity.
+    /// Heat is diffused and taken from the colliders in the force pass and
+    /// applied by `integrate`, so it only moves with the force-based solvers.
+    /// RK2 runs `update` a second time at the positions `predict_rk2` puts
+    /// the particles at.
+    pub fn step(
         &mut self,
-        world_size: Vec2,
-        mouse_pos: Vec2,
-        interaction: IOInteraction,
-        dt: f32,
         params: &SimulationParams,
+        colliders: &[Collider],
+        bodies: &[RigidBody],
+        materials: &GpuMaterials,
+    ) -> Option<SolverStats> {
+        self.update(params, colliders, bodies, materials);
+        if params.active_integrator().is_two_stage() {
+            self.predict_rk2(params);
+            self.update(params, colliders, bodies, materials);
+        }
+        if params.boundary_particles() && !bodies.is_empty() {
+            self.calculate_body_forces(params, materials);
+        }
+        let solver = Solver::from_u32(params.solver);
+        if solver == Solver::DoubleDensity {
+            self.integrate_relaxed(params, colliders, bodies);
+            return None;
+        }
+        if solver == Solver::PositionBased {
+            let stats = self.solve_pbf(params, materials);
+            if params.xsph > 0.0 {
+                self.smooth_velocities(params, materials);
+            }
+            if params.vorticity_confinement > 0.0 {
+                self.confine_vorticity(params, materials);
+            }
+            self.integrate_pbf(params, colliders, bodies);
+            return Some(stats);
+        }
+        if params.xsph > 0.0 {
+            self.smooth_velocities(params, materials);
+        }
+        let stats = match solver {
+            Solver::Pcisph => Some(self.correct_pressure(params, materials)),
+            Solver::Dfsph => Some(self.solve_dfsph(params, materials)),
+            _ => None,
+        };
+        self.integrate(params, colliders, bodies);
+        stats
+    }
+
+    /// Applies the displacements `update` computed for double density
+    /// relaxation. `predicted_pos` already carries last step's velocity,
+    /// gravity included, so the relaxed position is that plus the
+    /// correction and the new velocity is whatever actually moved the particle.
+    pub fn integrate_relaxed(
+        &mut self,
+        params: &SimulationParams,
+        colliders: &[Collider],
+        bodies: &[RigidBody],
     ) {
-        for i in 0..NO_PARTICLES {
-            let acceleration = self.force[i] / self.density[i];
-            let velocity_old = self.vel[i];
+        let world_size = Vec2::new(params.width, params.height);
+        let mouse_pos = Vec2::from(params.mouse_pos);
+        let interaction = IOInteraction::from_params(params);
+        let dt = params.dt;
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
 
-            self.vel[i] += acceleration * dt;
+        for i in 0..num_particles {
+            let relaxed_pos = self.predicted_pos[i] + self.force[i] * dt * dt;
+            self.vel[i] = (relaxed_pos - self.pos[i]) / dt;
+            self.pos[i] = relaxed_pos;
+            Self::boundaries(
+                world_size,
+                colliders,
+                bodies,
+                &mut self.pos[i],
+                &mut self.vel[i],
+                params,
+            );
 
-            let interaction_vel = interaction.delta_vel(self.pos[i], mouse_pos, params);
-            self.vel[i] += interaction_vel;
+            self.vel[i] += Vec2::from(params.gravity) * dt;
+            self.vel[i] += interaction.delta_vel(self.pos[i], mouse_pos, params);
+            if self.vel[i].length_squared() > params.max_vel * params.max_vel {
+                self.vel[i] = (self.vel[i] / self.vel[i].length()) * params.max_vel;
+            }
+            self.predicted_pos[i] = self.pos[i] + self.vel[i] * dt;
+        }
+    }
+
+    /// Finishes a position based fluids step. `solve_pbf` already moved the
+    /// particles and set their velocities, so like `integrate_relaxed` this
+    /// only applies the boundaries, then gravity and the mouse for the next
+    /// prediction.
+    pub fn integrate_pbf(
+        &mut self,
+        params: &SimulationParams,
+        colliders: &[Collider],
+        bodies: &[RigidBody],
+    ) {
+        let world_size = Vec2::new(params.width, params.height);
+        let mouse_pos = Vec2::from(params.mouse_pos);
+        let interaction = IOInteraction::from_params(params);
+        let dt = params.dt;
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
 
+        for i in 0..num_particles {
+            Self::boundaries(
+                world_size,
+                colliders,
+                bodies,
+                &mut self.pos[i],
+                &mut self.vel[i],
+                params,
+            );
+
+            self.vel[i] += Vec2::from(params.gravity) * dt;
+            self.vel[i] += interaction.delta_vel(self.pos[i], mouse_pos, params);
             if self.vel[i].length_squared() > params.max_vel * params.max_vel {
                 self.vel[i] = (self.vel[i] / self.vel[i].length()) * params.max_vel;
             }
+            self.predicted_pos[i] = self.pos[i] + self.vel[i] * dt;
+        }
+    }
+
+    /// Steps the force-based solvers with `params.active_integrator()`. Every
+    /// integrator leaves the acceleration it stepped with in `accel` and puts
+    /// `predicted_pos` where the next step's forces are to be evaluated.
+    pub fn integrate(
+        &mut self,
+        params: &SimulationParams,
+        colliders: &[Collider],
+        bodies: &[RigidBody],
+    ) {
+        let world_size = Vec2::new(params.width, params.height);
+        let mouse_pos = Vec2::from(params.mouse_pos);
+        let interaction = IOInteraction::from_params(params);
+        let dt = params.dt;
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
 
-            self.pos[i] += (self.vel[i] + velocity_old) * 0.5 * dt;
-            Self::boundaries(world_size, &mut self.pos[i], &mut self.vel[i], params);
+        let solver = Solver::from_u32(params.solver);
+        let iterative = solver.iterates_pressure();
+        let integrator = params.active_integrator();
+
+        for i in 0..num_particles {
+            let mut acceleration = self.force[i] / self.density[i];
+            if iterative {
+                acceleration += self.pressure_accel[i];
+            }
+            self.temperature[i] += self.heating[i] * dt;
+
+            // the mouse and the velocity cap act on whichever velocity the
+            // integrator moves the particle with
+            let interaction_vel = interaction.delta_vel(self.pos[i], mouse_pos, params);
+            let limit = |vel: Vec2| {
+                let vel = vel + interaction_vel;
+                if vel.length_squared() > params.max_vel * params.max_vel {
+                    (vel / vel.length()) * params.max_vel
+                } else {
+                    vel
+                }
+            };
+
+            match integrator {
+                Integrator::Trapezoidal => {
+                    let velocity_old = self.vel[i];
+                    self.vel[i] = limit(self.vel[i] + acceleration * dt);
+                    self.pos[i] += (self.vel[i] + velocity_old) * 0.5 * dt;
+                }
+                Integrator::SymplecticEuler => {
+                    self.vel[i] = limit(self.vel[i] + acceleration * dt);
+                    self.pos[i] += self.vel[i] * dt;
+                }
+                Integrator::Leapfrog => {
+                    // kick and drift, the closing kick comes after the walls
+                    self.vel[i] = limit(self.vel[i] + self.accel[i] * 0.5 * dt);
+                    self.pos[i] += self.vel[i] * dt;
+                }
+                Integrator::VelocityVerlet => {
+                    self.pos[i] += (self.vel[i] + self.accel[i] * 0.5 * dt) * dt;
+                    self.vel[i] = limit(self.vel[i] + (self.accel[i] + acceleration) * 0.5 * dt);
+                }
+                Integrator::Rk2 => {
+                    // predict_rk2 kept v₀ and left a₁ in accel. vel holds
+                    // v₀ + a₁dt, smoothed by XSPH if that is on since, so
+                    // v₀ cannot be rebuilt from it
+                    let predicted_vel = self.vel[i];
+                    let velocity_old = self.rk2_vel[i];
+                    self.vel[i] = limit(velocity_old + (self.accel[i] + acceleration) * 0.5 * dt);
+                    self.pos[i] += (velocity_old + predicted_vel) * 0.5 * dt;
+                }
+            }
+            Self::boundaries(
+                world_size,
+                colliders,
+                bodies,
+                &mut self.pos[i],
+                &mut self.vel[i],
+                params,
+            );
+            if integrator == Integrator::Leapfrog {
+                self.vel[i] += acceleration * 0.5 * dt;
+            }
+            self.accel[i] = acceleration;
+
+            self.predicted_pos[i] = match integrator {
+                // Density and force kernels read predicted_pos (= pos + vel*dt)
+                // rather than pos, which stabilizes the sim against pressure
+                // instabilities at large timesteps (Müller). PCISPH's
+                // prediction assumes the same.
+                Integrator::Trapezoidal => self.pos[i] + self.vel[i] * dt,
+                Integrator::SymplecticEuler if solver == Solver::Pcisph => {
+                    self.pos[i] + self.vel[i] * dt
+                }
+                // where the next drift takes the particle
+                Integrator::Leapfrog | Integrator::VelocityVerlet => {
+                    self.pos[i] + (self.vel[i] + acceleration * 0.5 * dt) * dt
+                }
+                // the current positions, which is also what DFSPH wants as it
+                // predicts the density from the velocities itself
+                Integrator::SymplecticEuler | Integrator::Rk2 => self.pos[i],
+            };
+        }
+    }
+
+    /// RK2's predictor: an Euler step with the acceleration of the first
+    /// force evaluation, for the second one to be made at. The velocity it
+    /// starts from is kept for `integrate`, the acceleration in `accel`.
+    pub fn predict_rk2(&mut self, params: &SimulationParams) {
+        let dt = params.dt;
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+        self.rk2_vel.clear();
+        self.rk2_vel.extend_from_slice(&self.vel[..num_particles]);
+        for i in 0..num_particles {
+            let acceleration = self.force[i] / self.density[i];
+            self.accel[i] = acceleration;
             self.predicted_pos[i] = self.pos[i] + self.vel[i] * dt;
+            self.vel[i] += acceleration * dt;
         }
     }
 
-    pub fn update(&mut self, world_size: Vec2, params: &SimulationParams) {
-        let mut cells: Vec<(u32, usize)> = Vec::new(); //cell id, particle id
+    pub fn update(
+        &mut self,
+        params: &SimulationParams,
+        colliders: &[Collider],
+        bodies: &[RigidBody],
+        materials: &GpuMaterials,
+    ) {
+        let world_size = Vec2::new(params.width, params.height);
         let grid_width = (world_size.x / params.cell_size).floor() as usize;
         let grid_height = (world_size.y / params.cell_size).floor() as usize;
         let total_cells = grid_width * grid_height;
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+
         self.cells.clear();
+        for i in 0..num_particles {
+            let clamped_pos = self.predicted_pos[i].clamp(Vec2::ZERO, world_size - 0.1);
+            let grid_coord = search::grid_coord(clamped_pos, params);
+            self.cells
+                .push((search::hash(grid_coord, world_size, params), i));
+        }
+        self.cells.sort_by_key(|k| k.0);
 
         self.lookups.clear();
         self.lookups.resize(total_cells, (0usize, 0usize));
+        search::find_cell_start(&mut self.lookups, &self.cells);
 
-        for i in 0..NO_PARTICLES {
-            let clamped_pos = self.predicted_pos[i].clamp(Vec2::ZERO, world_size - 0.1);
-            let grid_coord = search::grid_coord(clamped_pos, params);
-            cells.push((search::hash(grid_coord, world_size, params), i));
+        let boundary_particles = params.boundary_particles();
+        if boundary_particles
+            && self
+                .boundary
+                .as_ref()
+                .is_none_or(|boundary| boundary.is_stale(params))
+        {
+            self.boundary = Some(BoundaryParticles::new(params));
         }
-        cells.sort_by_key(|k| k.0);
+        if boundary_particles && let Some(boundary) = &mut self.boundary {
+            boundary.set_bodies(bodies, params);
+        }
+
+        let cells = &self.cells;
+        let lookups = &self.lookups;
+        let predicted_pos = &self.predicted_pos;
+        let phases = &self.phase;
+        let boundary = self.boundary.as_ref().filter(|_| boundary_particles);
 
-        let mut lookups = vec![(0usize, 0usize); total_cells];
-        search::find_cell_start(&mut lookups, &cells);
+        let solver = Solver::from_u32(params.solver);
+        if solver == Solver::DoubleDensity {
+            self.relax(params);
+            return;
+        }
 
-        self.density
+        self.alpha.clear();
+        self.alpha.resize(num_particles, 0.0);
+        self.density[..num_particles]
             .par_iter_mut()
             .enumerate()
-            .zip(self.pressure.par_iter_mut())
-            .for_each(|((i, density_ref), pressure_ref)| {
+            .zip(self.pressure[..num_particles].par_iter_mut())
+            .zip(self.alpha.par_iter_mut())
+            .for_each(|(((i, density_ref), pressure_ref), alpha_ref)| {
+                let material = materials.get(phases[i]);
                 let mut current_density: f32 = 0.0;
-                let grid_coord = search::grid_coord(self.predicted_pos[i], params);
-
-                for (offset_x, offset_y) in search::neighbours() {
-                    let neighbor_x = grid_coord.x as i32 + offset_x;
-                    let neighbor_y = grid_coord.y as i32 + offset_y;
-                    if neighbor_x >= 0
-                        && neighbor_x < grid_width as i32
-                        && neighbor_y >= 0
-                        && neighbor_y < grid_height as i32
-                    {
-                        let valid_coord = uvec2(neighbor_x as u32, neighbor_y as u32);
-                        let cell_key = search::hash(valid_coord, world_size, params);
-
-                        let (start_index, count) = lookups[cell_key as usize];
-
-                        for j in 0..count {
-                            let particle_idx = cells[start_index + j].1;
-
-                            current_density += calculate_density(
-                                self.predicted_pos[i],
-                                self.predicted_pos[particle_idx],
-                                params,
-                            );
-                        }
+                let mut grad_sum = Vec2::ZERO;
+                let mut grad_square_sum = 0.0;
+                search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                    current_density += calculate_density(
+                        predicted_pos[i],
+                        predicted_pos[j],
+                        materials.get(phases[j]).mass,
+                        params,
+                    );
+                    if solver == Solver::Dfsph && i != j {
+                        let grad = calculate_dfsph_gradient(
+                            predicted_pos[i],
+                            predicted_pos[j],
+                            materials.get(phases[j]).mass,
+                            params,
+                        );
+                        grad_sum += grad;
+                        grad_square_sum += grad.length_squared();
                     }
+                });
+                if let Some(boundary) = boundary {
+                    boundary.for_each_neighbour(predicted_pos[i], params, |wall| {
+                        current_density += calculate_boundary_density(
+                            predicted_pos[i],
+                            Vec2::from(wall.pos),
+                            wall.volume,
+                            material.rest_density,
+                            params,
+                        );
+                    });
                 }
-                // for j in 0..NO_PARTICLES {
-                //     current_density +=
-                //         calculate_density(self.predicted_pos[i], self.predicted_pos[j]);
-                // }
                 *density_ref = current_density;
-                *pressure_ref = calculate_pressure(*density_ref, params);
+                *pressure_ref = if solver.is_iterative() {
+                    // built up from zero by correct_pressure or solve_dfsph,
+                    // position based fluids have none
+                    0.0
+                } else {
+                    calculate_pressure(*density_ref, material.rest_density, params)
+                };
+                *alpha_ref = calculate_dfsph_alpha(grad_sum, grad_square_sum, current_density);
             });
 
-        let predicted_pos = &self.predicted_pos;
+        if solver == Solver::PositionBased {
+            // solve_pbf works on positions, there are no forces to compute
+            return;
+        }
+
         let pressures = &self.pressure;
         let densities = &self.density;
+        let velocities = &self.vel;
+        let temperatures = &self.temperature;
 
-        self.force
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, force_ref)| {
-                let mut current_force = Vec2::ZERO;
-                // println!("{}", densities[i]);
-                let grid_coord = search::grid_coord(self.predicted_pos[i], params);
-                let grid_neighbours = search::neighbours();
-                for (offset_x, offset_y) in grid_neighbours {
-                    let neighbor_x = grid_coord.x as i32 + offset_x;
-                    let neighbor_y = grid_coord.y as i32 + offset_y;
-                    if neighbor_x >= 0
-                        && neighbor_x < grid_width as i32
-                        && neighbor_y >= 0
-                        && neighbor_y < grid_height as i32
-                    {
-                        let valid_coord = uvec2(neighbor_x as u32, neighbor_y as u32);
-                        let cell_key = search::hash(valid_coord, world_size, params);
-
-                        let (start_index, count) = lookups[cell_key as usize];
-
-                        for j in 0..count {
-                            let particle_idx = cells[start_index + j].1;
-
-                            if i == particle_idx {
-                                continue;
-                            }
-                            let pressure_force = calculate_pressure_force(
+        // colour-field normals for surface tension, they need every density
+        // first and every normal has to be ready before the force pass
+        self.normals.clear();
+        if params.surface_tension > 0.0 {
+            self.normals.resize(num_particles, Vec2::ZERO);
+            self.normals
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, normal_ref)| {
+                    let mut normal = Vec2::ZERO;
+                    search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                        if i != j {
+                            normal += calculate_surface_normal(
                                 predicted_pos[i],
-                                predicted_pos[particle_idx],
-                                pressures[i],
-                                pressures[particle_idx],
-                                densities[particle_idx],
+                                predicted_pos[j],
+                                densities[j],
+                                materials.get(phases[j]).mass,
                                 params,
                             );
-                            current_force -= pressure_force;
                         }
+                    });
+                    *normal_ref = normal;
+                });
+        }
+        let normals = &self.normals;
+
+        self.heating.clear();
+        self.heating.resize(num_particles, 0.0);
+        self.force[..num_particles]
+            .par_iter_mut()
+            .enumerate()
+            .zip(self.heating.par_iter_mut())
+            .for_each(|((i, force_ref), heating_ref)| {
+                let material = materials.get(phases[i]);
+                let mut current_force = Vec2::ZERO;
+                let mut heating =
+                    collider::heating(colliders, predicted_pos[i], temperatures[i], params);
+                search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                    if i == j {
+                        return;
                     }
+                    let material_other = materials.get(phases[j]);
+                    if !solver.iterates_pressure() {
+                        let pressure_force = calculate_pressure_force(
+                            predicted_pos[i],
+                            predicted_pos[j],
+                            pressures[i],
+                            pressures[j],
+                            densities[j],
+                            material_other.mass,
+                            params,
+                        );
+                        current_force -= pressure_force;
+                    }
+                    let volume_other = material_other.mass / densities[j];
+                    current_force += calculate_viscosity_force(
+                        predicted_pos[i],
+                        predicted_pos[j],
+                        velocities[i],
+                        velocities[j],
+                        volume_other,
+                        0.5 * (material.viscosity + material_other.viscosity),
+                        params,
+                    );
+                    heating += calculate_heat_diffusion(
+                        predicted_pos[i],
+                        predicted_pos[j],
+                        temperatures[i],
+                        temperatures[j],
+                        volume_other,
+                        params,
+                    );
+                    if params.surface_tension > 0.0 {
+                        current_force += calculate_surface_tension_force(
+                            predicted_pos[i] - predicted_pos[j],
+                            normals[i] - normals[j],
+                            densities[i],
+                            densities[j],
+                            material,
+                            material_other,
+                            params,
+                        );
+                    }
+                });
+                if let Some(boundary) = boundary {
+                    boundary.for_each_neighbour(predicted_pos[i], params, |wall| {
+                        current_force -= calculate_boundary_pressure_force(
+                            predicted_pos[i],
+                            Vec2::from(wall.pos),
+                            wall.volume,
+                            pressures[i],
+                            densities[i],
+                            material.rest_density,
+                            params,
+                        );
+                    });
                 }
                 let gravity_force = calculate_gravity_force(densities[i], params);
                 current_force += gravity_force;
+                current_force += calculate_buoyancy_force(densities[i], temperatures[i], params);
 
                 *force_ref = current_force;
+                *heating_ref = heating;
+            });
+    }
+
+    /// The push of the fluid on every body particle, mirroring
+    /// calculate_body_forces in update.wgsl: the opposite of the boundary
+    /// pressure force each neighbour got in `update`.
+    pub fn calculate_body_forces(&mut self, params: &SimulationParams, materials: &GpuMaterials) {
+        let Some(boundary) = &mut self.boundary else {
+            return;
+        };
+        let cells = &self.cells;
+        let lookups = &self.lookups;
+        let predicted_pos = &self.predicted_pos;
+        let pressures = &self.pressure;
+        let densities = &self.density;
+        let phases = &self.phase;
+
+        boundary.particles.par_iter_mut().for_each(|particle| {
+            if particle.body == NO_BODY {
+                return;
+            }
+            let pos = Vec2::from(particle.pos);
+            let mut force = Vec2::ZERO;
+            search::for_each_neighbour(pos, cells, lookups, params, |j| {
+                force += calculate_boundary_reaction_force(
+                    predicted_pos[j],
+                    pos,
+                    particle.volume,
+                    pressures[j],
+                    densities[j],
+                    materials.get(phases[j]),
+                    params,
+                );
+            });
+            particle.force = force.into();
+        });
+    }
+
+    /// The fastest particle and the largest acceleration of the step that
+    /// just ran, mirroring reduce_step_limits in update.wgsl.
+    pub fn step_limits(&self, params: &SimulationParams) -> StepLimits {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+        let solver = Solver::from_u32(params.solver);
+        let gravity = Vec2::from(params.gravity).length();
+        (0..num_particles)
+            .into_par_iter()
+            .map(|i| {
+                let max_acceleration = if solver.has_force_pass() {
+                    let mut acceleration = self.force[i] / self.density[i];
+                    if solver.iterates_pressure() {
+                        acceleration += self.pressure_accel[i];
+                    }
+                    acceleration.length()
+                } else {
+                    gravity
+                };
+                StepLimits {
+                    max_speed: self.vel[i].length(),
+                    max_acceleration,
+                }
+            })
+            .reduce(StepLimits::default, |a, b| StepLimits {
+                max_speed: a.max_speed.max(b.max_speed),
+                max_acceleration: a.max_acceleration.max(b.max_acceleration),
+            })
+    }
+
+    /// The wall and body particles with the forces `calculate_body_forces`
+    /// left, none when the boundary particles are off.
+    pub fn body_samples(&self, params: &SimulationParams) -> &[GpuBoundaryParticle] {
+        match &self.boundary {
+            Some(boundary) if params.boundary_particles() => boundary.particles.as_slice(),
+            _ => &[],
+        }
+    }
+
+    /// PCISPH's prediction-correction loop, mirroring
+    /// `GpuSimulation::correct_pressure`: predict where `integrate` would move
+    /// each particle with the current pressure, add δ times the density error
+    /// there to the pressure, recompute the pressure acceleration, until the
+    /// mean relative compression is below `max_density_error`. Needs the
+    /// non-pressure forces from `update` and the XSPH velocities, if any.
+    pub fn correct_pressure(
+        &mut self,
+        params: &SimulationParams,
+        materials: &GpuMaterials,
+    ) -> SolverStats {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+        let max_iterations = params.max_solver_iterations.max(1);
+        let min_iterations = params.min_solver_iterations.clamp(1, max_iterations);
+        // one δ per phase, see pcisph_delta
+        let deltas: Vec<f32> = materials.materials[..materials.count.max(1) as usize]
+            .iter()
+            .map(|material| pcisph_delta(material, params))
+            .collect();
+        let dt = params.dt;
+        let world_size = Vec2::new(params.width, params.height);
+
+        self.solver_pos.clear();
+        self.solver_pos.resize(num_particles, Vec2::ZERO);
+        self.pressure_accel.clear();
+        self.pressure_accel.resize(num_particles, Vec2::ZERO);
+
+        let mut stats = SolverStats::default();
+        for iteration in 1..=max_iterations {
+            for i in 0..num_particles {
+                let acceleration = self.force[i] / self.density[i] + self.pressure_accel[i];
+                // kept inside the walls like `boundaries`, see pcisph_predict in update.wgsl
+                self.solver_pos[i] = (self.pos[i] + (self.vel[i] + acceleration * dt) * dt)
+                    .clamp(Vec2::splat(params.radius), world_size - params.radius);
+            }
+
+            let cells = &self.cells;
+            let lookups = &self.lookups;
+            let predicted_pos = &self.predicted_pos;
+            let solver_pos = &self.solver_pos;
+            let phases = &self.phase;
+            let deltas = &deltas;
+
+            let error_sum: f32 = self.pressure[..num_particles]
+                .par_iter_mut()
+                .enumerate()
+                .map(|(i, pressure_ref)| {
+                    let material = materials.get(phases[i]);
+                    let mut density = 0.0;
+                    // the grid was built from predicted_pos, which is within a
+                    // dt² term of solver_pos
+                    search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                        density += calculate_density(
+                            solver_pos[i],
+                            solver_pos[j],
+                            materials.get(phases[j]).mass,
+                            params,
+                        );
+                    });
+                    let density_error = density - material.rest_density;
+                    let delta = deltas[(phases[i] as usize).min(deltas.len() - 1)];
+                    // negative pressure would pull the free surface into clumps
+                    *pressure_ref = (*pressure_ref + delta * density_error).max(0.0);
+                    density_error.max(0.0) / material.rest_density
+                })
+                .sum();
+
+            let pressures = &self.pressure;
+            self.pressure_accel
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, accel_ref)| {
+                    let mut acceleration = Vec2::ZERO;
+                    search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                        if i != j {
+                            acceleration -= calculate_pcisph_pressure_accel(
+                                solver_pos[i],
+                                solver_pos[j],
+                                pressures[i],
+                                pressures[j],
+                                materials.get(phases[i]),
+                                materials.get(phases[j]),
+                                params,
+                            );
+                        }
+                    });
+                    *accel_ref = acceleration;
+                });
+
+            stats.iterations = iteration;
+            if iteration >= min_iterations {
+                stats.density_error = error_sum / num_particles.max(1) as f32;
+                if stats.density_error <= params.max_density_error {
+                    break;
+                }
+            }
+        }
+        stats
+    }
+
+    /// DFSPH (Bender and Koschier 2015), mirroring `GpuSimulation::solve_dfsph`:
+    /// a divergence solve on the velocities `integrate` starts from, folded
+    /// into `vel`, then a density solve whose pressure acceleration
+    /// `integrate` adds. Needs the densities, α factors and non-pressure
+    /// forces from `update` and the XSPH velocities, if any.
+    pub fn solve_dfsph(
+        &mut self,
+        params: &SimulationParams,
+        materials: &GpuMaterials,
+    ) -> SolverStats {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+
+        self.pressure_accel.clear();
+        self.pressure_accel.resize(num_particles, Vec2::ZERO);
+
+        let (divergence_iterations, divergence_error) = self.iterate_dfsph(params, materials, true);
+        for i in 0..num_particles {
+            self.vel[i] += self.pressure_accel[i] * params.dt;
+            self.pressure[i] = 0.0;
+            self.pressure_accel[i] = Vec2::ZERO;
+        }
+
+        let (iterations, density_error) = self.iterate_dfsph(params, materials, false);
+        SolverStats {
+            iterations,
+            density_error,
+            divergence_iterations,
+            divergence_error,
+        }
+    }
+
+    // Jacobi sweeps of one DFSPH solve until the mean compression is within
+    // its tolerance, see dfsph_solve in update.wgsl. The density solve
+    // includes the non-pressure forces in the predicted velocities, the
+    // divergence solve does not. Returns the iterations run and the last error.
+    fn iterate_dfsph(
+        &mut self,
+        params: &SimulationParams,
+        materials: &GpuMaterials,
+        divergence: bool,
+    ) -> (u32, f32) {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+        let max_iterations = params.max_solver_iterations.max(1);
+        let min_iterations = params.min_solver_iterations.clamp(1, max_iterations);
+        let tolerance = if divergence {
+            params.max_divergence_error
+        } else {
+            params.max_density_error
+        };
+        let dt = params.dt;
+        let world_size = Vec2::new(params.width, params.height);
+
+        self.solver_vel.clear();
+        self.solver_vel.resize(num_particles, Vec2::ZERO);
+
+        let mut error = 0.0;
+        for iteration in 1..=max_iterations {
+            for i in 0..num_particles {
+                let mut vel = self.vel[i] + self.pressure_accel[i] * dt;
+                if !divergence {
+                    vel += self.force[i] / self.density[i] * dt;
+                }
+                // stopped at the walls, see dfsph_velocity in update.wgsl
+                let limited_pos = (self.predicted_pos[i] + vel * dt)
+                    .clamp(Vec2::splat(params.radius), world_size - params.radius);
+                self.solver_vel[i] = (limited_pos - self.predicted_pos[i]) / dt;
+            }
+
+            let cells = &self.cells;
+            let lookups = &self.lookups;
+            let predicted_pos = &self.predicted_pos;
+            let densities = &self.density;
+            let alphas = &self.alpha;
+            let solver_vel = &self.solver_vel;
+            let phases = &self.phase;
+
+            let error_sum: f32 = self.pressure[..num_particles]
+                .par_iter_mut()
+                .enumerate()
+                .map(|(i, pressure_ref)| {
+                    let mut density_derivative = 0.0;
+                    search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                        if i != j {
+                            density_derivative += calculate_density_derivative(
+                                predicted_pos[i],
+                                predicted_pos[j],
+                                solver_vel[i],
+                                solver_vel[j],
+                                materials.get(phases[j]).mass,
+                                params,
+                            );
+                        }
+                    });
+                    let (stiffness, error) = calculate_dfsph_stiffness(
+                        densities[i],
+                        density_derivative,
+                        alphas[i],
+                        divergence,
+                        materials.get(phases[i]).rest_density,
+                        params,
+                    );
+                    // negative pressure would pull the free surface into clumps
+                    *pressure_ref = (*pressure_ref + stiffness * densities[i]).max(0.0);
+                    error
+                })
+                .sum();
+
+            let pressures = &self.pressure;
+            let pressure_term = |i: usize| pressures[i] / (densities[i] * densities[i]);
+            self.pressure_accel
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, accel_ref)| {
+                    let mut acceleration = Vec2::ZERO;
+                    search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                        if i != j {
+                            acceleration -= calculate_dfsph_pressure_accel(
+                                predicted_pos[i],
+                                predicted_pos[j],
+                                pressure_term(i),
+                                pressure_term(j),
+                                materials.get(phases[j]).mass,
+                                params,
+                            );
+                        }
+                    });
+                    *accel_ref = acceleration;
+                });
+
+            if iteration >= min_iterations {
+                error = error_sum / num_particles.max(1) as f32;
+                if error <= tolerance {
+                    return (iteration, error);
+                }
+            }
+        }
+        (max_iterations, error)
+    }
+
+    /// Position based fluids (Macklin and Müller 2013), mirroring the PBF
+    /// passes of `GpuSimulation::compute`: Jacobi iterations of the density
+    /// constraints on positions starting from `predicted_pos`, kept inside
+    /// the walls, until the mean relative compression is below
+    /// `max_density_error`. The particles are then moved there and their
+    /// velocity is whatever moved them. Reuses the neighbour grid built by `update`.
+    pub fn solve_pbf(
+        &mut self,
+        params: &SimulationParams,
+        materials: &GpuMaterials,
+    ) -> SolverStats {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+        let max_iterations = params.max_solver_iterations.max(1);
+        let min_iterations = params.min_solver_iterations.clamp(1, max_iterations);
+        let world_size = Vec2::new(params.width, params.height);
+
+        self.solver_pos.clear();
+        self.solver_pos
+            .extend_from_slice(&self.predicted_pos[..num_particles]);
+        self.lambda.clear();
+        self.lambda.resize(num_particles, 0.0);
+        self.position_delta.clear();
+        self.position_delta.resize(num_particles, Vec2::ZERO);
+
+        let mut stats = SolverStats::default();
+        for iteration in 1..=max_iterations {
+            let cells = &self.cells;
+            let lookups = &self.lookups;
+            let predicted_pos = &self.predicted_pos;
+            let solver_pos = &self.solver_pos;
+            let phases = &self.phase;
+
+            let error_sum: f32 = self
+                .lambda
+                .par_iter_mut()
+                .enumerate()
+                .map(|(i, lambda_ref)| {
+                    let material = materials.get(phases[i]);
+                    let mut density = 0.0;
+                    let mut grad_sum = Vec2::ZERO;
+                    let mut grad_square_sum = 0.0;
+                    // the grid was built from predicted_pos, where solver_pos started
+                    search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                        let mass_other = materials.get(phases[j]).mass;
+                        density +=
+                            calculate_density(solver_pos[i], solver_pos[j], mass_other, params);
+                        if i != j {
+                            let grad = calculate_pbf_gradient(
+                                solver_pos[i],
+                                solver_pos[j],
+                                mass_other,
+                                material.rest_density,
+                                params,
+                            );
+                            grad_sum += grad;
+                            grad_square_sum += grad.length_squared() / mass_other;
+                        }
+                    });
+                    *lambda_ref =
+                        calculate_pbf_lambda(density, grad_sum, grad_square_sum, material, params);
+                    (density / material.rest_density - 1.0).max(0.0)
+                })
+                .sum();
+
+            let lambdas = &self.lambda;
+            self.position_delta
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, delta_ref)| {
+                    let mut delta = Vec2::ZERO;
+                    search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                        if i != j {
+                            delta += calculate_pbf_displacement(
+                                solver_pos[i],
+                                solver_pos[j],
+                                lambdas[i],
+                                lambdas[j],
+                                materials.get(phases[i]),
+                                materials.get(phases[j]),
+                                params,
+                            );
+                        }
+                    });
+                    *delta_ref = delta;
+                });
+
+            for i in 0..num_particles {
+                self.solver_pos[i] = (self.solver_pos[i] + self.position_delta[i])
+                    .clamp(Vec2::splat(params.radius), world_size - params.radius);
+            }
+
+            stats.iterations = iteration;
+            if iteration >= min_iterations {
+                stats.density_error = error_sum / num_particles.max(1) as f32;
+                if stats.density_error <= params.max_density_error {
+                    break;
+                }
+            }
+        }
+
+        for i in 0..num_particles {
+            self.vel[i] = (self.solver_pos[i] - self.pos[i]) / params.dt;
+            self.pos[i] = self.solver_pos[i];
+        }
+        stats
+    }
+
+    /// Vorticity confinement for position based fluids: the vorticity of the
+    /// velocities `solve_pbf` left, then ε (N × ω) added back to them, see
+    /// pbf_vorticity and integrate_pbf in update.wgsl. Reuses the neighbour
+    /// grid built by `update`.
+    pub fn confine_vorticity(&mut self, params: &SimulationParams, materials: &GpuMaterials) {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+
+        let cells = &self.cells;
+        let lookups = &self.lookups;
+        let predicted_pos = &self.predicted_pos;
+        let positions = &self.pos;
+        let velocities = &self.vel;
+        let densities = &self.density;
+        let phases = &self.phase;
+
+        self.vorticity.clear();
+        self.vorticity.resize(num_particles, 0.0);
+        self.vorticity
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, vorticity_ref)| {
+                let mut vorticity = 0.0;
+                search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                    if i != j {
+                        vorticity += calculate_vorticity(
+                            positions[i],
+                            positions[j],
+                            velocities[i],
+                            velocities[j],
+                            params,
+                        );
+                    }
+                });
+                *vorticity_ref = vorticity;
             });
+
+        let vorticities = &self.vorticity;
+        let confinement: Vec<Vec2> = (0..num_particles)
+            .into_par_iter()
+            .map(|i| {
+                let mut vorticity_gradient = Vec2::ZERO;
+                search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                    if i != j {
+                        vorticity_gradient += calculate_vorticity_gradient(
+                            positions[i],
+                            positions[j],
+                            vorticities[i],
+                            vorticities[j],
+                            materials.get(phases[j]).mass / densities[j],
+                            params,
+                        );
+                    }
+                });
+                calculate_vorticity_confinement(vorticity_gradient, vorticities[i], params)
+            })
+            .collect();
+
+        for (vel, confinement) in self.vel.iter_mut().zip(confinement) {
+            *vel += confinement * params.dt;
+        }
+    }
+
+    // Double density relaxation (Clavet et al. 2005): density and near-density,
+    // then the Jacobi position corrections over dt² into force. Reuses the
+    // neighbour grid built by `update`.
+    fn relax(&mut self, params: &SimulationParams) {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+
+        let cells = &self.cells;
+        let lookups = &self.lookups;
+        let predicted_pos = &self.predicted_pos;
+
+        self.near_density.clear();
+        self.near_density.resize(num_particles, 0.0);
+        self.density[..num_particles]
+            .par_iter_mut()
+            .zip(self.pressure[..num_particles].par_iter_mut())
+            .zip(self.near_density.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, ((density_ref, pressure_ref), near_density_ref))| {
+                let mut current_density: f32 = 0.0;
+                let mut current_near_density: f32 = 0.0;
+                search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                    if i != j {
+                        let (density, near_density) = calculate_relaxation_density(
+                            predicted_pos[i],
+                            predicted_pos[j],
+                            params,
+                        );
+                        current_density += density;
+                        current_near_density += near_density;
+                    }
+                });
+                *density_ref = current_density;
+                *near_density_ref = current_near_density;
+                *pressure_ref = calculate_relaxation_pressure(current_density, params);
+            });
+
+        let pressures = &self.pressure;
+        let near_densities = &self.near_density;
+
+        self.force[..num_particles]
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, force_ref)| {
+                let mut displacement = Vec2::ZERO;
+                search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                    if i != j {
+                        displacement -= calculate_relaxation_displacement(
+                            predicted_pos[i],
+                            predicted_pos[j],
+                            pressures[i],
+                            pressures[j],
+                            params.ddr_near_stiffness * near_densities[i],
+                            params.ddr_near_stiffness * near_densities[j],
+                            params,
+                        );
+                    }
+                });
+                *force_ref = displacement;
+            });
+    }
+
+    /// XSPH: blends each velocity with its neighbours', vᵢ += ε Σ mⱼ (vⱼ - vᵢ) / ρ̄ᵢⱼ W.
+    /// Reuses the neighbour grid built by `update`, so it must run after it.
+    pub fn smooth_velocities(&mut self, params: &SimulationParams, materials: &GpuMaterials) {
+        let num_particles = (params.no_particles as usize).min(self.pos.len());
+
+        let cells = &self.cells;
+        let lookups = &self.lookups;
+        let predicted_pos = &self.predicted_pos;
+        let velocities = &self.vel;
+        let densities = &self.density;
+        let phases = &self.phase;
+
+        self.xsph_vel.clear();
+        self.xsph_vel.resize(num_particles, Vec2::ZERO);
+        self.xsph_vel
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, xsph_vel_ref)| {
+                let mut correction = Vec2::ZERO;
+                search::for_each_neighbour(predicted_pos[i], cells, lookups, params, |j| {
+                    if i != j {
+                        correction += calculate_xsph_correction(
+                            predicted_pos[i],
+                            predicted_pos[j],
+                            velocities[i],
+                            velocities[j],
+                            0.5 * (densities[i] + densities[j]),
+                            materials.get(phases[j]).mass,
+                            params,
+                        );
+                    }
+                });
+                *xsph_vel_ref = velocities[i] + params.xsph * correction;
+            });
+
+        self.vel[..num_particles].copy_from_slice(&self.xsph_vel);
+    }
+}
+
+impl CpuSimulation {
+    pub fn new(params: SimulationParams) -> Self {
+        Self {
+            particles: Particles::from_params(&params, &[]),
+            params,
+            colliders: Vec::new(),
+            bodies: Vec::new(),
+            initial_bodies: Vec::new(),
+            materials: Vec::new(),
+            time: 0.0,
+            solver_stats: None,
+            step_limits: None,
+        }
+    }
+}
+
+impl Simulation for CpuSimulation {
+    fn step(&mut self) {
+        let materials = GpuMaterials::new(&self.materials, &self.params);
+        self.solver_stats =
+            self.particles
+                .step(&self.params, &self.colliders, &self.bodies, &materials);
+        rigid_body::step(
+            &mut self.bodies,
+            self.particles.body_samples(&self.params),
+            &self.params,
+        );
+        self.step_limits = if self.params.cfl_number > 0.0 {
+            Some(self.particles.step_limits(&self.params))
+        } else {
+            None
+        };
+        self.time += self.params.dt as f64;
+    }
+
+    fn set_params(&mut self, params: SimulationParams) {
+        self.params = params;
+    }
+
+    fn params(&self) -> &SimulationParams {
+        &self.params
+    }
+
+    fn set_colliders(&mut self, colliders: Vec<Collider>) {
+        self.colliders = colliders;
+    }
+
+    fn colliders(&self) -> &[Collider] {
+        &self.colliders
+    }
+
+    fn set_bodies(&mut self, bodies: Vec<RigidBody>) {
+        self.initial_bodies = bodies.clone();
+        self.bodies = bodies;
+    }
+
+    fn bodies(&self) -> &[RigidBody] {
+        &self.bodies
+    }
+
+    fn set_materials(&mut self, materials: Vec<Material>) {
+        let count = self.particles.phase.len();
+        for (index, phase) in self.particles.phase.iter_mut().enumerate() {
+            *phase = material::phase(index, count, &materials);
+        }
+        self.materials = materials;
+    }
+
+    fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    fn read_particles(&self) -> Vec<GpuParticle> {
+        let particles = &self.particles;
+        (0..particles.pos.len())
+            .map(|i| GpuParticle {
+                pos: particles.pos[i].to_array(),
+                vel: particles.vel[i].to_array(),
+                force: particles.force[i].to_array(),
+                density: particles.density[i],
+                pressure: particles.pressure[i],
+                phase: particles.phase[i],
+                temperature: particles.temperature[i],
+                accel: particles.accel[i].to_array(),
+            })
+            .collect()
+    }
+
+    fn read_predicted_pos(&self) -> Vec<[f32; 2]> {
+        self.particles
+            .predicted_pos
+            .iter()
+            .map(|p| p.to_array())
+            .collect()
+    }
+
+    fn solver_stats(&self) -> Option<SolverStats> {
+        self.solver_stats
+    }
+
+    fn step_limits(&self) -> Option<StepLimits> {
+        self.step_limits
+    }
+
+    fn time(&self) -> f64 {
+        self.time
+    }
+
+    fn write_state(&mut self, particles: &[GpuParticle], predicted_pos: &[[f32; 2]], time: f64) {
+        self.particles = Particles::from_gpu_particles(particles, predicted_pos);
+        self.time = time;
+        self.step_limits = None;
+    }
+
+    fn reset(&mut self) {
+        self.particles = Particles::from_params(&self.params, &self.materials);
+        self.bodies = self.initial_bodies.clone();
+        self.time = 0.0;
+        self.solver_stats = None;
+        self.step_limits = None;
     }
 }
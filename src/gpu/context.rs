@@ -1,26 +1,22 @@
+use crate::collider::Collider;
 use crate::constants::SimulationParams;
-use std::num::NonZeroU32;
+use crate::material::Material;
+use crate::rigid_body::RigidBody;
+use crate::{Simulation, SolverStats, StepLimits};
 use std::sync::Arc;
-use wgpu::{self, util::DeviceExt};
-use wgpu_sort;
 use winit::window::Window;
 
 use super::particle::GpuParticle;
-use super::pipelines::Pipelines;
+use super::pipelines::ParticleRenderer;
+use super::simulation::GpuSimulation;
 
 pub struct GpuContext {
-    pub device: wgpu::Device,
-    pub queue: wgpu::Queue,
+    pub sim: GpuSimulation,
     pub surface: wgpu::Surface<'static>,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub config: wgpu::SurfaceConfiguration,
 
-    pub pipelines: Pipelines,
-    pub constants_buffer: wgpu::Buffer,
-    pub lookups_buffer: wgpu::Buffer,
-    pub predicted_pos_buffer: wgpu::Buffer,
-    pub sorter: wgpu_sort::GPUSorter,
-    pub sort_buffers: wgpu_sort::SortBuffers,
+    pub renderer: ParticleRenderer,
 
     pub egui_ctx: egui::Context,
     pub egui_state: egui_winit::State,
@@ -49,7 +45,6 @@ impl GpuContext {
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: Some("GPU Device"),
-                required_features: wgpu::Features::VERTEX_WRITABLE_STORAGE,
                 ..Default::default()
             })
             .await
@@ -76,44 +71,13 @@ impl GpuContext {
         };
         surface.configure(&device, &config);
 
-        let initial_particles = GpuParticle::spawn_particles(&params, config.width, config.height);
-        let particle_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&initial_particles),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
-        let initial_predicted_pos: Vec<[f32; 2]> =
-            initial_particles.iter().map(|p| p.pos).collect();
-        let predicted_pos_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Predicted Pos Buffer"),
-            contents: bytemuck::cast_slice(&initial_predicted_pos),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
-        let constants_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Constants Buffer"),
-            contents: bytemuck::cast_slice(&[params]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-        let sorter = wgpu_sort::GPUSorter::new(&device, 32);
-        let sort_buffers =
-            sorter.create_sort_buffers(&device, NonZeroU32::new(params.no_particles).unwrap());
-
-        let grid_width = (params.width / params.cell_size).floor() as u32;
-        let grid_height = (params.height / params.cell_size).floor() as u32;
-        let lookups_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Lookups Buffer"),
-            size: (grid_width * grid_height * 8) as wgpu::BufferAddress,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-        let pipelines = Pipelines::new(
-            &device,
+        let sim = GpuSimulation::new(device, queue, params);
+        let renderer = ParticleRenderer::new(
+            &sim.device,
             surface_format,
-            &particle_buffer,
-            &constants_buffer,
-            &sort_buffers,
-            &lookups_buffer,
-            &predicted_pos_buffer,
+            &sim.particle_buffer,
+            &sim.constants_buffer,
+            &sim.materials_buffer,
         );
 
         let egui_ctx = egui::Context::default();
@@ -126,22 +90,16 @@ impl GpuContext {
             None,
         );
         let egui_renderer = egui_wgpu::Renderer::new(
-            &device,
+            &sim.device,
             surface_format,
             egui_wgpu::RendererOptions::default(),
         );
         Self {
+            sim,
             surface,
-            device,
-            queue,
             size,
             config,
-            pipelines,
-            constants_buffer,
-            lookups_buffer,
-            predicted_pos_buffer,
-            sort_buffers,
-            sorter,
+            renderer,
             egui_ctx,
             egui_state,
             egui_renderer,
@@ -152,104 +110,15 @@ impl GpuContext {
         let _ = self.egui_state.on_window_event(window, event);
     }
 
-    pub fn update_params(&self, params: &SimulationParams) {
-        self.queue
-            .write_buffer(&self.constants_buffer, 0, bytemuck::cast_slice(&[*params]));
-    }
-
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            self.surface.configure(&self.sim.device, &self.config);
         }
     }
 
-    pub fn compute(&mut self, num_particles: u32) {
-        // Frame pipeline (each pass reads the previous one's output):
-        // hash    -> assign cell id to each particle
-        // sort    -> reorder particle_ids by cell_id (radix on GPU)
-        // clear   -> wipe lookups so empty cells don't keep stale ranges
-        // lookups -> build per-cell [start, end) ranges from sorted ids
-        // density -> per-particle density + pressure from neighbors
-        // forces  -> pressure + gravity, reads density/pressure
-        // physics -> integrate velocity/position, writes new predicted_pos
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Compute Encoder"),
-            });
-        let workgroup_count = (num_particles as f32 / 128.0).ceil() as u32;
-
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Assign Cells Pass"),
-                ..Default::default()
-            });
-            compute_pass.set_pipeline(&self.pipelines.hash);
-            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
-            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
-        }
-
-        self.sorter
-            .sort(&mut encoder, &self.queue, &self.sort_buffers, None);
-
-        // Zero stale (start, end) ranges from last frame. build_lookups only
-        // writes cells that contain a particle this frame, so cells that just
-        // emptied would otherwise keep last frame's range, pointing into
-        // the re-sorted particle_ids array at unrelated particles.
-        // The SPH kernels return 0 outside influence_radius, so most of those stale
-        // fetches contribute nothing. But occasionally a stale fetch hits a particle that is currently
-        // near the query point, double-counting it. The error is invisible
-        // visually, but to the extent of keeping the simulation as accurate as possible I think it is
-        // worth keeping it.
-        encoder.clear_buffer(&self.lookups_buffer, 0, None);
-
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Calculate Lookups Pass"),
-                ..Default::default()
-            });
-            compute_pass.set_pipeline(&self.pipelines.lookups);
-            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
-            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
-        }
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Density Compute Pass"),
-                ..Default::default()
-            });
-            compute_pass.set_pipeline(&self.pipelines.density);
-            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
-            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
-        }
-
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Forces Compute Pass"),
-                ..Default::default()
-            });
-            compute_pass.set_pipeline(&self.pipelines.forces);
-            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
-            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
-        }
-
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Physics Compute Pass"),
-                timestamp_writes: None,
-            });
-
-            compute_pass.set_pipeline(&self.pipelines.physics);
-            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
-            let workgroup_count = (num_particles as f32 / 128.0).ceil() as u32;
-            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
-        }
-
-        self.queue.submit(std::iter::once(encoder.finish()));
-    }
     pub fn render(
         &mut self,
         window: &Window,
@@ -275,6 +144,7 @@ impl GpuContext {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self
+            .sim
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
@@ -282,7 +152,7 @@ impl GpuContext {
 
         for (id, image_delta) in &full_output.textures_delta.set {
             self.egui_renderer
-                .update_texture(&self.device, &self.queue, *id, image_delta);
+                .update_texture(&self.sim.device, &self.sim.queue, *id, image_delta);
         }
         let font_tex_id = egui::TextureId::default();
         if self.egui_renderer.texture(&font_tex_id).is_none() {
@@ -293,8 +163,12 @@ impl GpuContext {
                     pos: None,
                     options: egui::epaint::textures::TextureOptions::LINEAR,
                 };
-                self.egui_renderer
-                    .update_texture(&self.device, &self.queue, font_tex_id, &delta);
+                self.egui_renderer.update_texture(
+                    &self.sim.device,
+                    &self.sim.queue,
+                    font_tex_id,
+                    &delta,
+                );
             });
         }
         let screen_descriptor = egui_wgpu::ScreenDescriptor {
@@ -302,8 +176,8 @@ impl GpuContext {
             pixels_per_point: window.scale_factor() as f32,
         };
         let egui_cmd_buffers = self.egui_renderer.update_buffers(
-            &self.device,
-            &self.queue,
+            &self.sim.device,
+            &self.sim.queue,
             &mut encoder,
             &clipped_primitives,
             &screen_descriptor,
@@ -333,8 +207,8 @@ impl GpuContext {
                     multiview_mask: None,
                 })
                 .forget_lifetime();
-            render_pass.set_pipeline(&self.pipelines.render);
-            render_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            render_pass.set_pipeline(&self.renderer.pipeline);
+            render_pass.set_bind_group(0, &self.renderer.bind_group, &[]);
             render_pass.draw(0..6, 0..num_particles);
         }
 
@@ -365,13 +239,82 @@ impl GpuContext {
             self.egui_renderer.free_texture(id);
         }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        // self.queue.submit(
-        //     egui_cmd_buffers
-        //         .into_iter()
-        //         .chain(std::iter::once(encoder.finish())),
-        // );
+        self.sim.queue.submit(
+            egui_cmd_buffers
+                .into_iter()
+                .chain(std::iter::once(encoder.finish())),
+        );
         output.present();
         Ok(())
     }
 }
+
+impl Simulation for GpuContext {
+    fn step(&mut self) {
+        self.sim.step();
+    }
+
+    fn step_many(&mut self, steps: u32) {
+        self.sim.step_many(steps);
+    }
+
+    fn set_params(&mut self, params: SimulationParams) {
+        self.sim.set_params(params);
+    }
+
+    fn params(&self) -> &SimulationParams {
+        self.sim.params()
+    }
+
+    fn set_colliders(&mut self, colliders: Vec<Collider>) {
+        self.sim.set_colliders(colliders);
+    }
+
+    fn colliders(&self) -> &[Collider] {
+        self.sim.colliders()
+    }
+
+    fn set_bodies(&mut self, bodies: Vec<RigidBody>) {
+        self.sim.set_bodies(bodies);
+    }
+
+    fn bodies(&self) -> &[RigidBody] {
+        self.sim.bodies()
+    }
+
+    fn set_materials(&mut self, materials: Vec<Material>) {
+        self.sim.set_materials(materials);
+    }
+
+    fn materials(&self) -> &[Material] {
+        self.sim.materials()
+    }
+
+    fn read_particles(&self) -> Vec<GpuParticle> {
+        self.sim.read_particles()
+    }
+
+    fn read_predicted_pos(&self) -> Vec<[f32; 2]> {
+        self.sim.read_predicted_pos()
+    }
+
+    fn solver_stats(&self) -> Option<SolverStats> {
+        self.sim.solver_stats()
+    }
+
+    fn step_limits(&self) -> Option<StepLimits> {
+        self.sim.step_limits()
+    }
+
+    fn time(&self) -> f64 {
+        self.sim.time()
+    }
+
+    fn write_state(&mut self, particles: &[GpuParticle], predicted_pos: &[[f32; 2]], time: f64) {
+        self.sim.write_state(particles, predicted_pos, time);
+    }
+
+    fn reset(&mut self) {
+        self.sim.reset();
+    }
+}
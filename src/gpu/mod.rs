@@ -1,3 +1,4 @@
 pub mod context;
 pub mod particle;
 pub mod pipelines;
+pub mod simulation;
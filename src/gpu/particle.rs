@@ -1,32 +1,286 @@
+use crate::collider::{Collider, MAX_COLLIDER_POINTS, MAX_COLLIDERS, Shape};
 use crate::constants::SimulationParams;
+use crate::material::{self, MAX_MATERIALS, Material};
+use crate::rigid_body::{BodyShape, MAX_BODIES, MAX_BODY_POINTS, RigidBody};
 use bytemuck::{Pod, Zeroable};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct GpuParticle {
-    pub pos: [f32; 2],   // 8 bytes
-    pub vel: [f32; 2],   // 8 bytes
-    pub force: [f32; 2], // 8 bytes
-    pub density: f32,    // 4 bytes
-    pub pressure: f32,   // 4 bytes
-                         // we have 32 bytes
-                         // from specs (https://www.w3.org/TR/WGSL/#alignment-and-size) alignment of a struct is defined as
-                         // AlignOf(S) = max(AlignOfMember(S,0), max(AlignOfMember(S,1), ... , AlignOfMember(S,N)) = 8 here
-                         // SizeOf(S) = roundUp(AlignOf(S), justPastLastMember) =
-                         // ceil(justPastLastMember / AlignOf(S)) * AlignOf(S)
-                         // where justPastLastMember = OffsetOfMember(S,N) + SizeOfMember(S,N)
-
-                         // justPastLastMember = 28 + 4 = 32
-                         // since pressure starts at the 28th byte and is 4 bytes
-                         // 32 is divisible by 8 so roundUp(8, 32) = ceil(32/8) * 8 = 32
-                         //
-                         // predicted_pos lives in its own SoA buffer (binding 5)
-                         // so the hot neighbor-fetch path only pulls 8 bytes per
-                         // neighbor instead of 48.
+    pub pos: [f32; 2],    // 8 bytes
+    pub vel: [f32; 2],    // 8 bytes
+    pub force: [f32; 2],  // 8 bytes
+    pub density: f32,     // 4 bytes
+    pub pressure: f32,    // 4 bytes
+    pub phase: u32,       // 4 bytes, index into the materials, see material.rs
+    pub temperature: f32, // 4 bytes
+    pub accel: [f32; 2],  // 8 bytes, acceleration of the last step, for leapfrog, Verlet and RK2
+                          // we have 48 bytes
+                          // from specs (https://www.w3.org/TR/WGSL/#alignment-and-size) alignment of a struct is defined as
+                          // AlignOf(S) = max(AlignOfMember(S,0), max(AlignOfMember(S,1), ... , AlignOfMember(S,N)) = 8 here
+                          // SizeOf(S) = roundUp(AlignOf(S), justPastLastMember) =
+                          // ceil(justPastLastMember / AlignOf(S)) * AlignOf(S)
+                          // where justPastLastMember = OffsetOfMember(S,N) + SizeOfMember(S,N)
+
+                          // justPastLastMember = 40 + 8 = 48
+                          // since accel starts at the 40th byte and is 8 bytes
+                          // 48 is divisible by 8 so roundUp(8, 48) = 48, no padding needed
+                          //
+                          // predicted_pos lives in its own SoA buffer (binding 5)
+                          // so the hot neighbor-fetch path only pulls 8 bytes per
+                          // neighbor instead of 48.
+}
+
+// Per-particle working values of the passes that only live for one step,
+// mirrored by the Scratch struct in common.wgsl. Packed into one buffer rather
+// than one buffer each because wgpu's default limits only allow 8 storage
+// buffers per shader stage. The CPU backend keeps these as plain Vecs.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuScratch {
+    pub xsph_vel: [f32; 2],       // 8 bytes, XSPH smoothed velocity
+    pub normal: [f32; 2],         // 8 bytes, colour-field normal for surface tension
+    pub solver_pos: [f32; 2],     // 8 bytes, PCISPH/PBF predicted position
+    pub pressure_accel: [f32; 2], // 8 bytes, PCISPH/DFSPH pressure acceleration
+    pub rk2_vel: [f32; 2],        // 8 bytes, RK2 velocity at the start of the step
+    pub near_density: f32,        // 4 bytes, double density relaxation
+    pub alpha: f32,               // 4 bytes, DFSPH factor
+    pub position_delta: [f32; 2], // 8 bytes, PBF position correction
+    pub lambda: f32,              // 4 bytes, PBF constraint multiplier
+    pub vorticity: f32,           // 4 bytes, PBF vorticity for confinement
+    pub heating: f32,             // 4 bytes, rate of change of the temperature
+    pub _padding: f32,            // 4 bytes
+                                  // 72 bytes, a multiple of the 8 byte vec2 alignment
+}
+
+// A wall or rigid body particle, mirrored by the BoundaryParticle struct in
+// common.wgsl. Built on the CPU by `BoundaryParticles`, the shaders only
+// write the force the fluid puts on the body particles.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuBoundaryParticle {
+    pub pos: [f32; 2],      // 8 bytes
+    pub volume: f32,        // 4 bytes, Akinci's 1/Σ W over the other wall particles
+    pub body: u32,          // 4 bytes, index into the bodies, rigid_body::NO_BODY for walls
+    pub force: [f32; 2],    // 8 bytes, the fluid's push on a body particle
+    pub _padding: [f32; 2], // 8 bytes, 32 in total
+}
+
+// One collider as the shaders read it, mirrored by the Collider struct in
+// common.wgsl. The shape's numbers are packed into a and b by GpuColliders::new.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuCollider {
+    pub shape: u32,       // 4 bytes, 0 circle, 1 box, 2 capsule, 3 polygon
+    pub operation: u32,   // 4 bytes, Operation discriminant
+    pub container: u32,   // 4 bytes, 1 inverts the shape
+    pub point_start: u32, // 4 bytes, polygons: first point in GpuColliders::points
+    pub point_count: u32, // 4 bytes
+    pub heat_source: u32, // 4 bytes, 1 when the collider has a temperature
+    pub temperature: f32, // 4 bytes
+    pub _padding: u32,    // 4 bytes, vec4 members are 16 byte aligned
+    pub a: [f32; 4],      // 16 bytes
+    pub b: [f32; 4],      // 16 bytes
+                          // 64 bytes, a multiple of 16 as uniform array elements must be
+}
+
+// Every collider of the scene in one uniform, mirrored by the Colliders struct
+// in common.wgsl. Fixed size, so the counts are capped by collider::validate.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuColliders {
+    pub count: u32,
+    pub _padding: [u32; 3],
+    pub colliders: [GpuCollider; MAX_COLLIDERS],
+    // two points per element, uniform arrays have a 16 byte stride
+    pub points: [[f32; 4]; MAX_COLLIDER_POINTS / 2],
+}
+
+impl GpuColliders {
+    // circle:  a = (centre, radius, 0)
+    // box:     a = (centre, half size), b = (angle, 0, 0, 0)
+    // capsule: a = (start, end), b = (radius, 0, 0, 0)
+    // polygon: points[point_start..point_start + point_count]
+    pub fn new(colliders: &[Collider]) -> Self {
+        let mut gpu = Self::zeroed();
+        let mut point_start = 0;
+        for (gpu_collider, collider) in gpu.colliders.iter_mut().zip(colliders) {
+            gpu_collider.operation = collider.operation as u32;
+            gpu_collider.container = collider.container as u32;
+            if let Some(temperature) = collider.temperature {
+                gpu_collider.heat_source = 1;
+                gpu_collider.temperature = temperature;
+            }
+            match &collider.shape {
+                Shape::Circle { center, radius } => {
+                    gpu_collider.shape = 0;
+                    gpu_collider.a = [center[0], center[1], *radius, 0.0];
+                }
+                Shape::Box {
+                    center,
+                    half_size,
+                    angle,
+                } => {
+                    gpu_collider.shape = 1;
+                    gpu_collider.a = [center[0], center[1], half_size[0], half_size[1]];
+                    gpu_collider.b = [*angle, 0.0, 0.0, 0.0];
+                }
+                Shape::Capsule { start, end, radius } => {
+                    gpu_collider.shape = 2;
+                    gpu_collider.a = [start[0], start[1], end[0], end[1]];
+                    gpu_collider.b = [*radius, 0.0, 0.0, 0.0];
+                }
+                Shape::Polygon { points } => {
+                    gpu_collider.shape = 3;
+                    let count = points.len().min(MAX_COLLIDER_POINTS - point_start);
+                    gpu_collider.point_start = point_start as u32;
+                    gpu_collider.point_count = count as u32;
+                    for (k, point) in points[..count].iter().enumerate() {
+                        let index = point_start + k;
+                        let packed = &mut gpu.points[index / 2];
+                        packed[(index % 2) * 2] = point[0];
+                        packed[(index % 2) * 2 + 1] = point[1];
+                    }
+                    point_start += count;
+                }
+            }
+        }
+        gpu.count = colliders.len().min(MAX_COLLIDERS) as u32;
+        gpu
+    }
+}
+
+// One rigid body as the shaders read it, mirrored by the Body struct in
+// common.wgsl. Polygon points are in the body's frame.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuBody {
+    pub shape: u32,       // 4 bytes, 0 circle, 1 box, 2 polygon
+    pub point_start: u32, // 4 bytes, polygons: first point in GpuBodies::points
+    pub point_count: u32, // 4 bytes
+    pub _padding: u32,    // 4 bytes
+    pub pos: [f32; 2],    // 8 bytes
+    pub vel: [f32; 2],    // 8 bytes
+    pub size: [f32; 2],   // 8 bytes, (radius, 0) or the box's half size
+    pub angle: f32,       // 4 bytes
+    pub angular_velocity: f32, // 4 bytes
+                          // 48 bytes, a multiple of 16 as uniform array elements must be
+}
+
+// Every rigid body's current pose in one uniform, mirrored by the Bodies
+// struct in common.wgsl. Rewritten every step, the counts are capped by
+// rigid_body::validate.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuBodies {
+    pub count: u32,
+    pub _padding: [u32; 3],
+    pub bodies: [GpuBody; MAX_BODIES],
+    // two points per element, like GpuColliders::points
+    pub points: [[f32; 4]; MAX_BODY_POINTS / 2],
+}
+
+impl GpuBodies {
+    pub fn new(bodies: &[RigidBody]) -> Self {
+        let mut gpu = Self::zeroed();
+        let mut point_start = 0;
+        for (gpu_body, body) in gpu.bodies.iter_mut().zip(bodies) {
+            gpu_body.pos = body.position;
+            gpu_body.vel = body.velocity;
+            gpu_body.angle = body.angle;
+            gpu_body.angular_velocity = body.angular_velocity;
+            match &body.shape {
+                BodyShape::Circle { radius } => {
+                    gpu_body.shape = 0;
+                    gpu_body.size = [*radius, 0.0];
+                }
+                BodyShape::Box { half_size } => {
+                    gpu_body.shape = 1;
+                    gpu_body.size = *half_size;
+                }
+                BodyShape::Polygon { points } => {
+                    gpu_body.shape = 2;
+                    let count = points.len().min(MAX_BODY_POINTS - point_start);
+                    gpu_body.point_start = point_start as u32;
+                    gpu_body.point_count = count as u32;
+                    for (k, point) in points[..count].iter().enumerate() {
+                        let index = point_start + k;
+                        let packed = &mut gpu.points[index / 2];
+                        packed[(index % 2) * 2] = point[0];
+                        packed[(index % 2) * 2 + 1] = point[1];
+                    }
+                    point_start += count;
+                }
+            }
+        }
+        gpu.count = bodies.len().min(MAX_BODIES) as u32;
+        gpu
+    }
+}
+
+// One fluid phase as the shaders read it, mirrored by the Material struct in
+// common.wgsl, with the parameters' values filled in by GpuMaterials::new.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuMaterial {
+    pub mass: f32,         // 4 bytes
+    pub rest_density: f32, // 4 bytes
+    pub viscosity: f32,    // 4 bytes
+    pub _padding: f32,     // 4 bytes, vec4 members are 16 byte aligned
+    pub colour: [f32; 4],  // 16 bytes, RGB and 1
+                           // 32 bytes, a multiple of 16 as uniform array elements must be
+}
+
+// Every phase in one uniform, mirrored by the Materials struct in common.wgsl.
+// Fixed size, so the count is capped by material::validate.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuMaterials {
+    pub count: u32,
+    pub _padding: [u32; 3],
+    pub materials: [GpuMaterial; MAX_MATERIALS],
+}
+
+impl GpuMaterials {
+    // Without materials there is one phase, the parameters' fluid. Double
+    // density relaxation has its own unitless densities and ignores all of
+    // this but the colour, see material::check_solver.
+    pub fn new(materials: &[Material], params: &SimulationParams) -> Self {
+        let mut gpu = Self::zeroed();
+        let default = [Material::default()];
+        let materials = if materials.is_empty() {
+            &default[..]
+        } else {
+            materials
+        };
+        for (gpu_material, material) in gpu.materials.iter_mut().zip(materials) {
+            let [r, g, b] = material.colour;
+            gpu_material.colour = [r, g, b, 1.0];
+            gpu_material.mass = material.mass(params);
+            gpu_material.rest_density = material.rest_density(params);
+            gpu_material.viscosity = material.viscosity(params);
+        }
+        gpu.count = materials.len().min(MAX_MATERIALS) as u32;
+        gpu
+    }
+
+    /// The material of `phase`, the last one for phases past the end like in
+    /// the shaders.
+    pub fn get(&self, phase: u32) -> &GpuMaterial {
+        &self.materials[phase.min(self.count.max(1) - 1) as usize]
+    }
 }
 
 impl GpuParticle {
-    pub fn spawn_particles(params: &SimulationParams, width: u32, height: u32) -> Vec<Self> {
+    /// A square block of `params.no_particles` particles in the middle of the
+    /// domain at the ambient temperature, split between `materials` row by
+    /// row from the top.
+    pub fn spawn_particles(
+        params: &SimulationParams,
+        materials: &[Material],
+        width: u32,
+        height: u32,
+    ) -> Vec<Self> {
         let cols = (params.no_particles as f32).sqrt().ceil() as u32;
         let spacing = 1.0;
         let start_x = width as f32 / 2.0 - (cols as f32 * spacing) / 2.0;
@@ -42,6 +296,9 @@ impl GpuParticle {
                 force: [0.0, 0.0],
                 density: 0.0,
                 pressure: 0.0,
+                phase: material::phase(i as usize, params.no_particles as usize, materials),
+                temperature: params.ambient_temperature,
+                accel: [0.0, 0.0],
             });
         }
         particles
@@ -18,116 +18,120 @@ pub struct Pipelines {
     pub hash: wgpu::ComputePipeline,
     pub lookups: wgpu::ComputePipeline,
     pub density: wgpu::ComputePipeline,
+    pub normals: wgpu::ComputePipeline,
     pub forces: wgpu::ComputePipeline,
+    pub body_forces: wgpu::ComputePipeline,
+    pub xsph: wgpu::ComputePipeline,
+    pub relax: wgpu::ComputePipeline,
+    pub pcisph_predict: wgpu::ComputePipeline,
+    pub pcisph_pressure: wgpu::ComputePipeline,
+    pub pcisph_force: wgpu::ComputePipeline,
+    pub dfsph_divergence: wgpu::ComputePipeline,
+    pub dfsph_density: wgpu::ComputePipeline,
+    pub dfsph_accel: wgpu::ComputePipeline,
+    pub dfsph_apply: wgpu::ComputePipeline,
+    pub pbf_lambda: wgpu::ComputePipeline,
+    pub pbf_displacement: wgpu::ComputePipeline,
+    pub pbf_apply: wgpu::ComputePipeline,
+    pub pbf_velocity: wgpu::ComputePipeline,
+    pub pbf_vorticity: wgpu::ComputePipeline,
+    pub rk2_predict: wgpu::ComputePipeline,
     pub physics: wgpu::ComputePipeline,
-    pub render: wgpu::RenderPipeline,
+    pub step_limits: wgpu::ComputePipeline,
     pub bind_group: wgpu::BindGroup,
 }
 
+// Kept apart from the compute pipelines so a headless GpuSimulation never
+// needs a surface format. It only reads the particles, so its binding is
+// read-only and the device doesn't need VERTEX_WRITABLE_STORAGE. The
+// materials give each particle its phase's colour.
+pub struct ParticleRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    pub bind_group: wgpu::BindGroup,
+}
+
+/// Every buffer the compute shaders bind. The binding numbers are fixed by
+/// the declarations at the top of search.wgsl and update.wgsl.
+pub struct ComputeBuffers<'a> {
+    pub particles: &'a wgpu::Buffer,
+    pub constants: &'a wgpu::Buffer,
+    pub sort_buffers: &'a wgpu_sort::SortBuffers,
+    pub lookups: &'a wgpu::Buffer,
+    pub predicted_pos: &'a wgpu::Buffer,
+    pub scratch: &'a wgpu::Buffer,
+    pub partial_sums: &'a wgpu::Buffer,
+    pub boundary: &'a wgpu::Buffer,
+    pub colliders: &'a wgpu::Buffer,
+    pub bodies: &'a wgpu::Buffer,
+    pub materials: &'a wgpu::Buffer,
+}
+
+impl ComputeBuffers<'_> {
+    // indexed by binding number
+    fn resources(&self) -> [wgpu::BindingResource<'_>; 12] {
+        [
+            self.particles.as_entire_binding(),
+            self.constants.as_entire_binding(),
+            self.sort_buffers.keys().as_entire_binding(),
+            self.sort_buffers.values().as_entire_binding(),
+            self.lookups.as_entire_binding(),
+            self.predicted_pos.as_entire_binding(),
+            self.scratch.as_entire_binding(),
+            self.partial_sums.as_entire_binding(),
+            self.boundary.as_entire_binding(),
+            self.colliders.as_entire_binding(),
+            self.bodies.as_entire_binding(),
+            self.materials.as_entire_binding(),
+        ]
+    }
+}
+
+const CONSTANTS_BINDING: u32 = 1;
+const COLLIDERS_BINDING: u32 = 9;
+const BODIES_BINDING: u32 = 10;
+const MATERIALS_BINDING: u32 = 11;
+
 impl Pipelines {
-    pub fn new(
-        device: &wgpu::Device,
-        surface_format: wgpu::TextureFormat,
-        particle_buffer: &wgpu::Buffer,
-        constants_buffer: &wgpu::Buffer,
-        sort_buffers: &wgpu_sort::SortBuffers,
-        lookups_buffer: &wgpu::Buffer,
-        predicted_pos_buffer: &wgpu::Buffer,
-    ) -> Pipelines {
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Compute Bind Group Layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+    pub fn new(device: &wgpu::Device, buffers: &ComputeBuffers) -> Pipelines {
+        let resources = buffers.resources();
+        // the constants, colliders, bodies and materials are uniforms,
+        // everything else is a read_write storage buffer
+        let layout_entries: Vec<_> = (0..resources.len() as u32)
+            .map(|binding| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: if binding == CONSTANTS_BINDING
+                        || binding == COLLIDERS_BINDING
+                        || binding == BODIES_BINDING
+                        || binding == MATERIALS_BINDING
+                    {
+                        wgpu::BufferBindingType::Uniform
+                    } else {
+                        wgpu::BufferBindingType::Storage { read_only: false }
                     },
-                    count: None,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
+                count: None,
+            })
+            .collect();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Compute Bind Group Layout"),
+            entries: &layout_entries,
         });
+        let bind_group_entries: Vec<_> = resources
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect();
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Compute Bind Group"),
             layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: particle_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: constants_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: sort_buffers.keys().as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: sort_buffers.values().as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: lookups_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: predicted_pos_buffer.as_entire_binding(),
-                },
-            ],
+            entries: &bind_group_entries,
         });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Pipeline Layout"),
@@ -137,7 +141,6 @@ impl Pipelines {
 
         let search_shader = make_shader(device, "search", SEARCH_WGSL);
         let update_shader = make_shader(device, "update", UPDATE_WGSL);
-        let render_shader = make_shader(device, "render", RENDER_WGSL);
 
         let hash = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("Hash Pipeline"),
@@ -163,6 +166,14 @@ impl Pipelines {
             cache: None,
             compilation_options: PipelineCompilationOptions::default(),
         });
+        let normals = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Normals Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("calculate_normals"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
         let forces = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("Forces Pipeline"),
             layout: Some(&pipeline_layout),
@@ -171,6 +182,134 @@ impl Pipelines {
             cache: None,
             compilation_options: PipelineCompilationOptions::default(),
         });
+        let body_forces = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Body Forces Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("calculate_body_forces"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let xsph = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("XSPH Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("apply_xsph"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let relax = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Relax Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("relax_positions"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pcisph_predict = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PCISPH Predict Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pcisph_predict"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pcisph_pressure = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PCISPH Pressure Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pcisph_correct_pressure"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pcisph_force = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PCISPH Force Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pcisph_pressure_force"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let dfsph_divergence = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("DFSPH Divergence Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("dfsph_divergence"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let dfsph_density = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("DFSPH Density Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("dfsph_density"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let dfsph_accel = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("DFSPH Pressure Acceleration Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("dfsph_pressure_accel"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let dfsph_apply = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("DFSPH Apply Divergence Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("dfsph_apply_divergence"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pbf_lambda = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PBF Lambda Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pbf_lambda"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pbf_displacement = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PBF Displacement Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pbf_displacement"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pbf_apply = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PBF Apply Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pbf_apply"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pbf_velocity = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PBF Velocity Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pbf_velocity"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let pbf_vorticity = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PBF Vorticity Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("pbf_vorticity"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+        let rk2_predict = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("RK2 Predict Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("rk2_predict"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
         let physics = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("Physics Pipeline"),
             layout: Some(&pipeline_layout),
@@ -179,8 +318,114 @@ impl Pipelines {
             cache: None,
             compilation_options: PipelineCompilationOptions::default(),
         });
+        let step_limits = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Step Limits Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &update_shader,
+            entry_point: Some("reduce_step_limits"),
+            cache: None,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+
+        Pipelines {
+            hash,
+            lookups,
+            density,
+            normals,
+            forces,
+            body_forces,
+            xsph,
+            relax,
+            pcisph_predict,
+            pcisph_pressure,
+            pcisph_force,
+            dfsph_divergence,
+            dfsph_density,
+            dfsph_accel,
+            dfsph_apply,
+            pbf_lambda,
+            pbf_displacement,
+            pbf_apply,
+            pbf_velocity,
+            pbf_vorticity,
+            rk2_predict,
+            physics,
+            step_limits,
+            bind_group,
+        }
+    }
+}
+
+impl ParticleRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        particle_buffer: &wgpu::Buffer,
+        constants_buffer: &wgpu::Buffer,
+        materials_buffer: &wgpu::Buffer,
+    ) -> ParticleRenderer {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Render Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Render Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: constants_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: materials_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
 
-        let render = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let render_shader = make_shader(device, "render", RENDER_WGSL);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
@@ -214,13 +459,8 @@ impl Pipelines {
             cache: None,
         });
 
-        Pipelines {
-            hash,
-            lookups,
-            density,
-            forces,
-            physics,
-            render,
+        ParticleRenderer {
+            pipeline,
             bind_group,
         }
     }
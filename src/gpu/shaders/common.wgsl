@@ -18,7 +18,40 @@ struct Constants {
     mouse_pos: vec2<f32>,
     mouse_strength: f32,
     mouse_influence_radius: f32,
-    _padding: vec2<f32>,
+    viscosity: f32,
+    equation_of_state: u32,
+    gamma: f32,
+    xsph: f32,
+    surface_tension: f32,
+    solver: u32,
+    ddr_stiffness: f32,
+    ddr_near_stiffness: f32,
+    ddr_rest_density: f32,
+    max_density_error: f32,
+    min_solver_iterations: u32,
+    max_solver_iterations: u32,
+    max_divergence_error: f32,
+    pbf_relaxation: f32,
+    artificial_pressure: f32,
+    vorticity_confinement: f32,
+    boundary: u32,
+    collider_restitution: f32,
+    collider_friction: f32,
+    thermal_diffusivity: f32,
+    thermal_expansion: f32,
+    ambient_temperature: f32,
+    heat_transfer: f32,
+    cfl_number: f32,
+    cfl_force_factor: f32,
+    min_dt: f32,
+    physics_rate: f32,
+    max_substeps: u32,
+    integrator: u32,
+    kernel: u32,
+    cohesion_density: f32,
+    pcisph_relaxation: f32,
+    dfsph_relaxation: f32,
+    _padding: f32,
 }
 
 struct Particle {
@@ -27,6 +60,100 @@ struct Particle {
     force: vec2<f32>,
     density: f32,
     pressure: f32,
+    phase: u32,
+    temperature: f32,
+    accel: vec2<f32>,
+}
+
+// GpuScratch in gpu/particle.rs, one per particle, only valid within a step
+struct Scratch {
+    xsph_vel: vec2<f32>,
+    normal: vec2<f32>,
+    solver_pos: vec2<f32>,
+    pressure_accel: vec2<f32>,
+    rk2_vel: vec2<f32>,
+    near_density: f32,
+    alpha: f32,
+    position_delta: vec2<f32>,
+    lambda: f32,
+    vorticity: f32,
+    heating: f32,
+    _padding: f32,
+}
+
+// GpuBoundaryParticle in gpu/particle.rs, the wall and rigid body particles
+struct BoundaryParticle {
+    pos: vec2<f32>,
+    volume: f32,
+    body: u32,
+    force: vec2<f32>,
+    _padding: vec2<f32>,
+}
+
+// GpuCollider in gpu/particle.rs, see GpuColliders::new for what each shape keeps in a and b
+struct Collider {
+    shape: u32,
+    operation: u32,
+    container: u32,
+    point_start: u32,
+    point_count: u32,
+    heat_source: u32,
+    temperature: f32,
+    _padding: u32,
+    a: vec4<f32>,
+    b: vec4<f32>,
+}
+
+// GpuColliders in gpu/particle.rs, polygon points are packed two per vec4
+// because uniform array elements are 16 byte aligned
+struct Colliders {
+    count: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+    colliders: array<Collider, 16>,
+    points: array<vec4<f32>, 64>,
+}
+
+// GpuBody in gpu/particle.rs
+struct Body {
+    shape: u32,
+    point_start: u32,
+    point_count: u32,
+    _padding: u32,
+    pos: vec2<f32>,
+    vel: vec2<f32>,
+    size: vec2<f32>,
+    angle: f32,
+    angular_velocity: f32,
+}
+
+// GpuBodies in gpu/particle.rs, polygon points packed like Colliders::points
+struct Bodies {
+    count: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+    bodies: array<Body, 16>,
+    points: array<vec4<f32>, 32>,
+}
+
+// GpuMaterial in gpu/particle.rs, the parameters' values already filled in
+struct Material {
+    mass: f32,
+    rest_density: f32,
+    viscosity: f32,
+    _padding: f32,
+    colour: vec4<f32>,
+}
+
+// GpuMaterials in gpu/particle.rs, there is always at least one
+struct Materials {
+    count: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+    materials: array<Material, 8>,
 }
 
 struct Lookup {
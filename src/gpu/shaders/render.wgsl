@@ -1,12 +1,16 @@
 @group(0) @binding(0)
-var<storage, read_write> particles: array<Particle>;
+var<storage, read> particles: array<Particle>;
 
 @group(0) @binding(1) 
 var<uniform> constants: Constants;
 
+@group(0) @binding(2)
+var<uniform> materials: Materials;
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) local_pos: vec2<f32>, 
+    @location(1) colour: vec4<f32>,
 }
 
 @vertex
@@ -33,6 +37,8 @@ fn vs_main(
     var out: VertexOutput;
     out.clip_position = vec4<f32>(final_clip_pos, 0.0, 1.0);
     out.local_pos = quad_pos; // Pass local -1 to +1 coordinate to fragment shader
+    // the phase's colour, phases past the end take the last material's like in update.wgsl
+    out.colour = materials.materials[min(particle.phase, materials.count - 1u)].colour;
     return out;
 }
 
@@ -42,7 +48,6 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     if dot(in.local_pos, in.local_pos) > 1.0 {
         discard; 
     }
-    
-    // Blue!
-    return vec4<f32>(0.1, 0.5, 1.0, 1.0);
+
+    return in.colour;
 }
@@ -1,6 +1,53 @@
 const PI = 3.141592;
 const NEIGHBOUR_CELL_COUNT: u32 = 9;
 
+// EquationOfState discriminants in constants.rs, 0 (linear) is the default case
+const EOS_CLAMPED_LINEAR: u32 = 1u;
+const EOS_TAIT: u32 = 2u;
+
+// Solver discriminants in constants.rs, 0 (SPH forces) is the default case
+const SOLVER_SPH: u32 = 0u;
+const SOLVER_DOUBLE_DENSITY: u32 = 1u;
+const SOLVER_PCISPH: u32 = 2u;
+const SOLVER_DFSPH: u32 = 3u;
+const SOLVER_PBF: u32 = 4u;
+
+// Boundary discriminant in constants.rs, 0 (clamp) is the default case
+const BOUNDARY_PARTICLES: u32 = 1u;
+
+// Integrator discriminants in constants.rs, 0 (trapezoidal) is the default case
+const INTEGRATOR_SYMPLECTIC_EULER: u32 = 1u;
+const INTEGRATOR_LEAPFROG: u32 = 2u;
+const INTEGRATOR_VELOCITY_VERLET: u32 = 3u;
+const INTEGRATOR_RK2: u32 = 4u;
+
+// Kernel discriminants in constants.rs, 0 (poly6 / spiky) is the default case
+const KERNEL_MULLER: u32 = 0u;
+const KERNEL_CUBIC_SPLINE: u32 = 1u;
+const KERNEL_WENDLAND_C2: u32 = 2u;
+const KERNEL_WENDLAND_C4: u32 = 3u;
+const KERNEL_QUINTIC: u32 = 4u;
+
+// GpuCollider::shape and Operation discriminants, see gpu/particle.rs and collider.rs
+const SHAPE_CIRCLE: u32 = 0u;
+const SHAPE_BOX: u32 = 1u;
+const SHAPE_CAPSULE: u32 = 2u;
+const SHAPE_POLYGON: u32 = 3u;
+const OPERATION_SUBTRACT: u32 = 1u;
+// central difference step for the SDF gradient, see collider.rs
+const COLLIDER_GRADIENT_STEP = 0.1;
+const FLOAT_MAX = 3.40282347e38;
+
+// GpuBody::shape discriminants, see gpu/particle.rs
+const BODY_BOX: u32 = 1u;
+const BODY_POLYGON: u32 = 2u;
+
+const WORKGROUP_SIZE: u32 = 128u;
+
+// Macklin and Müller's artificial pressure Δq/h and n, see cpu/physics.rs
+const ARTIFICIAL_PRESSURE_RADIUS = 0.2;
+const ARTIFICIAL_PRESSURE_EXPONENT = 4.0;
+
 fn pcg_hash(seed: u32) -> u32 {
     var state = seed * 747796405u + 2891336453u;
     var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
@@ -29,16 +76,138 @@ var<storage, read_write> lookups: array<Lookup>;
 @group(0) @binding(5)
 var<storage, read_write> predicted_pos: array<vec2<f32>>;
 
+@group(0) @binding(6)
+var<storage, read_write> scratch: array<Scratch>;
+
+// one sum per workgroup, finished on the CPU, see GpuSimulation::correct_pressure
+@group(0) @binding(7)
+var<storage, read_write> partial_sums: array<f32>;
+
+// the static wall particles, sorted by cell like particle_ids. Their ranges
+// sit in lookups after the fluid's, see boundary_lookup_offset
+@group(0) @binding(8)
+var<storage, read_write> boundary: array<BoundaryParticle>;
+
+// obstacles and containers, see collider.rs
+@group(0) @binding(9)
+var<uniform> colliders: Colliders;
+
+// the rigid bodies where they are this step, see rigid_body.rs
+@group(0) @binding(10)
+var<uniform> bodies: Bodies;
+
+// the fluid phases, indexed by Particle::phase, see material.rs
+@group(0) @binding(11)
+var<uniform> materials: Materials;
+
 var<private> rand_state: u32;
 
+// σ of the selected kernel in 2D, mirrors normalisation in cpu/kernels.rs
+// where the 3D constants are listed too
+fn kernel_normalisation() -> f32 {
+    let h2 = constants.influence_radius * constants.influence_radius;
+    switch constants.kernel {
+        case KERNEL_CUBIC_SPLINE: {
+            return 40.0 / (7.0 * PI * h2);
+        }
+        case KERNEL_WENDLAND_C2: {
+            return 7.0 / (PI * h2);
+        }
+        case KERNEL_WENDLAND_C4: {
+            return 9.0 / (PI * h2);
+        }
+        case KERNEL_QUINTIC: {
+            return 63.0 / (478.0 * PI * h2);
+        }
+        default: {
+            return 4.0 / (PI * h2);
+        }
+    }
+}
+
+// f(q) and df/dq of the selected kernel, mirrors shape in cpu/kernels.rs
+fn kernel_shape(q: f32) -> vec2<f32> {
+    if q < 0.0 || q > 1.0 {
+        return vec2(0.0, 0.0);
+    }
+    switch constants.kernel {
+        case KERNEL_CUBIC_SPLINE: {
+            if 2.0 * q <= 1.0 {
+                return vec2(6.0 * (q * q * q - q * q) + 1.0, 6.0 * q * (3.0 * q - 2.0));
+            }
+            let t = 1.0 - q;
+            return vec2(2.0 * t * t * t, -6.0 * t * t);
+        }
+        case KERNEL_WENDLAND_C2: {
+            let t = 1.0 - q;
+            let t3 = t * t * t;
+            return vec2(t3 * t * (1.0 + 4.0 * q), -20.0 * q * t3);
+        }
+        case KERNEL_WENDLAND_C4: {
+            let t = 1.0 - q;
+            let t5 = t * t * t * t * t;
+            return vec2(
+                t5 * t * (1.0 + 6.0 * q + 35.0 / 3.0 * q * q),
+                -56.0 / 3.0 * q * t5 * (1.0 + 5.0 * q),
+            );
+        }
+        case KERNEL_QUINTIC: {
+            // each bracket only counts while it is positive
+            let s = 3.0 * q;
+            let t = max(vec3(3.0, 2.0, 1.0) - s, vec3(0.0));
+            let weights = vec3(1.0, -6.0, 15.0);
+            let t4 = t * t * t * t;
+            return vec2(dot(weights, t4 * t), -15.0 * dot(weights, t4));
+        }
+        default: {
+            let t = 1.0 - q * q;
+            return vec2(t * t * t, -6.0 * q * t * t);
+        }
+    }
+}
+
+// the selected kernel W, mirrors smoothing_kernel in cpu/kernels.rs
+fn smoothing_kernel(pos: vec2<f32>, pos_other: vec2<f32>) -> f32 {
+    if constants.kernel == KERNEL_MULLER {
+        return poly_kernel(pos, pos_other);
+    }
+    let q = length(pos - pos_other) / constants.influence_radius;
+    return kernel_normalisation() * kernel_shape(q).x;
+}
+
+// ∇W of the selected kernel, mirrors smoothing_kernel_gradient in cpu/kernels.rs
+fn smoothing_kernel_gradient(pos: vec2<f32>, pos_other: vec2<f32>) -> vec2<f32> {
+    if constants.kernel == KERNEL_MULLER {
+        return poly_kernel_gradient(pos, pos_other);
+    }
+    let delta = pos - pos_other;
+    let r = length(delta);
+    // every one of them is flat at the centre, so there is no direction to lose
+    if r < 0.00001 {
+        return vec2(0.0, 0.0);
+    }
+    let h = constants.influence_radius;
+    return kernel_normalisation() * kernel_shape(r / h).y / h * delta / r;
+}
+
+// spiky for the Müller pair, the selected kernel's ∇W otherwise, mirrors
+// pressure_kernel_gradient in cpu/kernels.rs
+fn pressure_kernel_gradient(pos: vec2<f32>, pos_other: vec2<f32>) -> vec2<f32> {
+    if constants.kernel == KERNEL_MULLER {
+        return spiky_kernel_gradient(pos, pos_other);
+    }
+    return smoothing_kernel_gradient(pos, pos_other);
+}
+
 fn spiky_kernel_gradient(pos: vec2<f32>, pos_other: vec2<f32>) -> vec2<f32> {
     // Used for pressure force calculations
-    // (-45/(pi*h⁶)) * (h-r)² * r̂ if 0<=r<=h
+    // (-10/(πh⁵)) * (h-r)² * r̂ if 0<=r<=h
     // 0 if h<r
+    // a third of the normalised 2D spiky gradient -30/(πh⁵), see cpu/kernels.rs
 
     let delta = pos - pos_other;
     let r = dot(delta, delta); // magnitude of the vector pointing at particle i
-    let norm_coeff = -10.0 / (PI * pow(constants.influence_radius, 5)); // -45.0 / (PI * INFLUENCE_RADIUS.powi(6)) for 3D
+    let norm_coeff = -10.0 / (PI * pow(constants.influence_radius, 5));
 
     if r < 0.00001 * 0.00001 {
         // particles can be in the same position in which case send them in random direction
@@ -59,7 +228,7 @@ fn spiky_kernel_gradient(pos: vec2<f32>, pos_other: vec2<f32>) -> vec2<f32> {
 
 fn poly_kernel(pos: vec2<f32>, pos_other: vec2<f32>) -> f32 {
     // used for density
-    //(315 / (64πh⁹)) * (h² - r²)³  if r <= h
+    // (4/(πh⁸)) * (h² - r²)³  if r <= h
     // 0 if r>h
     let delta = pos - pos_other;
     let r = dot(delta, delta); // magnitude of the vector pointing at particle i
@@ -71,6 +240,52 @@ fn poly_kernel(pos: vec2<f32>, pos_other: vec2<f32>) -> f32 {
     }
 }
 
+fn viscosity_kernel_laplacian(pos: vec2<f32>, pos_other: vec2<f32>) -> f32 {
+    // used for viscosity
+    // (40/(πh⁵)) * (h - r) if r <= h
+    // 0 if r>h
+    let r = length(pos - pos_other);
+    let norm_coeff = 40.0 / (PI * pow(constants.influence_radius, 5)); // 45.0 / (PI * INFLUENCE_RADIUS.powi(6)) for 3D
+    if r <= constants.influence_radius {
+        return norm_coeff * (constants.influence_radius - r);
+    } else {
+        return 0.0;
+    }
+}
+
+fn poly_kernel_gradient(pos: vec2<f32>, pos_other: vec2<f32>) -> vec2<f32> {
+    // used for the colour-field normals in surface tension
+    // (-24/(πh⁸)) * (h² - r²)² * r⃗ if r <= h
+    // 0 if r>h
+    let delta = pos - pos_other;
+    let r = dot(delta, delta);
+    let norm_coeff = -24.0 / (PI * pow(constants.influence_radius, 8)); // -945.0 / (32.0 * PI * INFLUENCE_RADIUS.powi(9)) for 3D
+    if r <= constants.influence_radius * constants.influence_radius {
+        return norm_coeff * pow(pow(constants.influence_radius, 2) - r, 2) * delta;
+    } else {
+        return vec2(0.0, 0.0);
+    }
+}
+
+fn cohesion_kernel(r: f32) -> f32 {
+    // used for surface tension, from Akinci et al. 2013
+    // C * (h-r)³r³ if h/2 < r <= h
+    // C * (2(h-r)³r³ - h⁶/64) if 0 < r <= h/2
+    // 0 otherwise
+    // Akinci's 3D constant is 32/(πh⁹), 40/(πh⁸) gives the same integral over the support in 2D
+    let h = constants.influence_radius;
+    let norm_coeff = 40.0 / (PI * pow(h, 8));
+    if r <= 0.0 || r > h {
+        return 0.0;
+    }
+    let spline = pow(h - r, 3) * pow(r, 3);
+    if 2.0 * r > h {
+        return norm_coeff * spline;
+    } else {
+        return norm_coeff * (2.0 * spline - pow(h, 6) / 64.0);
+    }
+}
+
 @compute @workgroup_size(128)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let index = global_id.x;
@@ -78,7 +293,13 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    integrate(index);
+    if constants.solver == SOLVER_DOUBLE_DENSITY {
+        integrate_relaxed(index);
+    } else if constants.solver == SOLVER_PBF {
+        integrate_pbf(index);
+    } else {
+        integrate(index);
+    }
 }
 
 fn neighbours() -> array<vec2<i32>, NEIGHBOUR_CELL_COUNT> {
@@ -102,12 +323,57 @@ fn hash(grid_coord: vec2<u32>) -> u32 {
     return grid_coord.y * cells_per_row + grid_coord.x;
 }
 
-fn calculate_density(pos: vec2<f32>, pos_other: vec2<f32>) -> f32 {
-    return constants.mass * poly_kernel(pos, pos_other);
+fn relaxation_q(pos: vec2<f32>, pos_other: vec2<f32>) -> f32 {
+    // r/h clamped to 1, so (1 - q) vanishes outside the influence radius
+    return min(length(pos - pos_other) / constants.influence_radius, 1.0);
+}
+
+fn material(index: u32) -> Material {
+    // mirrors GpuMaterials::get, phases past the end take the last material
+    return materials.materials[min(particles[index].phase, materials.count - 1u)];
+}
+
+fn calculate_density(pos: vec2<f32>, pos_other: vec2<f32>, mass_other: f32) -> f32 {
+    return mass_other * smoothing_kernel(pos, pos_other);
+}
+
+fn iterative_pressure() -> bool {
+    // mirrors Solver::iterates_pressure in constants.rs
+    return constants.solver == SOLVER_PCISPH || constants.solver == SOLVER_DFSPH;
+}
+
+fn active_integrator() -> u32 {
+    // mirrors SimulationParams::active_integrator in constants.rs
+    if constants.solver == SOLVER_SPH {
+        return constants.integrator;
+    }
+    return INTEGRATOR_SYMPLECTIC_EULER;
+}
+
+fn dfsph_alpha(grad_sum: vec2<f32>, grad_square_sum: f32, density: f32) -> f32 {
+    // mirrors calculate_dfsph_alpha in cpu/physics.rs
+    let denominator = dot(grad_sum, grad_sum) + grad_square_sum;
+    if denominator <= 0.0 {
+        return 0.0;
+    }
+    return density / denominator;
 }
 
-fn calculate_pressure(density: f32) -> f32 {
-    return constants.gas_constant * (density - constants.rest_density);
+fn calculate_pressure(density: f32, rest_density: f32) -> f32 {
+    // mirrors calculate_pressure in cpu/physics.rs
+    switch constants.equation_of_state {
+        case EOS_CLAMPED_LINEAR: {
+            return max(constants.gas_constant * (density - rest_density), 0.0);
+        }
+        case EOS_TAIT: {
+            // Tait equation: Non-linear response (stiff)
+            let b = constants.gas_constant * rest_density / constants.gamma;
+            return b * (pow(density / rest_density, constants.gamma) - 1.0);
+        }
+        default: {
+            return constants.gas_constant * (density - rest_density);
+        }
+    }
 }
 
 @compute @workgroup_size(128)
@@ -116,7 +382,11 @@ fn calculate_pressure_density(@builtin(global_invocation_id) global_id: vec3<u32
     if index >= constants.no_particles {
         return;
     }
+    rand_state = pcg_hash(index);
     particles[index].density = 0.0;
+    var my_near_density = 0.0;
+    var grad_sum = vec2<f32>(0.0, 0.0);
+    var grad_square_sum = 0.0;
     let grid_width = floor(constants.width / constants.cell_size);
     let grid_height = floor(constants.height / constants.cell_size);
 
@@ -136,14 +406,57 @@ fn calculate_pressure_density(@builtin(global_invocation_id) global_id: vec3<u32
             let end_index = lookups[cell_key].end_index;
             for (var j: u32 = start_index; j < end_index; j += 1u) {
                 let particle_idx = particle_ids[j];
-                particles[index].density += calculate_density(
-                    my_predicted_pos,
-                    predicted_pos[particle_idx]
-                );
+                if constants.solver == SOLVER_DOUBLE_DENSITY {
+                    if index == particle_idx {
+                        continue;
+                    }
+                    let q = relaxation_q(my_predicted_pos, predicted_pos[particle_idx]);
+                    particles[index].density += pow(1.0 - q, 2);
+                    my_near_density += pow(1.0 - q, 3);
+                } else {
+                    particles[index].density += calculate_density(
+                        my_predicted_pos,
+                        predicted_pos[particle_idx],
+                        material(particle_idx).mass
+                    );
+                    if constants.solver == SOLVER_DFSPH && index != particle_idx {
+                        let grad = material(particle_idx).mass
+                            * pressure_kernel_gradient(my_predicted_pos, predicted_pos[particle_idx]);
+                        grad_sum += grad;
+                        grad_square_sum += dot(grad, grad);
+                    }
+                }
             }
         }
     }
-    particles[index].pressure = calculate_pressure(particles[index].density);
+    if boundary_particles() {
+        particles[index].density += boundary_density(
+            my_predicted_pos,
+            material(index).rest_density
+        );
+    }
+    if constants.solver == SOLVER_DOUBLE_DENSITY {
+        // mirrors calculate_relaxation_pressure in cpu/physics.rs
+        particles[index].pressure = constants.ddr_stiffness
+            * (particles[index].density - constants.ddr_rest_density);
+        scratch[index].near_density = my_near_density;
+    } else if iterative_pressure() {
+        // pressure starts from zero every step and is built up by
+        // pcisph_correct_pressure or the DFSPH solves
+        particles[index].pressure = 0.0;
+        scratch[index].pressure_accel = vec2<f32>(0.0, 0.0);
+        scratch[index].alpha = dfsph_alpha(grad_sum, grad_square_sum, particles[index].density);
+    } else if constants.solver == SOLVER_PBF {
+        // position based fluids have no pressure, the constraint solve
+        // starts from the predicted positions
+        particles[index].pressure = 0.0;
+        scratch[index].solver_pos = my_predicted_pos;
+    } else {
+        particles[index].pressure = calculate_pressure(
+            particles[index].density,
+            material(index).rest_density
+        );
+    }
 }
 fn calculate_pressure_vector(
     pos: vec2<f32>,
@@ -151,10 +464,182 @@ fn calculate_pressure_vector(
     pressure: f32,
     pressure_other: f32,
     density_other: f32,
+    mass_other: f32,
+) -> vec2<f32> {
+
+    let grad_pressure = pressure_kernel_gradient(pos, pos_other);
+    return mass_other * ((pressure + pressure_other) / (2.0 * density_other)) * grad_pressure;
+}
+
+fn boundary_particles() -> bool {
+    // mirrors SimulationParams::boundary_particles in constants.rs
+    return constants.boundary == BOUNDARY_PARTICLES && constants.solver == SOLVER_SPH;
+}
+
+fn boundary_lookup_offset() -> u32 {
+    // one fluid range per cell comes first, see GpuSimulation::write_boundary
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+    return u32(grid_width * grid_height);
+}
+
+fn boundary_density(pos: vec2<f32>, rest_density: f32) -> f32 {
+    // Σ ρ₀V W over the wall particles around pos, mirrors
+    // calculate_boundary_density in cpu/physics.rs
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+    let lookup_offset = boundary_lookup_offset();
+
+    var density = 0.0;
+    let grid_coord = grid_coord(pos);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = lookup_offset + hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var k: u32 = start_index; k < end_index; k += 1u) {
+                density += rest_density * boundary[k].volume
+                    * smoothing_kernel(pos, boundary[k].pos);
+            }
+        }
+    }
+    return density;
+}
+
+fn boundary_pressure_force(
+    pos: vec2<f32>,
+    pressure: f32,
+    density: f32,
+    rest_density: f32,
+) -> vec2<f32> {
+    // Σ ρ₀V p/ρ ∇W over the wall particles around pos, mirrors
+    // calculate_boundary_pressure_force in cpu/physics.rs. Walls only push.
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+    let lookup_offset = boundary_lookup_offset();
+
+    var force = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(pos);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = lookup_offset + hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var k: u32 = start_index; k < end_index; k += 1u) {
+                force += rest_density * boundary[k].volume
+                    * (max(pressure, 0.0) / density)
+                    * pressure_kernel_gradient(pos, boundary[k].pos);
+            }
+        }
+    }
+    return force;
+}
+
+fn calculate_viscosity_vector(
+    pos: vec2<f32>,
+    pos_other: vec2<f32>,
+    vel: vec2<f32>,
+    vel_other: vec2<f32>,
+    volume_other: f32,
+    viscosity: f32,
+) -> vec2<f32> {
+    // pulls the velocity towards the neighbours' velocities, μ * Vⱼ * (vⱼ - vᵢ) * ∇²W
+    // with Vⱼ = mⱼ/ρⱼ and μ the mean of both phases' viscosities
+    let laplacian = viscosity_kernel_laplacian(pos, pos_other);
+    return viscosity * volume_other * (vel_other - vel) * laplacian;
+}
+
+fn calculate_heat_diffusion(
+    pos: vec2<f32>,
+    pos_other: vec2<f32>,
+    temperature: f32,
+    temperature_other: f32,
+    volume_other: f32,
+) -> f32 {
+    // mirrors calculate_heat_diffusion in cpu/physics.rs, α * Vⱼ * (Tⱼ - Tᵢ) * ∇²W
+    let laplacian = viscosity_kernel_laplacian(pos, pos_other);
+    return constants.thermal_diffusivity * volume_other * (temperature_other - temperature) * laplacian;
+}
+
+fn calculate_buoyancy_vector(density: f32, temperature: f32) -> vec2<f32> {
+    // mirrors calculate_buoyancy_force in cpu/physics.rs, Boussinesq's -ρβ(T - T₀)g
+    return -density * constants.thermal_expansion
+        * (temperature - constants.ambient_temperature) * constants.gravity;
+}
+
+fn calculate_surface_tension_vector(
+    offset: vec2<f32>,
+    normal_difference: vec2<f32>,
+    density: f32,
+    density_other: f32,
+    material: Material,
+    material_other: Material,
 ) -> vec2<f32> {
+    // mirrors calculate_surface_tension_force in cpu/physics.rs:
+    // Akinci's per-particle force scaled by ρᵢ/mᵢ into a force density, with
+    // mⱼ in the cohesion term measured at cohesion_density
+    let r = length(offset);
+    let correction = 2.0 * material.rest_density / (density + density_other);
+    let cohesion_mass = constants.cohesion_density * material_other.mass / material_other.rest_density;
+    var cohesion = vec2<f32>(0.0, 0.0);
+    if r > 0.00001 {
+        cohesion = -cohesion_mass * cohesion_kernel(r) * offset / r;
+    }
+    let curvature = -normal_difference;
+    return constants.surface_tension * density * correction * (cohesion + curvature);
+}
+
+@compute @workgroup_size(128)
+fn calculate_normals(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // colour-field normal nᵢ = h Σ mⱼ/ρⱼ ∇W for surface tension, it points into
+    // the fluid at the surface and is zero deep inside it
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
 
-    let grad_spiky = spiky_kernel_gradient(pos, pos_other);
-    return constants.mass * ((pressure + pressure_other) / (2.0 * density_other)) * grad_spiky;
+    let my_predicted_pos = predicted_pos[index];
+    var normal = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(my_predicted_pos);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                normal += constants.influence_radius * material(particle_idx).mass / particles[particle_idx].density
+                    * smoothing_kernel_gradient(my_predicted_pos, predicted_pos[particle_idx]);
+            }
+        }
+    }
+    scratch[index].normal = normal;
 }
 
 @compute @workgroup_size(128)
@@ -170,7 +655,11 @@ fn calculate_pressure_force(@builtin(global_invocation_id) global_id: vec3<u32>)
     let my_predicted_pos = predicted_pos[index];
     let my_pressure = particles[index].pressure;
     let my_density = particles[index].density;
+    let my_vel = particles[index].vel;
+    let my_temperature = particles[index].temperature;
+    let my_material = material(index);
     var force = vec2<f32>(0.0, 0.0);
+    var heating = collider_heating(my_predicted_pos, my_temperature);
     let grid_coord = grid_coord(my_predicted_pos);
     let grid_neighbours = neighbours();
     for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
@@ -189,61 +678,947 @@ fn calculate_pressure_force(@builtin(global_invocation_id) global_id: vec3<u32>)
                 if index == particle_idx {
                     continue;
                 }
-                force -= calculate_pressure_vector(
+                let other_material = material(particle_idx);
+                if !iterative_pressure() {
+                    force -= calculate_pressure_vector(
+                        my_predicted_pos,
+                        predicted_pos[particle_idx],
+                        my_pressure,
+                        particles[particle_idx].pressure,
+                        particles[particle_idx].density,
+                        other_material.mass
+                    );
+                }
+                let volume_other = other_material.mass / particles[particle_idx].density;
+                force += calculate_viscosity_vector(
                     my_predicted_pos,
                     predicted_pos[particle_idx],
-                    my_pressure,
-                    particles[particle_idx].pressure,
-                    particles[particle_idx].density
+                    my_vel,
+                    particles[particle_idx].vel,
+                    volume_other,
+                    0.5 * (my_material.viscosity + other_material.viscosity)
+                );
+                heating += calculate_heat_diffusion(
+                    my_predicted_pos,
+                    predicted_pos[particle_idx],
+                    my_temperature,
+                    particles[particle_idx].temperature,
+                    volume_other
                 );
+                if constants.surface_tension > 0.0 {
+                    // normals only hold this frame's values when calculate_normals ran
+                    force += calculate_surface_tension_vector(
+                        my_predicted_pos - predicted_pos[particle_idx],
+                        scratch[index].normal - scratch[particle_idx].normal,
+                        my_density,
+                        particles[particle_idx].density,
+                        my_material,
+                        other_material
+                    );
+                }
             }
         }
     }
+    if boundary_particles() {
+        force -= boundary_pressure_force(
+            my_predicted_pos,
+            my_pressure,
+            my_density,
+            my_material.rest_density
+        );
+    }
     force += my_density * constants.gravity;
+    force += calculate_buoyancy_vector(my_density, my_temperature);
     particles[index].force = force;
+    // written to scratch so neighbours still read this step's temperature,
+    // integrate applies it
+    scratch[index].heating = heating;
 }
 
-fn mouse_delta_vel(particle_pos: vec2<f32>) -> vec2<f32> {
-    // mirrors IOInteraction::delta_vel in cpu/simulation.rs
-    // strength > 0 attracts toward mouse, < 0 repels away, == 0 disables
-    let strength = constants.mouse_strength;
-    if strength == 0.0 {
-        return vec2<f32>(0.0, 0.0);
-    }
-    let radius = constants.mouse_influence_radius;
-    // attract: delta points toward mouse, factor grows with dist
-    // repel:   delta points away from mouse, factor shrinks with dist
-    let attract = strength > 0.0;
-    let delta = select(particle_pos - constants.mouse_pos, constants.mouse_pos - particle_pos, attract);
-    let dist = length(delta);
-    if dist >= radius || dist <= 0.0001 {
-        return vec2<f32>(0.0, 0.0);
+@compute @workgroup_size(128)
+fn apply_xsph(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // XSPH: vᵢ += ε Σ mⱼ (vⱼ - vᵢ) / ρ̄ᵢⱼ W, with ρ̄ᵢⱼ the mean of both densities.
+    // Written to scratch rather than particles so neighbours still read the
+    // unsmoothed velocity, integrate picks it up afterwards.
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
     }
-    let dir = delta / dist;
-    let factor = select(1.0 - dist / radius, dist / radius, attract);
-    return dir * abs(strength) * factor;
-}
-
-fn integrate(index: u32) {
-    let acceleration = particles[index].force / particles[index].density;
-    let velocity_old = particles[index].vel;
-
-    particles[index].vel += acceleration * constants.dt;
-    particles[index].vel += mouse_delta_vel(particles[index].pos);
-    let velocity_length = length(particles[index].vel);
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
 
-    // Limit velocity so they don't blow up when coming too close to eachother. 
-    // TODO: maybe we need a better solution for this.
-    if velocity_length * velocity_length > constants.max_vel * constants.max_vel {
-        particles[index].vel = (particles[index].vel / velocity_length) * constants.max_vel;
+    let my_predicted_pos = predicted_pos[index];
+    let my_vel = particles[index].vel;
+    let my_density = particles[index].density;
+    var correction = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(my_predicted_pos);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                let mean_density = 0.5 * (my_density + particles[particle_idx].density);
+                correction += material(particle_idx).mass * (particles[particle_idx].vel - my_vel) / mean_density
+                    * smoothing_kernel(my_predicted_pos, predicted_pos[particle_idx]);
+            }
+        }
     }
-
-    // Density and force kernels read predicted_pos (= pos + vel*dt) rather
-    // than pos. Using the projected next step positions stabilizes the sim
-    // against pressure instabilities at large timesteps. This was borrowed from Muller's paper.
-    particles[index].pos += (particles[index].vel + velocity_old) * 0.5 * constants.dt;
-    boundaries(index);
-    predicted_pos[index] = particles[index].pos + particles[index].vel * constants.dt;
+    scratch[index].xsph_vel = my_vel + constants.xsph * correction;
+}
+
+@compute @workgroup_size(128)
+fn relax_positions(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // Double density relaxation (Clavet et al. 2005). Every pair is pushed
+    // apart along r̂ᵢⱼ by dt² (P(1 - q) + Pⁿᵉᵃʳ(1 - q)²), half to each side.
+    // Run as a Jacobi pass with the pair's mean pressures so it is symmetric
+    // and order independent. The displacement over dt² goes into force and
+    // integrate applies it.
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+
+    let my_predicted_pos = predicted_pos[index];
+    let my_pressure = particles[index].pressure;
+    let my_near_pressure = constants.ddr_near_stiffness * scratch[index].near_density;
+    var displacement = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(my_predicted_pos);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                displacement -= calculate_relaxation_vector(
+                    my_predicted_pos,
+                    predicted_pos[particle_idx],
+                    my_pressure,
+                    particles[particle_idx].pressure,
+                    my_near_pressure,
+                    constants.ddr_near_stiffness * scratch[particle_idx].near_density
+                );
+            }
+        }
+    }
+    particles[index].force = displacement;
+}
+
+fn calculate_relaxation_vector(
+    pos: vec2<f32>,
+    pos_other: vec2<f32>,
+    pressure: f32,
+    pressure_other: f32,
+    near_pressure: f32,
+    near_pressure_other: f32,
+) -> vec2<f32> {
+    // mirrors calculate_relaxation_displacement in cpu/physics.rs
+    let delta = pos_other - pos;
+    let r = length(delta);
+    if r < 0.00001 || r >= constants.influence_radius {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let q = r / constants.influence_radius;
+    let magnitude = (pressure + pressure_other) * (1.0 - q)
+        + (near_pressure + near_pressure_other) * pow(1.0 - q, 2);
+    return 0.5 * magnitude * delta / r;
+}
+
+fn pcisph_delta(material: Material) -> f32 {
+    // mirrors pcisph_delta in cpu/physics.rs, pressure per unit of density error
+    let spacing = sqrt(material.mass / material.rest_density);
+    let extent = i32(ceil(constants.influence_radius / spacing));
+    var sum_grad = vec2<f32>(0.0, 0.0);
+    var sum_grad_pressure = vec2<f32>(0.0, 0.0);
+    var sum_dot = 0.0;
+    for (var y = -extent; y <= extent; y += 1) {
+        for (var x = -extent; x <= extent; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * spacing;
+            let r = length(offset);
+            if r == 0.0 || r > constants.influence_radius {
+                continue;
+            }
+            let origin = vec2<f32>(0.0, 0.0);
+            let grad = smoothing_kernel_gradient(origin, offset);
+            let grad_pressure = pressure_kernel_gradient(origin, offset);
+            sum_grad += grad;
+            sum_grad_pressure += grad_pressure;
+            sum_dot += dot(grad, grad_pressure);
+        }
+    }
+    let beta = 2.0 * pow(constants.dt * material.mass / material.rest_density, 2);
+    let denominator = beta * (dot(sum_grad, sum_grad_pressure) + sum_dot);
+    if denominator <= 0.0 {
+        return 0.0;
+    }
+    return constants.pcisph_relaxation / denominator;
+}
+
+fn solver_base_vel(index: u32) -> vec2<f32> {
+    // the velocity integrate will start from, for PCISPH and DFSPH
+    if constants.xsph > 0.0 {
+        return scratch[index].xsph_vel;
+    }
+    return particles[index].vel;
+}
+
+@compute @workgroup_size(128)
+fn pcisph_predict(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // where integrate would put the particle with the current pressure guess,
+    // kept inside the walls like boundaries() does, otherwise particles
+    // resting on a wall are predicted to leave through it and never
+    // build up the pressure that holds them up
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    let vel = solver_base_vel(index);
+    let acceleration = particles[index].force / particles[index].density
+        + scratch[index].pressure_accel;
+    let vel_new = vel + acceleration * constants.dt;
+    scratch[index].solver_pos = clamp(
+        particles[index].pos + vel_new * constants.dt,
+        vec2<f32>(constants.radius, constants.radius),
+        vec2<f32>(constants.width, constants.height) - constants.radius
+    );
+}
+
+var<workgroup> error_sums: array<f32, WORKGROUP_SIZE>;
+
+fn write_error_sum(error: f32, local_index: u32, workgroup_index: u32) {
+    // tree reduction of the workgroup's errors into partial_sums, summed up
+    // on the CPU. Must be reached by every invocation, barriers included.
+    error_sums[local_index] = error;
+    workgroupBarrier();
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if local_index < stride {
+            error_sums[local_index] += error_sums[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+    if local_index == 0u {
+        partial_sums[workgroup_index] = error_sums[0];
+    }
+}
+
+var<workgroup> limit_maxima: array<vec2<f32>, WORKGROUP_SIZE>;
+
+@compute @workgroup_size(128)
+fn reduce_step_limits(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    // mirrors Particles::step_limits in cpu/simulation.rs: the workgroup's
+    // fastest speed and largest acceleration into partial_sums[2w] and
+    // [2w + 1], the maximum over the workgroups is taken on the CPU. Runs
+    // after physics, so the speed is the one the next step starts with.
+    let index = global_id.x;
+    var limits = vec2<f32>(0.0, 0.0);
+    if index < constants.no_particles {
+        var acceleration = length(constants.gravity);
+        if constants.solver != SOLVER_DOUBLE_DENSITY && constants.solver != SOLVER_PBF {
+            var force_acceleration = particles[index].force / particles[index].density;
+            if iterative_pressure() {
+                force_acceleration += scratch[index].pressure_accel;
+            }
+            acceleration = length(force_acceleration);
+        }
+        limits = vec2<f32>(length(particles[index].vel), acceleration);
+    }
+    let local_index = local_id.x;
+    limit_maxima[local_index] = limits;
+    workgroupBarrier();
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if local_index < stride {
+            limit_maxima[local_index] = max(limit_maxima[local_index], limit_maxima[local_index + stride]);
+        }
+        workgroupBarrier();
+    }
+    if local_index == 0u {
+        partial_sums[2u * workgroup_id.x] = limit_maxima[0].x;
+        partial_sums[2u * workgroup_id.x + 1u] = limit_maxima[0].y;
+    }
+}
+
+@compute @workgroup_size(128)
+fn pcisph_correct_pressure(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    // density at the predicted positions, pressure += δ (ρ* - ρ₀), and each
+    // workgroup's sum of the relative compression into partial_sums
+    let index = global_id.x;
+    var error = 0.0;
+    if index < constants.no_particles {
+        let grid_width = floor(constants.width / constants.cell_size);
+        let grid_height = floor(constants.height / constants.cell_size);
+
+        let my_solver_pos = scratch[index].solver_pos;
+        var density = 0.0;
+        // the grid was built from predicted_pos, which is within a dt² term of solver_pos
+        let grid_coord = grid_coord(predicted_pos[index]);
+        let grid_neighbours = neighbours();
+        for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+            let offset = grid_neighbours[i];
+            let neighbour_x = i32(grid_coord.x) + offset.x;
+            let neighbour_y = i32(grid_coord.y) + offset.y;
+            if neighbour_x >= 0
+                && neighbour_x < i32(grid_width)
+                && neighbour_y >= 0
+                && neighbour_y < i32(grid_height) {
+                let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+                let start_index = lookups[cell_key].start_index;
+                let end_index = lookups[cell_key].end_index;
+                for (var j: u32 = start_index; j < end_index; j += 1u) {
+                    let particle_idx = particle_ids[j];
+                    density += calculate_density(
+                        my_solver_pos,
+                        scratch[particle_idx].solver_pos,
+                        material(particle_idx).mass
+                    );
+                }
+            }
+        }
+        let my_material = material(index);
+        let density_error = density - my_material.rest_density;
+        // negative pressure would pull the free surface into clumps
+        particles[index].pressure = max(
+            particles[index].pressure + pcisph_delta(my_material) * density_error,
+            0.0
+        );
+        error = max(density_error, 0.0) / my_material.rest_density;
+    }
+
+    write_error_sum(error, local_id.x, workgroup_id.x);
+}
+
+@compute @workgroup_size(128)
+fn pcisph_pressure_force(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    rand_state = pcg_hash(index);
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+
+    let my_solver_pos = scratch[index].solver_pos;
+    let my_rest_density = material(index).rest_density;
+    let my_term = particles[index].pressure / (my_rest_density * my_rest_density);
+    var acceleration = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(predicted_pos[index]);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                // mirrors calculate_pcisph_pressure_accel in cpu/physics.rs
+                let other_material = material(particle_idx);
+                let other_term = particles[particle_idx].pressure
+                    / (other_material.rest_density * other_material.rest_density);
+                acceleration -= other_material.mass * (my_term + other_term)
+                    * pressure_kernel_gradient(my_solver_pos, scratch[particle_idx].solver_pos);
+            }
+        }
+    }
+    scratch[index].pressure_accel = acceleration;
+}
+
+fn dfsph_velocity(index: u32, with_forces: bool) -> vec2<f32> {
+    // mirrors Particles::dfsph_velocity in cpu/simulation.rs: the velocity
+    // integrate would end up with under the current pressure, limited so the
+    // particle stops at a wall instead of being predicted through it
+    var vel = solver_base_vel(index) + scratch[index].pressure_accel * constants.dt;
+    if with_forces {
+        vel += particles[index].force / particles[index].density * constants.dt;
+    }
+    let pos = predicted_pos[index];
+    let limited_pos = clamp(
+        pos + vel * constants.dt,
+        vec2<f32>(constants.radius, constants.radius),
+        vec2<f32>(constants.width, constants.height) - constants.radius
+    );
+    return (limited_pos - pos) / constants.dt;
+}
+
+fn dfsph_solve(index: u32, local_index: u32, workgroup_index: u32, divergence: bool) {
+    // one Jacobi sweep of either DFSPH solve: the density change the velocities
+    // cause, Dρ/Dt = Σ mⱼ (vᵢ - vⱼ)·∇W, turned into a pressure increment κρ
+    var error = 0.0;
+    if index < constants.no_particles {
+        let grid_width = floor(constants.width / constants.cell_size);
+        let grid_height = floor(constants.height / constants.cell_size);
+
+        let my_predicted_pos = predicted_pos[index];
+        let my_vel = dfsph_velocity(index, !divergence);
+        var density_derivative = 0.0;
+        let grid_coord = grid_coord(my_predicted_pos);
+        let grid_neighbours = neighbours();
+        for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+            let offset = grid_neighbours[i];
+            let neighbour_x = i32(grid_coord.x) + offset.x;
+            let neighbour_y = i32(grid_coord.y) + offset.y;
+            if neighbour_x >= 0
+                && neighbour_x < i32(grid_width)
+                && neighbour_y >= 0
+                && neighbour_y < i32(grid_height) {
+                let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+                let start_index = lookups[cell_key].start_index;
+                let end_index = lookups[cell_key].end_index;
+                for (var j: u32 = start_index; j < end_index; j += 1u) {
+                    let particle_idx = particle_ids[j];
+                    if index == particle_idx {
+                        continue;
+                    }
+                    // mirrors calculate_density_derivative in cpu/physics.rs
+                    density_derivative += material(particle_idx).mass * dot(
+                        my_vel - dfsph_velocity(particle_idx, !divergence),
+                        pressure_kernel_gradient(my_predicted_pos, predicted_pos[particle_idx])
+                    );
+                }
+            }
+        }
+        // mirrors calculate_dfsph_stiffness in cpu/physics.rs
+        let density = particles[index].density;
+        let rest_density = material(index).rest_density;
+        let alpha = constants.dfsph_relaxation * scratch[index].alpha;
+        var stiffness = 0.0;
+        if divergence {
+            stiffness = density_derivative / constants.dt * alpha;
+            error = max(density_derivative, 0.0) * constants.dt / rest_density;
+        } else {
+            let density_error = density + constants.dt * density_derivative - rest_density;
+            stiffness = density_error / (constants.dt * constants.dt) * alpha;
+            error = max(density_error, 0.0) / rest_density;
+        }
+        // negative pressure would pull the free surface into clumps
+        particles[index].pressure = max(particles[index].pressure + stiffness * density, 0.0);
+    }
+    write_error_sum(error, local_index, workgroup_index);
+}
+
+@compute @workgroup_size(128)
+fn dfsph_divergence(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    dfsph_solve(global_id.x, local_id.x, workgroup_id.x, true);
+}
+
+@compute @workgroup_size(128)
+fn dfsph_density(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    dfsph_solve(global_id.x, local_id.x, workgroup_id.x, false);
+}
+
+@compute @workgroup_size(128)
+fn dfsph_pressure_accel(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // -Σ mⱼ (pᵢ/ρᵢ² + pⱼ/ρⱼ²) ∇W from the pressure summed up so far. The
+    // positions do not move during the solves, so this is the sum of every
+    // sweep's velocity correction over dt.
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    rand_state = pcg_hash(index);
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+
+    let my_predicted_pos = predicted_pos[index];
+    let my_density = particles[index].density;
+    let my_term = particles[index].pressure / (my_density * my_density);
+    var acceleration = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(my_predicted_pos);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                // mirrors calculate_dfsph_pressure_accel in cpu/physics.rs
+                let density_other = particles[particle_idx].density;
+                acceleration -= material(particle_idx).mass
+                    * (my_term + particles[particle_idx].pressure / (density_other * density_other))
+                    * pressure_kernel_gradient(my_predicted_pos, predicted_pos[particle_idx]);
+            }
+        }
+    }
+    scratch[index].pressure_accel = acceleration;
+}
+
+@compute @workgroup_size(128)
+fn dfsph_apply_divergence(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // folds the divergence solve's correction into the velocity integrate
+    // starts from and clears the pressure for the density solve
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    let vel = solver_base_vel(index) + scratch[index].pressure_accel * constants.dt;
+    if constants.xsph > 0.0 {
+        scratch[index].xsph_vel = vel;
+    } else {
+        particles[index].vel = vel;
+    }
+    particles[index].pressure = 0.0;
+    scratch[index].pressure_accel = vec2<f32>(0.0, 0.0);
+}
+
+fn pbf_gradient(pos: vec2<f32>, pos_other: vec2<f32>, mass_other: f32, rest_density: f32) -> vec2<f32> {
+    // mirrors calculate_pbf_gradient in cpu/physics.rs
+    return mass_other / rest_density * pressure_kernel_gradient(pos, pos_other);
+}
+
+@compute @workgroup_size(128)
+fn pbf_lambda(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    // density constraint Cᵢ = ρᵢ/ρ₀ᵢ - 1 at solver_pos and its multiplier λᵢ,
+    // each workgroup's sum of the relative compression into partial_sums
+    let index = global_id.x;
+    var error = 0.0;
+    if index < constants.no_particles {
+        rand_state = pcg_hash(index);
+        let grid_width = floor(constants.width / constants.cell_size);
+        let grid_height = floor(constants.height / constants.cell_size);
+
+        let my_solver_pos = scratch[index].solver_pos;
+        let my_material = material(index);
+        var density = 0.0;
+        var grad_sum = vec2<f32>(0.0, 0.0);
+        var grad_square_sum = 0.0;
+        // the grid was built from predicted_pos, where solver_pos started
+        let grid_coord = grid_coord(predicted_pos[index]);
+        let grid_neighbours = neighbours();
+        for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+            let offset = grid_neighbours[i];
+            let neighbour_x = i32(grid_coord.x) + offset.x;
+            let neighbour_y = i32(grid_coord.y) + offset.y;
+            if neighbour_x >= 0
+                && neighbour_x < i32(grid_width)
+                && neighbour_y >= 0
+                && neighbour_y < i32(grid_height) {
+                let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+                let start_index = lookups[cell_key].start_index;
+                let end_index = lookups[cell_key].end_index;
+                for (var j: u32 = start_index; j < end_index; j += 1u) {
+                    let particle_idx = particle_ids[j];
+                    let other_solver_pos = scratch[particle_idx].solver_pos;
+                    let mass_other = material(particle_idx).mass;
+                    density += calculate_density(my_solver_pos, other_solver_pos, mass_other);
+                    if index != particle_idx {
+                        let grad = pbf_gradient(
+                            my_solver_pos,
+                            other_solver_pos,
+                            mass_other,
+                            my_material.rest_density
+                        );
+                        grad_sum += grad;
+                        grad_square_sum += dot(grad, grad) / mass_other;
+                    }
+                }
+            }
+        }
+        // mirrors calculate_pbf_lambda in cpu/physics.rs
+        let constraint = density / my_material.rest_density - 1.0;
+        scratch[index].lambda = -constraint
+            / (dot(grad_sum, grad_sum) / my_material.mass + grad_square_sum + constants.pbf_relaxation);
+        error = max(constraint, 0.0);
+    }
+    write_error_sum(error, local_id.x, workgroup_id.x);
+}
+
+@compute @workgroup_size(128)
+fn pbf_displacement(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // Δpᵢ = Σ ((λᵢ/mᵢ + s_corr) mⱼ/ρ₀ᵢ + λⱼ/ρ₀ⱼ) ∇W into position_delta,
+    // applied by pbf_apply once every particle has its own
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    rand_state = pcg_hash(index);
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+
+    let my_solver_pos = scratch[index].solver_pos;
+    let my_lambda = scratch[index].lambda;
+    let my_material = material(index);
+    let reference = smoothing_kernel(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(ARTIFICIAL_PRESSURE_RADIUS * constants.influence_radius, 0.0)
+    );
+    var delta = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(predicted_pos[index]);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                // mirrors calculate_pbf_displacement in cpu/physics.rs
+                let other_solver_pos = scratch[particle_idx].solver_pos;
+                var correction = 0.0;
+                if reference > 0.0 {
+                    correction = -constants.artificial_pressure * pow(
+                        smoothing_kernel(my_solver_pos, other_solver_pos) / reference,
+                        ARTIFICIAL_PRESSURE_EXPONENT
+                    );
+                }
+                let other_material = material(particle_idx);
+                delta += ((my_lambda / my_material.mass + correction) * other_material.mass
+                    / my_material.rest_density
+                    + scratch[particle_idx].lambda / other_material.rest_density)
+                    * pressure_kernel_gradient(my_solver_pos, other_solver_pos);
+            }
+        }
+    }
+    scratch[index].position_delta = delta;
+}
+
+@compute @workgroup_size(128)
+fn pbf_apply(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // kept inside the walls like boundaries() does
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    scratch[index].solver_pos = clamp(
+        scratch[index].solver_pos + scratch[index].position_delta,
+        vec2<f32>(constants.radius, constants.radius),
+        vec2<f32>(constants.width, constants.height) - constants.radius
+    );
+}
+
+@compute @workgroup_size(128)
+fn pbf_velocity(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // moves the particles to the solved positions, the velocity is whatever
+    // moved them
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    let solved_pos = scratch[index].solver_pos;
+    particles[index].vel = (solved_pos - particles[index].pos) / constants.dt;
+    particles[index].pos = solved_pos;
+}
+
+@compute @workgroup_size(128)
+fn pbf_vorticity(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // ωᵢ = Σ (vⱼ - vᵢ) × ∇ⱼW of the velocities integrate_pbf starts from,
+    // only dispatched when vorticity_confinement > 0
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    rand_state = pcg_hash(index);
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+
+    let my_pos = particles[index].pos;
+    let my_vel = solver_base_vel(index);
+    var vorticity = 0.0;
+    let grid_coord = grid_coord(predicted_pos[index]);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                // mirrors calculate_vorticity in cpu/physics.rs
+                let other_pos = particles[particle_idx].pos;
+                let relative_vel = solver_base_vel(particle_idx) - my_vel;
+                let grad = pressure_kernel_gradient(other_pos, my_pos);
+                vorticity += relative_vel.x * grad.y - relative_vel.y * grad.x;
+            }
+        }
+    }
+    scratch[index].vorticity = vorticity;
+}
+
+fn vorticity_confinement(index: u32) -> vec2<f32> {
+    // mirrors calculate_vorticity_gradient and calculate_vorticity_confinement
+    // in cpu/physics.rs: ε (N × ω), N = ∇|ω| / |∇|ω||
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+
+    let my_pos = particles[index].pos;
+    let my_vorticity = scratch[index].vorticity;
+    var vorticity_gradient = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(predicted_pos[index]);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                if index == particle_idx {
+                    continue;
+                }
+                vorticity_gradient += material(particle_idx).mass / particles[particle_idx].density
+                    * (abs(scratch[particle_idx].vorticity) - abs(my_vorticity))
+                    * pressure_kernel_gradient(my_pos, particles[particle_idx].pos);
+            }
+        }
+    }
+    let gradient_length = length(vorticity_gradient);
+    if gradient_length < 0.00001 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let normal = vorticity_gradient / gradient_length;
+    return constants.vorticity_confinement
+        * vec2<f32>(normal.y * my_vorticity, -normal.x * my_vorticity);
+}
+
+fn integrate_pbf(index: u32) {
+    // mirrors Particles::integrate_pbf (and confine_vorticity's last loop) in
+    // cpu/simulation.rs. pbf_velocity already moved the particle, what is
+    // left is the boundaries, then gravity and the mouse for the next prediction.
+    rand_state = pcg_hash(index);
+    var vel = solver_base_vel(index);
+    if constants.vorticity_confinement > 0.0 {
+        vel += vorticity_confinement(index) * constants.dt;
+    }
+    particles[index].vel = vel;
+    boundaries(index);
+
+    particles[index].vel += constants.gravity * constants.dt;
+    particles[index].vel += mouse_delta_vel(particles[index].pos);
+    let velocity_length = length(particles[index].vel);
+    if velocity_length * velocity_length > constants.max_vel * constants.max_vel {
+        particles[index].vel = (particles[index].vel / velocity_length) * constants.max_vel;
+    }
+    predicted_pos[index] = particles[index].pos + particles[index].vel * constants.dt;
+}
+
+fn mouse_delta_vel(particle_pos: vec2<f32>) -> vec2<f32> {
+    // mirrors IOInteraction::delta_vel in cpu/simulation.rs
+    // strength > 0 attracts toward mouse, < 0 repels away, == 0 disables
+    let strength = constants.mouse_strength;
+    if strength == 0.0 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let radius = constants.mouse_influence_radius;
+    // attract: delta points toward mouse, factor grows with dist
+    // repel:   delta points away from mouse, factor shrinks with dist
+    let attract = strength > 0.0;
+    let delta = select(particle_pos - constants.mouse_pos, constants.mouse_pos - particle_pos, attract);
+    let dist = length(delta);
+    if dist >= radius || dist <= 0.0001 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let dir = delta / dist;
+    let factor = select(1.0 - dist / radius, dist / radius, attract);
+    return dir * abs(strength) * factor;
+}
+
+fn integrate_relaxed(index: u32) {
+    // mirrors Particles::integrate_relaxed in cpu/simulation.rs. predicted_pos
+    // already carries last step's velocity (gravity included), relaxation
+    // corrects it and the velocity is whatever actually moved the particle.
+    let relaxed_pos = predicted_pos[index] + particles[index].force * constants.dt * constants.dt;
+    particles[index].vel = (relaxed_pos - particles[index].pos) / constants.dt;
+    particles[index].pos = relaxed_pos;
+    boundaries(index);
+
+    particles[index].vel += constants.gravity * constants.dt;
+    particles[index].vel += mouse_delta_vel(particles[index].pos);
+    let velocity_length = length(particles[index].vel);
+    if velocity_length * velocity_length > constants.max_vel * constants.max_vel {
+        particles[index].vel = (particles[index].vel / velocity_length) * constants.max_vel;
+    }
+    predicted_pos[index] = particles[index].pos + particles[index].vel * constants.dt;
+}
+
+fn integrate(index: u32) {
+    // mirrors Particles::integrate in cpu/simulation.rs
+    if constants.xsph > 0.0 {
+        // apply_xsph only runs when xsph > 0, see GpuSimulation::compute
+        particles[index].vel = scratch[index].xsph_vel;
+    }
+    var acceleration = particles[index].force / particles[index].density;
+    if iterative_pressure() {
+        acceleration += scratch[index].pressure_accel;
+    }
+    particles[index].temperature += scratch[index].heating * constants.dt;
+
+    // the mouse and the velocity cap act on whichever velocity the
+    // integrator moves the particle with
+    let mouse_vel = mouse_delta_vel(particles[index].pos);
+    let integrator = active_integrator();
+    let dt = constants.dt;
+    let vel = particles[index].vel;
+    let accel_old = particles[index].accel;
+    switch integrator {
+        case INTEGRATOR_SYMPLECTIC_EULER: {
+            particles[index].vel = limit_velocity(vel + acceleration * dt + mouse_vel);
+            particles[index].pos += particles[index].vel * dt;
+        }
+        case INTEGRATOR_LEAPFROG: {
+            // kick and drift, the closing kick comes after the walls
+            particles[index].vel = limit_velocity(vel + accel_old * 0.5 * dt + mouse_vel);
+            particles[index].pos += particles[index].vel * dt;
+        }
+        case INTEGRATOR_VELOCITY_VERLET: {
+            particles[index].pos += (vel + accel_old * 0.5 * dt) * dt;
+            particles[index].vel = limit_velocity(
+                vel + (accel_old + acceleration) * 0.5 * dt + mouse_vel
+            );
+        }
+        case INTEGRATOR_RK2: {
+            // rk2_predict kept v₀ in scratch and left a₁ in accel. vel
+            // holds v₀ + a₁dt, smoothed by XSPH if that is on since, so v₀
+            // cannot be rebuilt from it
+            let velocity_old = scratch[index].rk2_vel;
+            particles[index].vel = limit_velocity(
+                velocity_old + (accel_old + acceleration) * 0.5 * dt + mouse_vel
+            );
+            particles[index].pos += (velocity_old + vel) * 0.5 * dt;
+        }
+        default: {
+            particles[index].vel = limit_velocity(vel + acceleration * dt + mouse_vel);
+            particles[index].pos += (particles[index].vel + vel) * 0.5 * dt;
+        }
+    }
+    boundaries(index);
+    if integrator == INTEGRATOR_LEAPFROG {
+        particles[index].vel += acceleration * 0.5 * dt;
+    }
+    particles[index].accel = acceleration;
+
+    let pos = particles[index].pos;
+    switch integrator {
+        case INTEGRATOR_SYMPLECTIC_EULER, INTEGRATOR_RK2: {
+            if constants.solver == SOLVER_PCISPH {
+                // PCISPH's prediction assumes the forces a step ahead
+                predicted_pos[index] = pos + particles[index].vel * dt;
+            } else {
+                // the current positions, which is also what DFSPH wants as
+                // it predicts the density from the velocities itself
+                predicted_pos[index] = pos;
+            }
+        }
+        case INTEGRATOR_LEAPFROG, INTEGRATOR_VELOCITY_VERLET: {
+            // where the next drift takes the particle
+            predicted_pos[index] = pos + (particles[index].vel + acceleration * 0.5 * dt) * dt;
+        }
+        default: {
+            // Density and force kernels read predicted_pos (= pos + vel*dt)
+            // rather than pos. Using the projected next step positions stabilizes the sim
+            // against pressure instabilities at large timesteps. This was borrowed from Muller's paper.
+            predicted_pos[index] = pos + particles[index].vel * dt;
+        }
+    }
+}
+
+fn limit_velocity(vel: vec2<f32>) -> vec2<f32> {
+    // Limit velocity so they don't blow up when coming too close to eachother.
+    // TODO: maybe we need a better solution for this.
+    let velocity_length = length(vel);
+    if velocity_length * velocity_length > constants.max_vel * constants.max_vel {
+        return (vel / velocity_length) * constants.max_vel;
+    }
+    return vel;
+}
+
+@compute @workgroup_size(128)
+fn rk2_predict(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // mirrors Particles::predict_rk2 in cpu/simulation.rs: an Euler step
+    // with the first force evaluation's acceleration, for the second one to
+    // be made at
+    let index = global_id.x;
+    if index >= constants.no_particles {
+        return;
+    }
+    let acceleration = particles[index].force / particles[index].density;
+    particles[index].accel = acceleration;
+    scratch[index].rk2_vel = particles[index].vel;
+    predicted_pos[index] = particles[index].pos + particles[index].vel * constants.dt;
+    particles[index].vel += acceleration * constants.dt;
 }
 
 fn boundaries(index: u32) {
@@ -266,4 +1641,265 @@ fn boundaries(index: u32) {
         particles[index].pos.y = constants.radius;
         particles[index].vel.y *= -constants.damping;
     }
+    collide(index);
+    collide_bodies(index);
+}
+
+fn collider_point(index: u32) -> vec2<f32> {
+    let packed = colliders.points[index / 2u];
+    if index % 2u == 0u {
+        return packed.xy;
+    }
+    return packed.zw;
+}
+
+fn polygon_distance(collider: Collider, pos: vec2<f32>) -> f32 {
+    // mirrors polygon_distance in collider.rs
+    let first = collider_point(collider.point_start);
+    var distance_squared = dot(pos - first, pos - first);
+    var winding = 1.0;
+    var j = collider.point_count - 1u;
+    for (var i: u32 = 0u; i < collider.point_count; i += 1u) {
+        let vi = collider_point(collider.point_start + i);
+        let vj = collider_point(collider.point_start + j);
+        let e = vj - vi;
+        let w = pos - vi;
+        let b = w - e * clamp(dot(w, e) / dot(e, e), 0.0, 1.0);
+        distance_squared = min(distance_squared, dot(b, b));
+        let above = pos.y >= vi.y;
+        let below = pos.y < vj.y;
+        let left = e.x * w.y > e.y * w.x;
+        if above == below && below == left {
+            winding = -winding;
+        }
+        j = i;
+    }
+    return winding * sqrt(distance_squared);
+}
+
+fn collider_distance(collider: Collider, pos: vec2<f32>) -> f32 {
+    // mirrors Collider::signed_distance in collider.rs
+    var surface_distance = 0.0;
+    switch collider.shape {
+        case SHAPE_BOX: {
+            let angle = collider.b.x;
+            let offset = pos - collider.a.xy;
+            let local = vec2<f32>(
+                cos(angle) * offset.x + sin(angle) * offset.y,
+                -sin(angle) * offset.x + cos(angle) * offset.y
+            );
+            let d = abs(local) - collider.a.zw;
+            surface_distance = length(max(d, vec2<f32>(0.0, 0.0))) + min(max(d.x, d.y), 0.0);
+        }
+        case SHAPE_CAPSULE: {
+            let pa = pos - collider.a.xy;
+            let ba = collider.a.zw - collider.a.xy;
+            let length_squared = dot(ba, ba);
+            var h = 0.0;
+            if length_squared > 0.0 {
+                h = clamp(dot(pa, ba) / length_squared, 0.0, 1.0);
+            }
+            surface_distance = length(pa - ba * h) - collider.b.x;
+        }
+        case SHAPE_POLYGON: {
+            surface_distance = polygon_distance(collider, pos);
+        }
+        default: {
+            surface_distance = length(pos - collider.a.xy) - collider.a.z;
+        }
+    }
+    if collider.container != 0u {
+        return -surface_distance;
+    }
+    return surface_distance;
+}
+
+fn scene_distance(pos: vec2<f32>) -> f32 {
+    // mirrors signed_distance in collider.rs
+    var surface_distance = FLOAT_MAX;
+    for (var i: u32 = 0u; i < colliders.count; i += 1u) {
+        let collider = colliders.colliders[i];
+        if collider.operation == OPERATION_SUBTRACT {
+            surface_distance = max(surface_distance, -collider_distance(collider, pos));
+        } else {
+            surface_distance = min(surface_distance, collider_distance(collider, pos));
+        }
+    }
+    return surface_distance;
+}
+
+fn collider_heating(pos: vec2<f32>, temperature: f32) -> f32 {
+    // mirrors heating in collider.rs
+    var heating = 0.0;
+    for (var i: u32 = 0u; i < colliders.count; i += 1u) {
+        let collider = colliders.colliders[i];
+        if collider.heat_source != 0u && collider_distance(collider, pos) < constants.influence_radius {
+            heating += constants.heat_transfer * (collider.temperature - temperature);
+        }
+    }
+    return heating;
+}
+
+fn collide(index: u32) {
+    // mirrors collide in collider.rs: push out along the SDF gradient, then
+    // restitution on the normal velocity and friction on the tangential one
+    if colliders.count == 0u {
+        return;
+    }
+    let pos = particles[index].pos;
+    let surface_distance = scene_distance(pos);
+    if surface_distance >= constants.radius {
+        return;
+    }
+    let step_x = vec2<f32>(COLLIDER_GRADIENT_STEP, 0.0);
+    let step_y = vec2<f32>(0.0, COLLIDER_GRADIENT_STEP);
+    let gradient = vec2<f32>(
+        scene_distance(pos + step_x) - scene_distance(pos - step_x),
+        scene_distance(pos + step_y) - scene_distance(pos - step_y)
+    );
+    let gradient_length = length(gradient);
+    if gradient_length < 0.00001 {
+        return;
+    }
+    let normal = gradient / gradient_length;
+    particles[index].pos = pos + normal * (constants.radius - surface_distance);
+
+    let vel = particles[index].vel;
+    let normal_speed = dot(vel, normal);
+    if normal_speed < 0.0 {
+        let tangent_vel = vel - normal * normal_speed;
+        particles[index].vel = tangent_vel * (1.0 - constants.collider_friction)
+            - normal * normal_speed * constants.collider_restitution;
+    }
+}
+
+fn body_point(index: u32) -> vec2<f32> {
+    let packed = bodies.points[index / 2u];
+    if index % 2u == 0u {
+        return packed.xy;
+    }
+    return packed.zw;
+}
+
+fn body_polygon_distance(body: Body, local: vec2<f32>) -> f32 {
+    // polygon_distance above, with the points from bodies instead
+    let first = body_point(body.point_start);
+    var distance_squared = dot(local - first, local - first);
+    var winding = 1.0;
+    var j = body.point_count - 1u;
+    for (var i: u32 = 0u; i < body.point_count; i += 1u) {
+        let vi = body_point(body.point_start + i);
+        let vj = body_point(body.point_start + j);
+        let e = vj - vi;
+        let w = local - vi;
+        let b = w - e * clamp(dot(w, e) / dot(e, e), 0.0, 1.0);
+        distance_squared = min(distance_squared, dot(b, b));
+        let above = local.y >= vi.y;
+        let below = local.y < vj.y;
+        let left = e.x * w.y > e.y * w.x;
+        if above == below && below == left {
+            winding = -winding;
+        }
+        j = i;
+    }
+    return winding * sqrt(distance_squared);
+}
+
+fn body_distance(body: Body, pos: vec2<f32>) -> f32 {
+    // mirrors RigidBody::signed_distance in rigid_body.rs
+    let offset = pos - body.pos;
+    let local = vec2<f32>(
+        cos(body.angle) * offset.x + sin(body.angle) * offset.y,
+        -sin(body.angle) * offset.x + cos(body.angle) * offset.y
+    );
+    switch body.shape {
+        case BODY_BOX: {
+            let d = abs(local) - body.size;
+            return length(max(d, vec2<f32>(0.0, 0.0))) + min(max(d.x, d.y), 0.0);
+        }
+        case BODY_POLYGON: {
+            return body_polygon_distance(body, local);
+        }
+        default: {
+            return length(local) - body.size.x;
+        }
+    }
+}
+
+fn collide_bodies(index: u32) {
+    // mirrors collide in rigid_body.rs: like collide, but the restitution and
+    // friction act on the velocity relative to the body's surface
+    for (var b: u32 = 0u; b < bodies.count; b += 1u) {
+        let body = bodies.bodies[b];
+        let pos = particles[index].pos;
+        let surface_distance = body_distance(body, pos);
+        if surface_distance >= constants.radius {
+            continue;
+        }
+        let step_x = vec2<f32>(COLLIDER_GRADIENT_STEP, 0.0);
+        let step_y = vec2<f32>(0.0, COLLIDER_GRADIENT_STEP);
+        let gradient = vec2<f32>(
+            body_distance(body, pos + step_x) - body_distance(body, pos - step_x),
+            body_distance(body, pos + step_y) - body_distance(body, pos - step_y)
+        );
+        let gradient_length = length(gradient);
+        if gradient_length < 0.00001 {
+            continue;
+        }
+        let normal = gradient / gradient_length;
+        let r = pos - body.pos;
+        let surface_vel = body.vel + body.angular_velocity * vec2<f32>(-r.y, r.x);
+        particles[index].pos = pos + normal * (constants.radius - surface_distance);
+
+        let relative = particles[index].vel - surface_vel;
+        let normal_speed = dot(relative, normal);
+        if normal_speed < 0.0 {
+            let tangent_vel = relative - normal * normal_speed;
+            particles[index].vel = surface_vel + tangent_vel * (1.0 - constants.collider_friction)
+                - normal * normal_speed * constants.collider_restitution;
+        }
+    }
+}
+
+@compute @workgroup_size(128)
+fn calculate_body_forces(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // the push of the fluid on each body particle, the opposite of the
+    // boundary pressure force every neighbour got in the force pass, mirrors
+    // Particles::calculate_body_forces. Summed per body on the CPU.
+    let index = global_id.x;
+    if index >= arrayLength(&boundary) || boundary[index].body >= bodies.count {
+        return;
+    }
+    rand_state = pcg_hash(index);
+    let grid_width = floor(constants.width / constants.cell_size);
+    let grid_height = floor(constants.height / constants.cell_size);
+
+    let pos = boundary[index].pos;
+    let volume = boundary[index].volume;
+    var force = vec2<f32>(0.0, 0.0);
+    let grid_coord = grid_coord(pos);
+    let grid_neighbours = neighbours();
+    for (var i: u32 = 0u; i < NEIGHBOUR_CELL_COUNT; i += 1u) {
+        let offset = grid_neighbours[i];
+        let neighbour_x = i32(grid_coord.x) + offset.x;
+        let neighbour_y = i32(grid_coord.y) + offset.y;
+        if neighbour_x >= 0
+            && neighbour_x < i32(grid_width)
+            && neighbour_y >= 0
+            && neighbour_y < i32(grid_height) {
+            let cell_key = hash(vec2(u32(neighbour_x), u32(neighbour_y)));
+            let start_index = lookups[cell_key].start_index;
+            let end_index = lookups[cell_key].end_index;
+            for (var j: u32 = start_index; j < end_index; j += 1u) {
+                let particle_idx = particle_ids[j];
+                let density = particles[particle_idx].density;
+                let other_material = material(particle_idx);
+                // mirrors calculate_boundary_reaction_force in cpu/physics.rs
+                force += other_material.mass / density * other_material.rest_density * volume
+                    * (max(particles[particle_idx].pressure, 0.0) / density)
+                    * pressure_kernel_gradient(predicted_pos[particle_idx], pos);
+            }
+        }
+    }
+    boundary[index].force = force;
 }
@@ -0,0 +1,930 @@
+use crate::boundary::BoundaryParticles;
+use crate::collider::Collider;
+use crate::constants::{ParamEnum, SimulationParams, Solver};
+use crate::material::{self, Material};
+use crate::rigid_body::{self, MAX_BODY_SAMPLES, RigidBody};
+use crate::{Simulation, SolverStats, StepLimits};
+use bytemuck::Pod;
+use std::num::NonZeroU32;
+use wgpu::{self, util::DeviceExt};
+use wgpu_sort;
+
+use super::particle::{
+    GpuBodies, GpuBoundaryParticle, GpuColliders, GpuMaterials, GpuParticle, GpuScratch,
+};
+use super::pipelines::{ComputeBuffers, Pipelines};
+
+// matches @workgroup_size in update.wgsl, each workgroup writes one partial
+// sum, or two maxima for the step limits
+const WORKGROUP_SIZE: u32 = 128;
+// one [start, end) range per cell, the Lookup struct in common.wgsl
+const LOOKUP_SIZE: usize = std::mem::size_of::<[u32; 2]>();
+
+/// The compute half of the GPU backend: the device, the particle buffers,
+/// the sorter and the compute pipelines, with no window or surface attached.
+/// `GpuContext` wraps one of these for the windowed app, batch jobs can
+/// create one directly with `GpuSimulation::headless`.
+pub struct GpuSimulation {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+
+    pub params: SimulationParams,
+    pub colliders: Vec<Collider>,
+    pub bodies: Vec<RigidBody>,
+    // what reset puts the bodies back to
+    pub initial_bodies: Vec<RigidBody>,
+    pub materials: Vec<Material>,
+    pub time: f64,
+    pub solver_stats: Option<SolverStats>,
+    pub step_limits: Option<StepLimits>,
+    // every buffer below is sized for this many particles at creation time
+    pub particle_capacity: u32,
+    // the walls are rebuilt when the domain changes, but keep the buffer
+    // size they had at creation, plus room for MAX_BODY_SAMPLES body particles
+    pub boundary: BoundaryParticles,
+    pub boundary_capacity: u32,
+
+    pub pipelines: Pipelines,
+    pub particle_buffer: wgpu::Buffer,
+    pub constants_buffer: wgpu::Buffer,
+    pub lookups_buffer: wgpu::Buffer,
+    pub predicted_pos_buffer: wgpu::Buffer,
+    pub scratch_buffer: wgpu::Buffer,
+    pub partial_sums_buffer: wgpu::Buffer,
+    pub boundary_buffer: wgpu::Buffer,
+    pub colliders_buffer: wgpu::Buffer,
+    pub bodies_buffer: wgpu::Buffer,
+    pub materials_buffer: wgpu::Buffer,
+    pub sorter: wgpu_sort::GPUSorter,
+    pub sort_buffers: wgpu_sort::SortBuffers,
+}
+
+impl GpuSimulation {
+    /// Opens a device without any surface. With `force_fallback_adapter` set
+    /// wgpu picks a software adapter (llvmpipe, lavapipe, WARP), so this runs
+    /// on CI machines and servers with no GPU.
+    pub async fn headless(params: SimulationParams, force_fallback_adapter: bool) -> Self {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter,
+                compatible_surface: None,
+            })
+            .await
+            .expect("Failed to find GPU adapter");
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("Headless GPU Device"),
+                ..Default::default()
+            })
+            .await
+            .expect("Failed to open GPU device");
+
+        Self::new(device, queue, params)
+    }
+
+    pub fn new(device: wgpu::Device, queue: wgpu::Queue, params: SimulationParams) -> Self {
+        let initial_particles =
+            GpuParticle::spawn_particles(&params, &[], params.width as u32, params.height as u32);
+        let particle_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Buffer"),
+            contents: bytemuck::cast_slice(&initial_particles),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        let initial_predicted_pos: Vec<[f32; 2]> =
+            initial_particles.iter().map(|p| p.pos).collect();
+        let predicted_pos_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Predicted Pos Buffer"),
+            contents: bytemuck::cast_slice(&initial_predicted_pos),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        let scratch_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Scratch Buffer"),
+            size: (initial_particles.len() * std::mem::size_of::<GpuScratch>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let partial_sums_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Partial Sums Buffer"),
+            size: (initial_particles
+                .len()
+                .div_ceil(WORKGROUP_SIZE as usize)
+                .max(1)
+                * 2
+                * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let constants_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Constants Buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sorter = wgpu_sort::GPUSorter::new(&device, 32);
+        let sort_buffers = sorter.create_sort_buffers(
+            &device,
+            NonZeroU32::new(params.no_particles)
+                .expect("no_particles is at least 1, see SimulationParams::validate"),
+        );
+
+        let grid_width = (params.width / params.cell_size).floor() as u32;
+        let grid_height = (params.height / params.cell_size).floor() as u32;
+        // the fluid's ranges, then the same number of ranges for the walls
+        let lookups_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lookups Buffer"),
+            size: (2 * grid_width as usize * grid_height as usize * LOOKUP_SIZE)
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let boundary = BoundaryParticles::new(&params);
+        let boundary_capacity = boundary.particles.len() + MAX_BODY_SAMPLES;
+        let boundary_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Boundary Buffer"),
+            size: (boundary_capacity * std::mem::size_of::<GpuBoundaryParticle>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let colliders_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Colliders Buffer"),
+            contents: bytemuck::cast_slice(&[GpuColliders::new(&[])]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bodies_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bodies Buffer"),
+            contents: bytemuck::cast_slice(&[GpuBodies::new(&[])]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let materials_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Materials Buffer"),
+            contents: bytemuck::cast_slice(&[GpuMaterials::new(&[], &params)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let pipelines = Pipelines::new(
+            &device,
+            &ComputeBuffers {
+                particles: &particle_buffer,
+                constants: &constants_buffer,
+                sort_buffers: &sort_buffers,
+                lookups: &lookups_buffer,
+                predicted_pos: &predicted_pos_buffer,
+                scratch: &scratch_buffer,
+                partial_sums: &partial_sums_buffer,
+                boundary: &boundary_buffer,
+                colliders: &colliders_buffer,
+                bodies: &bodies_buffer,
+                materials: &materials_buffer,
+            },
+        );
+
+        let simulation = Self {
+            device,
+            queue,
+            params,
+            colliders: Vec::new(),
+            bodies: Vec::new(),
+            initial_bodies: Vec::new(),
+            materials: Vec::new(),
+            time: 0.0,
+            solver_stats: None,
+            step_limits: None,
+            particle_capacity: params.no_particles,
+            boundary_capacity: boundary_capacity as u32,
+            boundary,
+            pipelines,
+            particle_buffer,
+            constants_buffer,
+            lookups_buffer,
+            predicted_pos_buffer,
+            scratch_buffer,
+            partial_sums_buffer,
+            boundary_buffer,
+            colliders_buffer,
+            bodies_buffer,
+            materials_buffer,
+            sorter,
+            sort_buffers,
+        };
+        simulation.write_boundary();
+        simulation
+    }
+
+    // Uploads the wall and body particles and their ranges, which go right
+    // after the fluid's one range per cell. Particles past `boundary_capacity`
+    // are dropped like particles past `particle_capacity`, so a domain that
+    // outgrew the buffers keeps only the clamp where they do not fit.
+    fn write_boundary(&self) {
+        let count = self
+            .boundary
+            .particles
+            .len()
+            .min(self.boundary_capacity as usize);
+        self.queue.write_buffer(
+            &self.boundary_buffer,
+            0,
+            bytemuck::cast_slice(&self.boundary.particles[..count]),
+        );
+
+        let lookups: Vec<[u32; 2]> = self
+            .boundary
+            .gpu_lookups()
+            .into_iter()
+            .map(|[start, end]| [start.min(count as u32), end.min(count as u32)])
+            .collect();
+        let offset = lookups.len() * LOOKUP_SIZE;
+        let room = (self.lookups_buffer.size() as usize).saturating_sub(offset) / LOOKUP_SIZE;
+        let written = lookups.len().min(room);
+        if written > 0 {
+            self.queue.write_buffer(
+                &self.lookups_buffer,
+                offset as wgpu::BufferAddress,
+                bytemuck::cast_slice(&lookups[..written]),
+            );
+        }
+    }
+
+    fn write_bodies(&self) {
+        self.queue.write_buffer(
+            &self.bodies_buffer,
+            0,
+            bytemuck::cast_slice(&[GpuBodies::new(&self.bodies)]),
+        );
+    }
+
+    // Reads back the force the fluid put on every body particle and moves
+    // the bodies `steps` steps with it. Waits on the GPU, like the iterative
+    // solvers do, so a batch reads back once at its end, with the last
+    // step's push standing in for the whole batch's.
+    fn step_bodies(&mut self, steps: u32) {
+        let samples: Vec<GpuBoundaryParticle> = if self.params.boundary_particles() {
+            let count = self
+                .boundary
+                .particles
+                .len()
+                .min(self.boundary_capacity as usize);
+            pollster::block_on(self.read_buffer(&self.boundary_buffer, count))
+        } else {
+            Vec::new()
+        };
+        for _ in 0..steps {
+            rigid_body::step(&mut self.bodies, &samples, &self.params);
+        }
+    }
+
+    fn write_params(&self) {
+        self.queue.write_buffer(
+            &self.constants_buffer,
+            0,
+            bytemuck::cast_slice(&[self.params]),
+        );
+    }
+
+    // depends on the parameters too, for the phases that leave values out
+    // and for which solver is selected
+    fn write_materials(&self) {
+        self.queue.write_buffer(
+            &self.materials_buffer,
+            0,
+            bytemuck::cast_slice(&[GpuMaterials::new(&self.materials, &self.params)]),
+        );
+    }
+
+    pub async fn read_particles_async(&self) -> Vec<GpuParticle> {
+        self.read_buffer(&self.particle_buffer, self.params.no_particles as usize)
+            .await
+    }
+
+    pub async fn read_predicted_pos_async(&self) -> Vec<[f32; 2]> {
+        self.read_buffer(
+            &self.predicted_pos_buffer,
+            self.params.no_particles as usize,
+        )
+        .await
+    }
+
+    // Copies the first `count` elements of `buffer` into a fresh staging
+    // buffer and maps it. Storage buffers can't be MAP_READ themselves, so the
+    // copy is the only way to see what the shaders wrote.
+    async fn read_buffer<T: Pod>(&self, buffer: &wgpu::Buffer, count: usize) -> Vec<T> {
+        let size = (count * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+        if size == 0 {
+            return Vec::new();
+        }
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Staging Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, size);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging_buffer.slice(..);
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        // native backends only run map callbacks from inside poll, on the web
+        // this is a no-op and the browser resolves the map for us
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .expect("Failed to wait for readback");
+        receiver
+            .receive()
+            .await
+            .expect("Readback channel closed")
+            .expect("Failed to map staging buffer");
+
+        let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging_buffer.unmap();
+        data
+    }
+
+    pub fn compute(&mut self, num_particles: u32) {
+        // Frame pipeline (each pass reads the previous one's output):
+        // hash    -> assign cell id to each particle
+        // sort    -> reorder particle_ids by cell_id (radix on GPU)
+        // clear   -> wipe lookups so empty cells don't keep stale ranges
+        // lookups -> build per-cell [start, end) ranges from sorted ids
+        // density -> per-particle density + pressure from neighbors
+        // normals -> colour-field normals, only when params.surface_tension > 0
+        // forces  -> pressure + viscosity + surface tension + gravity, reads density/pressure
+        // bodies  -> the fluid's push on each body particle, only with bodies and boundary particles
+        // xsph    -> smoothed velocities into xsph_vel, only when params.xsph > 0
+        // rk2     -> Euler predictor, only with the RK2 integrator, which then
+        //            runs hash through xsph a second time at the predicted positions
+        // physics -> integrate velocity/position, writes new predicted_pos
+        // limits  -> per-workgroup max speed and acceleration, only when params.cfl_number > 0
+        //
+        // With the double density relaxation solver, normals/forces/xsph are
+        // replaced by a single relax pass that writes position corrections.
+        // With PCISPH, forces leaves out pressure and correct_pressure
+        // iterates it before physics runs. DFSPH does the same with
+        // solve_dfsph, using the α factors the density pass leaves in scratch.
+        // Position based fluids skip normals/forces/xsph and run solve_pbf,
+        // which moves the particles itself and then smooths and confines
+        // their velocities before physics.
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Compute Encoder"),
+            });
+        let workgroup_count = num_particles.div_ceil(WORKGROUP_SIZE);
+        self.encode_forces(&mut encoder, workgroup_count);
+
+        let solver = Solver::from_u32(self.params.solver);
+        self.solver_stats = None;
+        if solver.is_iterative() {
+            self.queue.submit(std::iter::once(encoder.finish()));
+            self.solver_stats = Some(match solver {
+                Solver::Dfsph => self.solve_dfsph(num_particles, workgroup_count),
+                Solver::PositionBased => self.solve_pbf(num_particles, workgroup_count),
+                _ => self.correct_pressure(num_particles, workgroup_count),
+            });
+            encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Compute Encoder"),
+                });
+        }
+
+        self.encode_integrate(&mut encoder, workgroup_count);
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // Records `steps` whole steps in one encoder and submits them together,
+    // so a frame that catches up several steps costs one submission. Only
+    // for steps that don't wait on the GPU in between: no iterative solver
+    // and no step limits to read back. Bodies hold still within a batch.
+    fn compute_many(&self, num_particles: u32, steps: u32) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Batched Compute Encoder"),
+            });
+        let workgroup_count = num_particles.div_ceil(WORKGROUP_SIZE);
+        for _ in 0..steps {
+            self.encode_forces(&mut encoder, workgroup_count);
+            self.encode_integrate(&mut encoder, workgroup_count);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // everything a step does before the iterative solvers
+    fn encode_forces(&self, encoder: &mut wgpu::CommandEncoder, workgroup_count: u32) {
+        if self.params.active_integrator().is_two_stage() {
+            self.encode_force_passes(encoder, workgroup_count);
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("RK2 Predict Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.rk2_predict);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+        self.encode_force_passes(encoder, workgroup_count);
+    }
+
+    // hash through xsph, one evaluation of the forces
+    fn encode_force_passes(&self, encoder: &mut wgpu::CommandEncoder, workgroup_count: u32) {
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Assign Cells Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.hash);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+
+        self.sorter
+            .sort(encoder, &self.queue, &self.sort_buffers, None);
+
+        // Zero stale (start, end) ranges from last frame. build_lookups only
+        // writes cells that contain a particle this frame, so cells that just
+        // emptied would otherwise keep last frame's range, pointing into
+        // the re-sorted particle_ids array at unrelated particles.
+        // The SPH kernels return 0 outside influence_radius, so most of those stale
+        // fetches contribute nothing. But occasionally a stale fetch hits a particle that is currently
+        // near the query point, double-counting it. The error is invisible
+        // visually, but to the extent of keeping the simulation as accurate as possible I think it is
+        // worth keeping it.
+        // Only the fluid's half, the walls' ranges after it are static.
+        let grid_width = (self.params.width / self.params.cell_size).floor() as usize;
+        let grid_height = (self.params.height / self.params.cell_size).floor() as usize;
+        let fluid_lookups_size = ((grid_width * grid_height * LOOKUP_SIZE) as wgpu::BufferAddress)
+            .min(self.lookups_buffer.size());
+        encoder.clear_buffer(&self.lookups_buffer, 0, Some(fluid_lookups_size));
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Calculate Lookups Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.lookups);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Density Compute Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.density);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+
+        let solver = Solver::from_u32(self.params.solver);
+        let relaxation = solver == Solver::DoubleDensity;
+        let position_based = solver == Solver::PositionBased;
+
+        if relaxation {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Relax Compute Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.relax);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+
+        if !relaxation && !position_based && self.params.surface_tension > 0.0 {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Normals Compute Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.normals);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+
+        if !relaxation && !position_based {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Forces Compute Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.forces);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+
+        if self.params.boundary_particles() && !self.bodies.is_empty() {
+            let count = self
+                .boundary
+                .particles
+                .len()
+                .min(self.boundary_capacity as usize);
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Body Forces Compute Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.body_forces);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups((count as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+
+        if !relaxation && !position_based && self.params.xsph > 0.0 {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("XSPH Compute Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.xsph);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+    }
+
+    // physics and limits, everything a step does after the iterative solvers
+    fn encode_integrate(&self, encoder: &mut wgpu::CommandEncoder, workgroup_count: u32) {
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Physics Compute Pass"),
+                timestamp_writes: None,
+            });
+
+            compute_pass.set_pipeline(&self.pipelines.physics);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+
+        if self.params.cfl_number > 0.0 {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Step Limits Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.step_limits);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+    }
+
+    // Reads back the maxima the step limits pass left in partial_sums and
+    // takes the largest. Waits on the GPU, like the iterative solvers do.
+    fn read_step_limits(&self, num_particles: u32) -> StepLimits {
+        let workgroup_count = num_particles.div_ceil(WORKGROUP_SIZE) as usize;
+        let maxima: Vec<[f32; 2]> =
+            pollster::block_on(self.read_buffer(&self.partial_sums_buffer, workgroup_count));
+        maxima
+            .iter()
+            .fold(StepLimits::default(), |limits, &[speed, acceleration]| {
+                StepLimits {
+                    max_speed: limits.max_speed.max(speed),
+                    max_acceleration: limits.max_acceleration.max(acceleration),
+                }
+            })
+    }
+
+    // PCISPH's prediction-correction loop: predict positions with the current
+    // pressure, correct the pressure from the density error there, recompute
+    // the pressure acceleration.
+    fn correct_pressure(&self, num_particles: u32, workgroup_count: u32) -> SolverStats {
+        let (iterations, density_error) = self.iterate(
+            &[
+                ("PCISPH Predict Pass", &self.pipelines.pcisph_predict),
+                ("PCISPH Pressure Pass", &self.pipelines.pcisph_pressure),
+                ("PCISPH Force Pass", &self.pipelines.pcisph_force),
+            ],
+            self.params.max_density_error,
+            num_particles,
+            workgroup_count,
+        );
+        SolverStats {
+            iterations,
+            density_error,
+            ..Default::default()
+        }
+    }
+
+    // DFSPH: the divergence solve corrects the velocities integrate starts
+    // from, the apply pass folds that in and clears the pressure, then the
+    // density solve builds up the pressure acceleration integrate adds.
+    fn solve_dfsph(&self, num_particles: u32, workgroup_count: u32) -> SolverStats {
+        let (divergence_iterations, divergence_error) = self.iterate(
+            &[
+                ("DFSPH Divergence Pass", &self.pipelines.dfsph_divergence),
+                (
+                    "DFSPH Pressure Acceleration Pass",
+                    &self.pipelines.dfsph_accel,
+                ),
+            ],
+            self.params.max_divergence_error,
+            num_particles,
+            workgroup_count,
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Solver Encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("DFSPH Apply Divergence Pass"),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(&self.pipelines.dfsph_apply);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let (iterations, density_error) = self.iterate(
+            &[
+                ("DFSPH Density Pass", &self.pipelines.dfsph_density),
+                (
+                    "DFSPH Pressure Acceleration Pass",
+                    &self.pipelines.dfsph_accel,
+                ),
+            ],
+            self.params.max_density_error,
+            num_particles,
+            workgroup_count,
+        );
+        SolverStats {
+            iterations,
+            density_error,
+            divergence_iterations,
+            divergence_error,
+        }
+    }
+
+    // Position based fluids: Jacobi iterations of the density constraints on
+    // solver_pos, which the density pass started at predicted_pos, then the
+    // particles are moved there. XSPH and vorticity confinement work on the
+    // velocities that leaves, so they run here rather than before the solve.
+    fn solve_pbf(&self, num_particles: u32, workgroup_count: u32) -> SolverStats {
+        let (iterations, density_error) = self.iterate(
+            &[
+                ("PBF Lambda Pass", &self.pipelines.pbf_lambda),
+                ("PBF Displacement Pass", &self.pipelines.pbf_displacement),
+                ("PBF Apply Pass", &self.pipelines.pbf_apply),
+            ],
+            self.params.max_density_error,
+            num_particles,
+            workgroup_count,
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Solver Encoder"),
+            });
+        let mut passes = vec![("PBF Velocity Pass", &self.pipelines.pbf_velocity)];
+        if self.params.xsph > 0.0 {
+            passes.push(("XSPH Compute Pass", &self.pipelines.xsph));
+        }
+        if self.params.vorticity_confinement > 0.0 {
+            passes.push(("PBF Vorticity Pass", &self.pipelines.pbf_vorticity));
+        }
+        for (label, pipeline) in passes {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some(label),
+                ..Default::default()
+            });
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        SolverStats {
+            iterations,
+            density_error,
+            ..Default::default()
+        }
+    }
+
+    // Runs `passes` in order once per iteration, one of them writing the
+    // per-workgroup error sums, until their mean is at most `tolerance`. From
+    // min_solver_iterations on, the sums are read back after every iteration
+    // to decide whether to stop, so each of those iterations waits on the GPU.
+    // Returns the iterations run and the last error read back.
+    fn iterate(
+        &self,
+        passes: &[(&str, &wgpu::ComputePipeline)],
+        tolerance: f32,
+        num_particles: u32,
+        workgroup_count: u32,
+    ) -> (u32, f32) {
+        let max_iterations = self.params.max_solver_iterations.max(1);
+        let min_iterations = self.params.min_solver_iterations.clamp(1, max_iterations);
+        let mut error = 0.0;
+
+        for iteration in 1..=max_iterations {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Solver Encoder"),
+                });
+            for (label, pipeline) in passes {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some(label),
+                    ..Default::default()
+                });
+                compute_pass.set_pipeline(pipeline);
+                compute_pass.set_bind_group(0, &self.pipelines.bind_group, &[]);
+                compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+            }
+            self.queue.submit(std::iter::once(encoder.finish()));
+
+            if iteration >= min_iterations {
+                let partial_sums: Vec<f32> = pollster::block_on(
+                    self.read_buffer(&self.partial_sums_buffer, workgroup_count as usize),
+                );
+                error = partial_sums.iter().sum::<f32>() / num_particles.max(1) as f32;
+                if error <= tolerance {
+                    return (iteration, error);
+                }
+            }
+        }
+        (max_iterations, error)
+    }
+}
+
+impl Simulation for GpuSimulation {
+    fn step(&mut self) {
+        if self.params.boundary_particles() && self.boundary.set_bodies(&self.bodies, &self.params)
+        {
+            self.write_boundary();
+        }
+        if !self.bodies.is_empty() {
+            self.write_bodies();
+        }
+        self.compute(self.params.no_particles);
+        if !self.bodies.is_empty() {
+            self.step_bodies(1);
+        }
+        self.step_limits = if self.params.cfl_number > 0.0 {
+            Some(self.read_step_limits(self.params.no_particles))
+        } else {
+            None
+        };
+        self.time += self.params.dt as f64;
+    }
+
+    fn step_many(&mut self, steps: u32) {
+        if steps == 0 {
+            return;
+        }
+        let solver = Solver::from_u32(self.params.solver);
+        if solver.is_iterative() || self.params.cfl_number > 0.0 {
+            for _ in 0..steps {
+                self.step();
+            }
+            return;
+        }
+        // bodies move between batches of at most max_substeps, as many as a
+        // frame of the windowed app takes, so a long run does not leave them
+        // frozen in place
+        let batch = if self.bodies.is_empty() {
+            steps
+        } else {
+            self.params.max_substeps.max(1)
+        };
+        let mut remaining = steps;
+        while remaining > 0 {
+            let count = remaining.min(batch);
+            // the bodies may only just have been taken out
+            if self.params.boundary_particles()
+                && self.boundary.set_bodies(&self.bodies, &self.params)
+            {
+                self.write_boundary();
+            }
+            if !self.bodies.is_empty() {
+                self.write_bodies();
+            }
+            self.compute_many(self.params.no_particles, count);
+            if !self.bodies.is_empty() {
+                self.step_bodies(count);
+            }
+            remaining -= count;
+        }
+        self.solver_stats = None;
+        self.step_limits = None;
+        self.time += steps as f64 * self.params.dt as f64;
+    }
+
+    fn set_params(&mut self, mut params: SimulationParams) {
+        // the particle, predicted_pos and sort buffers cannot grow after creation
+        params.no_particles = params.no_particles.min(self.particle_capacity);
+        self.params = params;
+        self.write_params();
+        self.write_materials();
+        if self.boundary.is_stale(&self.params) {
+            self.boundary = BoundaryParticles::new(&self.params);
+            self.write_boundary();
+        }
+    }
+
+    fn params(&self) -> &SimulationParams {
+        &self.params
+    }
+
+    fn set_colliders(&mut self, colliders: Vec<Collider>) {
+        // GpuColliders drops whatever collider::validate would have rejected
+        self.queue.write_buffer(
+            &self.colliders_buffer,
+            0,
+            bytemuck::cast_slice(&[GpuColliders::new(&colliders)]),
+        );
+        self.colliders = colliders;
+    }
+
+    fn colliders(&self) -> &[Collider] {
+        &self.colliders
+    }
+
+    fn set_bodies(&mut self, bodies: Vec<RigidBody>) {
+        self.initial_bodies = bodies.clone();
+        self.bodies = bodies;
+        self.write_bodies();
+    }
+
+    fn bodies(&self) -> &[RigidBody] {
+        &self.bodies
+    }
+
+    fn set_materials(&mut self, materials: Vec<Material>) {
+        let mut particles = self.read_particles();
+        material::assign_phases(&mut particles, &materials);
+        self.queue
+            .write_buffer(&self.particle_buffer, 0, bytemuck::cast_slice(&particles));
+        self.materials = materials;
+        self.write_materials();
+    }
+
+    fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    fn read_particles(&self) -> Vec<GpuParticle> {
+        pollster::block_on(self.read_particles_async())
+    }
+
+    fn read_predicted_pos(&self) -> Vec<[f32; 2]> {
+        pollster::block_on(self.read_predicted_pos_async())
+    }
+
+    fn solver_stats(&self) -> Option<SolverStats> {
+        self.solver_stats
+    }
+
+    fn step_limits(&self) -> Option<StepLimits> {
+        self.step_limits
+    }
+
+    fn time(&self) -> f64 {
+        self.time
+    }
+
+    fn write_state(&mut self, particles: &[GpuParticle], predicted_pos: &[[f32; 2]], time: f64) {
+        // anything past the capacity the buffers were created with is dropped
+        let count = particles
+            .len()
+            .min(predicted_pos.len())
+            .min(self.particle_capacity as usize);
+        self.queue.write_buffer(
+            &self.particle_buffer,
+            0,
+            bytemuck::cast_slice(&particles[..count]),
+        );
+        self.queue.write_buffer(
+            &self.predicted_pos_buffer,
+            0,
+            bytemuck::cast_slice(&predicted_pos[..count]),
+        );
+        self.time = time;
+        self.step_limits = None;
+    }
+
+    fn reset(&mut self) {
+        let particles = GpuParticle::spawn_particles(
+            &self.params,
+            &self.materials,
+            self.params.width as u32,
+            self.params.height as u32,
+        );
+        let predicted_pos: Vec<[f32; 2]> = particles.iter().map(|p| p.pos).collect();
+        self.write_state(&particles, &predicted_pos, 0.0);
+        self.bodies = self.initial_bodies.clone();
+        self.write_bodies();
+        self.solver_stats = None;
+    }
+}
@@ -0,0 +1,146 @@
+pub mod boundary;
+pub mod collider;
+pub mod constants;
+pub mod cpu;
+pub mod gpu;
+pub mod material;
+pub mod parity;
+pub mod rigid_body;
+pub mod scene;
+pub mod snapshot;
+
+use crate::collider::Collider;
+use crate::constants::SimulationParams;
+use crate::gpu::particle::GpuParticle;
+use crate::material::Material;
+use crate::rigid_body::RigidBody;
+use crate::snapshot::Snapshot;
+
+/// How hard the last step's iterative pressure solve worked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SolverStats {
+    pub iterations: u32,
+    /// Mean relative compression, (ρ - ρ₀)/ρ₀ over compressed particles,
+    /// after the last iteration that was checked.
+    pub density_error: f32,
+    /// Iterations of DFSPH's divergence solve, 0 for the other solvers.
+    pub divergence_iterations: u32,
+    /// Mean compression the velocity field would cause over one step,
+    /// Δt (Dρ/Dt)/ρ₀, after the divergence solve's last checked iteration.
+    pub divergence_error: f32,
+}
+
+/// The fastest particle and the largest acceleration of the last step, what
+/// `SimulationParams::cfl_dt` limits the next step's `dt` with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StepLimits {
+    pub max_speed: f32,
+    /// |F/ρ| over the particles, plus the pressure acceleration of the
+    /// iterative solvers. The position-based solvers, which have no forces,
+    /// report the gravity.
+    pub max_acceleration: f32,
+}
+
+/// A particle solver that can be driven without knowing which backend runs it.
+///
+/// `cpu::simulation::CpuSimulation` and `gpu::simulation::GpuSimulation`
+/// implement this, so front-ends (the winit app, tools, tests) only talk to
+/// the trait. Headless users want `GpuSimulation`; `gpu::context::GpuContext`
+/// implements it too by delegating to the `GpuSimulation` it wraps for the
+/// windowed app.
+pub trait Simulation {
+    /// Advances the simulation by one step of `params().dt`.
+    fn step(&mut self);
+
+    /// Advances the simulation by `steps` steps of `params().dt`. Backends
+    /// that can record several steps without reading anything back in
+    /// between send them to the device at once, the rest step one by one.
+    fn step_many(&mut self, steps: u32) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    /// Replaces the parameters used by every following step.
+    fn set_params(&mut self, params: SimulationParams);
+
+    fn params(&self) -> &SimulationParams;
+
+    /// Replaces the obstacles and containers the particles collide with.
+    fn set_colliders(&mut self, colliders: Vec<Collider>);
+
+    fn colliders(&self) -> &[Collider];
+
+    /// Replaces the rigid bodies, which `reset` also goes back to.
+    fn set_bodies(&mut self, bodies: Vec<RigidBody>);
+
+    /// The rigid bodies where the last step left them.
+    fn bodies(&self) -> &[RigidBody];
+
+    /// Replaces the fluid phases and gives every particle the phase of its
+    /// place in the spawn order, which is also what `reset` spawns them with.
+    fn set_materials(&mut self, materials: Vec<Material>);
+
+    fn materials(&self) -> &[Material];
+
+    /// Copies the current particle state out of the backend.
+    fn read_particles(&self) -> Vec<GpuParticle>;
+
+    /// Copies out the positions the next density and force passes will use.
+    fn read_predicted_pos(&self) -> Vec<[f32; 2]>;
+
+    /// Iterations and remaining density error of the last step, `None` when
+    /// the selected solver does not iterate.
+    fn solver_stats(&self) -> Option<SolverStats>;
+
+    /// The speed and acceleration limits of the last step, `None` before the
+    /// first one and while the adaptive timestep is off (`cfl_number` is 0),
+    /// as finding them costs the GPU backend a readback.
+    fn step_limits(&self) -> Option<StepLimits>;
+
+    /// The `dt` the next step should take to stay within the CFL condition,
+    /// the fixed step until a step has reported its limits. `None` while the
+    /// adaptive timestep is off.
+    fn cfl_dt(&self) -> Option<f32> {
+        let params = self.params();
+        if params.cfl_number <= 0.0 {
+            return None;
+        }
+        Some(match self.step_limits() {
+            Some(limits) => params.cfl_dt(limits),
+            None => params.fixed_dt(),
+        })
+    }
+
+    /// Simulated seconds since the last reset, i.e. the sum of every step's `dt`.
+    fn time(&self) -> f64;
+
+    /// Overwrites the particle state and clock, e.g. to resume from a snapshot.
+    fn write_state(&mut self, particles: &[GpuParticle], predicted_pos: &[[f32; 2]], time: f64);
+
+    /// Respawns `params().no_particles` particles in their initial layout and
+    /// phases and puts the bodies back where `set_bodies` placed them.
+    fn reset(&mut self);
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            params: *self.params(),
+            colliders: self.colliders().to_vec(),
+            bodies: self.bodies().to_vec(),
+            materials: self.materials().to_vec(),
+            time: self.time(),
+            particles: self.read_particles(),
+            predicted_pos: self.read_predicted_pos(),
+        }
+    }
+
+    /// Resumes from `snapshot`, whichever backend it was taken on.
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.set_params(snapshot.params);
+        self.set_colliders(snapshot.colliders.clone());
+        self.set_bodies(snapshot.bodies.clone());
+        // before write_state, which puts back the particles' own phases
+        self.set_materials(snapshot.materials.clone());
+        self.write_state(&snapshot.particles, &snapshot.predicted_pos, snapshot.time);
+    }
+}
@@ -1,9 +1,12 @@
-mod constants;
-mod gpu;
 use std::sync::Arc;
 
-use crate::constants::SimulationParams;
-use crate::gpu::context::GpuContext;
+use fluid_simulation::Simulation;
+use fluid_simulation::collider::Collider;
+use fluid_simulation::constants::{SceneAction, SimulationParams};
+use fluid_simulation::gpu::context::GpuContext;
+use fluid_simulation::material::{self, Material};
+use fluid_simulation::rigid_body::{self, RigidBody};
+use fluid_simulation::scene::{self, Scene};
 use winit::application::ApplicationHandler;
 use winit::error::EventLoopError;
 use winit::event::{ElementState, MouseButton, WindowEvent};
@@ -19,7 +22,12 @@ pub struct App {
     last_render_time: std::time::Instant,
     last_frame_time: std::time::Instant,
     frame_rate: u32,
+    // frame time the physics has yet to catch up on
+    accumulator: f32,
     params: SimulationParams,
+    colliders: Vec<Collider>,
+    bodies: Vec<RigidBody>,
+    materials: Vec<Material>,
     attract_held: bool,
     repel_held: bool,
 }
@@ -32,7 +40,11 @@ impl Default for App {
             last_render_time: std::time::Instant::now(),
             last_frame_time: std::time::Instant::now(),
             frame_rate: 0,
+            accumulator: 0.0,
             params: SimulationParams::default(),
+            colliders: Vec::new(),
+            bodies: Vec::new(),
+            materials: Vec::new(),
             attract_held: false,
             repel_held: false,
         }
@@ -54,7 +66,10 @@ impl ApplicationHandler for App {
                     .expect("create window err."),
             );
             self.window = Some(window.clone());
-            let context = pollster::block_on(GpuContext::new(window.clone(), self.params));
+            let mut context = pollster::block_on(GpuContext::new(window.clone(), self.params));
+            context.set_colliders(std::mem::take(&mut self.colliders));
+            context.set_bodies(std::mem::take(&mut self.bodies));
+            context.set_materials(std::mem::take(&mut self.materials));
             self.gpu_context = Some(context);
             window.request_redraw();
         }
@@ -78,14 +93,12 @@ impl ApplicationHandler for App {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             }
-            WindowEvent::MouseInput { state, button, .. } => {
-                if !egui_wants_pointer {
-                    let pressed = state == ElementState::Pressed;
-                    match button {
-                        MouseButton::Left => self.attract_held = pressed,
-                        MouseButton::Right => self.repel_held = pressed,
-                        _ => {}
-                    }
+            WindowEvent::MouseInput { state, button, .. } if !egui_wants_pointer => {
+                let pressed = state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.attract_held = pressed,
+                    MouseButton::Right => self.repel_held = pressed,
+                    _ => {}
                 }
             }
             WindowEvent::Resized(physical_size) => {
@@ -93,7 +106,7 @@ impl ApplicationHandler for App {
                     gpu.resize(physical_size);
                     self.params.width = physical_size.width as f32;
                     self.params.height = physical_size.height as f32;
-                    gpu.update_params(&self.params);
+                    gpu.set_params(self.params);
                 }
             }
             WindowEvent::RedrawRequested => {
@@ -102,43 +115,108 @@ impl ApplicationHandler for App {
                 self.last_frame_time = now;
                 if let (Some(gpu), Some(window)) = (&mut self.gpu_context, self.window.as_ref()) {
                     self.params.mouse_strength = 0.0;
-                    if self.attract_held || self.repel_held {
-                        if let Some(pos) = gpu.egui_ctx.pointer_latest_pos() {
-                            let scale = window.scale_factor() as f32;
-                            self.params.mouse_pos = [pos.x * scale, pos.y * scale];
-                            // attract takes precedence if both held
-                            self.params.mouse_strength = if self.attract_held {
-                                MOUSE_FORCE
-                            } else {
-                                -MOUSE_FORCE
-                            };
-                        }
+                    if (self.attract_held || self.repel_held)
+                        && let Some(pos) = gpu.egui_ctx.pointer_latest_pos()
+                    {
+                        let scale = window.scale_factor() as f32;
+                        self.params.mouse_pos = [pos.x * scale, pos.y * scale];
+                        // attract takes precedence if both held
+                        self.params.mouse_strength = if self.attract_held {
+                            MOUSE_FORCE
+                        } else {
+                            -MOUSE_FORCE
+                        };
                     }
 
-                    let mut time_to_simulate = delta_time.min(0.1);
-
-                    let max_step_dt = 1.0 / 120.0;
-                    let max_substeps = 1;
+                    // The physics runs at physics_rate whatever the frame
+                    // rate: a frame takes as many whole steps as fit in the
+                    // time it has to cover and leaves the rest for the next.
+                    self.accumulator += delta_time;
+                    let max_substeps = self.params.max_substeps.max(1);
                     let mut substeps = 0;
-                    while time_to_simulate > 0.0 && substeps < max_substeps {
-                        let step_dt = time_to_simulate.min(max_step_dt);
+                    if self.params.cfl_number > 0.0 {
+                        // each adaptive step waits on the GPU for the limits
+                        // the next one is sized by
+                        loop {
+                            let step_dt = gpu.cfl_dt().unwrap_or(self.params.fixed_dt());
+                            if substeps >= max_substeps || self.accumulator < step_dt {
+                                break;
+                            }
+                            self.params.dt = step_dt;
+                            gpu.set_params(self.params);
+                            gpu.step();
+                            self.accumulator -= step_dt;
+                            substeps += 1;
+                        }
+                    } else {
+                        let step_dt = self.params.fixed_dt();
+                        substeps = ((self.accumulator / step_dt) as u32).min(max_substeps);
                         self.params.dt = step_dt;
-                        gpu.update_params(&self.params);
-                        gpu.compute(self.params.no_particles);
-                        time_to_simulate -= step_dt;
-                        substeps += 1;
+                        gpu.set_params(self.params);
+                        gpu.step_many(substeps);
+                        self.accumulator -= substeps as f32 * step_dt;
+                    }
+                    // When a frame can't keep up even at the cap, the backlog
+                    // would only grow and every frame would take the most
+                    // steps, which makes frames slower still. Dropping it
+                    // lets the simulation run slow instead.
+                    if substeps == max_substeps && self.accumulator >= self.params.dt {
+                        self.accumulator %= self.params.dt;
                     }
 
                     let num_particles = self.params.no_particles;
+                    let solver_stats = gpu.solver_stats();
+                    let step_limits = gpu.step_limits();
+                    let dt = self.params.dt;
+                    let outlines: Vec<Vec<_>> = gpu.bodies().iter().map(|b| b.outline()).collect();
+                    let scale = window.scale_factor() as f32;
+                    let (solver, boundary) = (self.params.solver, self.params.boundary);
                     let params = &mut self.params;
+                    let mut scene_action = None;
                     match gpu.render(
                         window,
                         |ctx| {
+                            // the bodies are drawn as outlines over the
+                            // particles, in points rather than pixels
+                            let painter = ctx.layer_painter(egui::LayerId::background());
+                            for outline in outlines {
+                                let points = outline
+                                    .into_iter()
+                                    .map(|p| egui::pos2(p.x / scale, p.y / scale))
+                                    .collect();
+                                painter.add(egui::Shape::closed_line(
+                                    points,
+                                    egui::Stroke::new(2.0, egui::Color32::WHITE),
+                                ));
+                            }
                             egui::Window::new("Parameters")
                                 .anchor(egui::Align2::LEFT_TOP, egui::vec2(8.0, 8.0))
                                 .resizable(false)
                                 .show(ctx, |ui| {
-                                    params.ui(ui);
+                                    scene_action = params.ui(ui);
+                                    match step_limits {
+                                        Some(limits) => ui.label(format!(
+                                            "Timestep: {:.3} ms, max speed {:.0}, max acceleration {:.0}",
+                                            dt * 1000.0,
+                                            limits.max_speed,
+                                            limits.max_acceleration
+                                        )),
+                                        None => ui.label(format!("Timestep: {:.3} ms", dt * 1000.0)),
+                                    };
+                                    if let Some(stats) = solver_stats {
+                                        ui.label(format!(
+                                            "Solver: {} iterations, density error {:.2}%",
+                                            stats.iterations,
+                                            stats.density_error * 100.0
+                                        ));
+                                        if stats.divergence_iterations > 0 {
+                                            ui.label(format!(
+                                                "Divergence: {} iterations, error {:.3}%",
+                                                stats.divergence_iterations,
+                                                stats.divergence_error * 100.0
+                                            ));
+                                        }
+                                    }
                                 });
                         },
                         num_particles,
@@ -155,6 +233,18 @@ impl ApplicationHandler for App {
                         }
                         Err(e) => eprintln!("{:?}", e),
                     }
+                    // a solver or boundary that cannot run what is loaded
+                    // is refused rather than quietly getting it wrong
+                    if let Err(e) = material::check_solver(gpu.materials(), &self.params)
+                        .and_then(|()| rigid_body::check_params(gpu.bodies(), &self.params))
+                    {
+                        self.params.solver = solver;
+                        self.params.boundary = boundary;
+                        SimulationParams::set_scene_status(&gpu.egui_ctx, e);
+                    }
+                    if let Some(action) = scene_action {
+                        run_scene_action(gpu, &mut self.params, action);
+                    }
                 }
                 self.frame_rate += 1;
                 let elapsed = self.last_render_time.elapsed().as_secs_f32();
@@ -175,10 +265,48 @@ impl ApplicationHandler for App {
     }
 }
 
+// The panel's Save and Load buttons. A scene is the parameters and whatever
+// lives on the context, so both go through here rather than the panel.
+fn run_scene_action(gpu: &mut GpuContext, params: &mut SimulationParams, action: SceneAction) {
+    let status = match action {
+        SceneAction::Save(path) => {
+            let text = scene::to_toml(params, gpu.colliders(), gpu.bodies(), gpu.materials());
+            match std::fs::write(&path, text) {
+                Ok(()) => format!("Saved {}", path),
+                Err(e) => format!("Save failed: {}", e),
+            }
+        }
+        SceneAction::Load(path) => match Scene::load(&path) {
+            Ok(scene) => {
+                *params = params.loaded_from(scene.params);
+                gpu.set_params(*params);
+                gpu.set_colliders(scene.colliders);
+                gpu.set_bodies(scene.bodies);
+                gpu.set_materials(scene.materials);
+                format!("Loaded {}", path)
+            }
+            Err(e) => format!("Load failed: {}", e),
+        },
+    };
+    SimulationParams::set_scene_status(&gpu.egui_ctx, status);
+}
+
 fn main() -> Result<(), EventLoopError> {
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
     let mut app = App::default();
+    // optional scene file: fluid-simulation [scene.toml]
+    if let Some(path) = std::env::args().nth(1) {
+        match Scene::load(&path) {
+            Ok(scene) => {
+                app.params = scene.params;
+                app.colliders = scene.colliders;
+                app.bodies = scene.bodies;
+                app.materials = scene.materials;
+            }
+            Err(e) => eprintln!("Failed to load scene {}: {}, using defaults", path, e),
+        }
+    }
     event_loop.run_app(&mut app)
 }
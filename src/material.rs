@@ -0,0 +1,271 @@
+use crate::constants::{ParamEnum, SimulationParams, Solver};
+use crate::gpu::particle::GpuParticle;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+// the uniform the shaders read is fixed size, see GpuMaterials
+pub const MAX_MATERIALS: usize = 8;
+
+// what the particles are drawn in when a material does not say
+pub const DEFAULT_COLOUR: [f32; 3] = [0.1, 0.5, 1.0];
+
+// Materials live in scene files next to the parameters, one table per fluid
+// phase, e.g. water over a lighter oil that will rise through it:
+//
+//     [[materials]]
+//     colour = [0.1, 0.5, 1.0]
+//
+//     [[materials]]
+//     mass = 0.6
+//     rest_density = 0.054
+//     viscosity = 2.0
+//     colour = [0.9, 0.7, 0.1]
+//
+// Any of mass, rest_density and viscosity left out is taken from the
+// parameters. Scaling mass and rest_density together keeps the rest spacing
+// √(m/ρ₀) of the default fluid. A particle's phase is its index into the list.
+
+/// One fluid phase: what its particles weigh, the density they settle at,
+/// how thick they are and what colour they are drawn in.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mass: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rest_density: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub viscosity: Option<f32>,
+    /// Linear RGB, each channel from 0 to 1.
+    #[serde(default = "default_colour")]
+    pub colour: [f32; 3],
+    /// How much of the spawn block this phase gets relative to the others.
+    /// The phases fill it in order from the top.
+    #[serde(default = "default_share")]
+    pub share: f32,
+}
+
+fn default_colour() -> [f32; 3] {
+    DEFAULT_COLOUR
+}
+
+fn default_share() -> f32 {
+    1.0
+}
+
+// the part of a scene file this module reads, the rest is ignored
+#[derive(Default, Serialize, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    materials: Vec<Material>,
+}
+
+impl Default for Material {
+    /// The parameters' fluid in the colour particles had before materials.
+    fn default() -> Self {
+        Self {
+            mass: None,
+            rest_density: None,
+            viscosity: None,
+            colour: DEFAULT_COLOUR,
+            share: default_share(),
+        }
+    }
+}
+
+impl Material {
+    pub fn mass(&self, params: &SimulationParams) -> f32 {
+        self.mass.unwrap_or(params.mass)
+    }
+
+    pub fn rest_density(&self, params: &SimulationParams) -> f32 {
+        self.rest_density.unwrap_or(params.rest_density)
+    }
+
+    pub fn viscosity(&self, params: &SimulationParams) -> f32 {
+        self.viscosity.unwrap_or(params.viscosity)
+    }
+
+    /// Every material of a scene file, none if it has no `[[materials]]`.
+    pub fn load_scene(path: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Vec<Self>, String> {
+        let scene: Scene = toml::from_str(text).map_err(|e| e.to_string())?;
+        validate(&scene.materials)?;
+        Ok(scene.materials)
+    }
+
+    /// The `[[materials]]` tables for `materials`, to append to a scene file.
+    pub fn to_toml(materials: &[Self]) -> String {
+        // nothing rather than an empty `materials = []`, which would land in
+        // whichever table comes before it
+        if materials.is_empty() {
+            return String::new();
+        }
+        toml::to_string(&Scene {
+            materials: materials.to_vec(),
+        })
+        .expect("materials always serialize")
+    }
+}
+
+/// Checks that `materials` fit in the GPU's fixed size uniform and that every
+/// phase has a positive mass and rest density and gets some of the particles.
+pub fn validate(materials: &[Material]) -> Result<(), String> {
+    if materials.len() > MAX_MATERIALS {
+        return Err(format!(
+            "{} materials, at most {} are supported",
+            materials.len(),
+            MAX_MATERIALS
+        ));
+    }
+    for material in materials {
+        if material.mass.is_some_and(|mass| mass <= 0.0) {
+            return Err("a material needs a mass above 0".to_string());
+        }
+        if material.rest_density.is_some_and(|density| density <= 0.0) {
+            return Err("a material needs a rest_density above 0".to_string());
+        }
+        if material.viscosity.is_some_and(|viscosity| viscosity < 0.0) {
+            return Err("a material cannot have a negative viscosity".to_string());
+        }
+        if material.share < 0.0 {
+            return Err("a material cannot have a negative share".to_string());
+        }
+    }
+    if !materials.is_empty() && materials.iter().all(|material| material.share == 0.0) {
+        return Err("at least one material needs a share above 0".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that `params.solver` can run `materials`. Double density
+/// relaxation works on unitless neighbour counts rather than masses and
+/// densities, it would silently run every phase as the same fluid, so a
+/// material with its own mass, rest density or viscosity is refused with it.
+/// Colour-only materials suit any solver.
+pub fn check_solver(materials: &[Material], params: &SimulationParams) -> Result<(), String> {
+    let solver = Solver::from_u32(params.solver);
+    let physical = materials.iter().any(|material| {
+        material.mass.is_some() || material.rest_density.is_some() || material.viscosity.is_some()
+    });
+    if physical && solver == Solver::DoubleDensity {
+        return Err(format!(
+            "materials with their own mass, rest_density or viscosity do not work with the {} solver",
+            solver.name()
+        ));
+    }
+    Ok(())
+}
+
+/// The phase of the `index`th of `count` spawned particles: the materials'
+/// shares are laid out one after the other over the spawn order, which
+/// fills the block row by row from the top. Always 0 without materials.
+pub fn phase(index: usize, count: usize, materials: &[Material]) -> u32 {
+    let total: f32 = materials.iter().map(|material| material.share).sum();
+    if materials.len() < 2 || total <= 0.0 || count == 0 {
+        return 0;
+    }
+    let position = (index as f32 + 0.5) / count as f32 * total;
+    let mut end = 0.0;
+    for (phase, material) in materials.iter().enumerate() {
+        end += material.share;
+        if position < end {
+            return phase as u32;
+        }
+    }
+    // only rounding gets here, the last phase with a share takes it
+    materials
+        .iter()
+        .rposition(|material| material.share > 0.0)
+        .unwrap_or(0) as u32
+}
+
+/// Gives every particle the phase its index has in the spawn order.
+pub fn assign_phases(particles: &mut [GpuParticle], materials: &[Material]) {
+    let count = particles.len();
+    for (index, particle) in particles.iter_mut().enumerate() {
+        particle.phase = phase(index, count, materials);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shares(shares: &[f32]) -> Vec<Material> {
+        shares
+            .iter()
+            .map(|&share| Material {
+                share,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn phases(count: usize, materials: &[Material]) -> Vec<u32> {
+        (0..count)
+            .map(|index| phase(index, count, materials))
+            .collect()
+    }
+
+    #[test]
+    fn validate_refuses_what_cannot_run() {
+        assert!(validate(&[]).is_ok());
+        assert!(validate(&shares(&[1.0, 0.0])).is_ok());
+        assert!(validate(&shares(&[1.0; MAX_MATERIALS + 1])).is_err());
+        assert!(validate(&shares(&[0.0, 0.0])).is_err());
+        assert!(validate(&shares(&[1.0, -1.0])).is_err());
+        for invalid in [
+            Material {
+                mass: Some(0.0),
+                ..Default::default()
+            },
+            Material {
+                rest_density: Some(-0.1),
+                ..Default::default()
+            },
+            Material {
+                viscosity: Some(-1.0),
+                ..Default::default()
+            },
+        ] {
+            assert!(validate(&[invalid.clone()]).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn phases_follow_the_shares_in_spawn_order() {
+        assert_eq!(phases(4, &shares(&[1.0, 1.0])), [0, 0, 1, 1]);
+        assert_eq!(phases(6, &shares(&[2.0, 1.0])), [0, 0, 0, 0, 1, 1]);
+        // a phase without a share gets no particles
+        assert_eq!(phases(4, &shares(&[1.0, 0.0, 1.0])), [0, 0, 2, 2]);
+        assert_eq!(phases(3, &shares(&[1.0])), [0, 0, 0]);
+        assert_eq!(phases(3, &[]), [0, 0, 0]);
+    }
+
+    #[test]
+    fn only_double_density_refuses_physical_materials() {
+        let physical = [Material {
+            mass: Some(0.6),
+            ..Default::default()
+        }];
+        let colour_only = shares(&[1.0, 2.0]);
+        for &solver in Solver::ALL {
+            let params = SimulationParams {
+                solver: solver.to_u32(),
+                ..Default::default()
+            };
+            assert_eq!(
+                check_solver(&physical, &params).is_err(),
+                solver == Solver::DoubleDensity,
+                "{:?}",
+                solver
+            );
+            assert!(check_solver(&colour_only, &params).is_ok());
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::Simulation;
+use crate::constants::Solver;
+use crate::gpu::particle::GpuParticle;
+use glam::Vec2;
+
+/// Largest divergence allowed per field. Density, pressure and force are
+/// relative to the largest magnitude that field reaches on the reference
+/// backend in the same step, position to the influence radius: particles
+/// sit hundreds of pixels from the origin, so relative to that a drift of
+/// several pixels would still pass.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerances {
+    pub density: f32,
+    pub pressure: f32,
+    pub force: f32,
+    pub position: f32,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        // the two backends only differ in float summation order and
+        // fused-multiply-add contraction, which stays well below this for
+        // the first handful of steps
+        Self {
+            density: 1e-3,
+            pressure: 1e-3,
+            force: 1e-3,
+            position: 1e-3,
+        }
+    }
+}
+
+impl Tolerances {
+    /// The defaults, with more room for the pressure of the iterative
+    /// solvers. PCISPH and DFSPH sum their pressure from density errors of a
+    /// percent or less, so the backends' tiny density differences come out
+    /// tens of times larger in it, and a solve that stops one sweep earlier
+    /// on one backend adds a whole sweep's increment. Their density, force
+    /// and position still get the default tolerance, which catches a
+    /// pressure that is wrong rather than noisy.
+    pub fn for_solver(solver: Solver) -> Self {
+        let defaults = Self::default();
+        match solver {
+            Solver::Pcisph | Solver::Dfsph => Self {
+                pressure: 5e-2,
+                ..defaults
+            },
+            _ => defaults,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Divergence {
+    pub max_abs: f32,
+    pub mean_abs: f32,
+    /// `max_abs` divided by the field's scale, see `Tolerances`.
+    pub max_rel: f32,
+    /// Index of the particle where `max_abs` happened.
+    pub worst_particle: usize,
+}
+
+impl Divergence {
+    fn measure(scale: f32, diffs: impl Iterator<Item = f32>) -> Self {
+        let mut divergence = Divergence::default();
+        let mut total = 0.0f64;
+        let mut count = 0usize;
+        for (i, diff) in diffs.enumerate() {
+            total += diff as f64;
+            count += 1;
+            if diff > divergence.max_abs {
+                divergence.max_abs = diff;
+                divergence.worst_particle = i;
+            }
+        }
+        if count > 0 {
+            divergence.mean_abs = (total / count as f64) as f32;
+        }
+        divergence.max_rel = divergence.max_abs / scale.max(f32::EPSILON);
+        divergence
+    }
+}
+
+fn largest(values: impl Iterator<Item = f32>) -> f32 {
+    values.fold(0.0f32, f32::max)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StepReport {
+    pub step: u32,
+    pub density: Divergence,
+    pub pressure: Divergence,
+    pub force: Divergence,
+    pub position: Divergence,
+}
+
+impl StepReport {
+    /// Compares every particle of `other` with `reference`, positions
+    /// against `influence_radius`.
+    pub fn compare(
+        step: u32,
+        reference: &[GpuParticle],
+        other: &[GpuParticle],
+        influence_radius: f32,
+    ) -> Self {
+        let pairs = || reference.iter().zip(other);
+        let vec_diff = |a: [f32; 2], b: [f32; 2]| Vec2::from(a).distance(Vec2::from(b));
+        let vec_len = |a: [f32; 2]| Vec2::from(a).length();
+        Self {
+            step,
+            density: Divergence::measure(
+                largest(reference.iter().map(|p| p.density.abs())),
+                pairs().map(|(a, b)| (a.density - b.density).abs()),
+            ),
+            pressure: Divergence::measure(
+                largest(reference.iter().map(|p| p.pressure.abs())),
+                pairs().map(|(a, b)| (a.pressure - b.pressure).abs()),
+            ),
+            force: Divergence::measure(
+                largest(reference.iter().map(|p| vec_len(p.force))),
+                pairs().map(|(a, b)| vec_diff(a.force, b.force)),
+            ),
+            position: Divergence::measure(
+                influence_radius,
+                pairs().map(|(a, b)| vec_diff(a.pos, b.pos)),
+            ),
+        }
+    }
+
+    pub fn within(&self, tolerances: &Tolerances) -> bool {
+        self.density.max_rel <= tolerances.density
+            && self.pressure.max_rel <= tolerances.pressure
+            && self.force.max_rel <= tolerances.force
+            && self.position.max_rel <= tolerances.position
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParityReport {
+    pub tolerances: Tolerances,
+    pub steps: Vec<StepReport>,
+}
+
+impl ParityReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.within(&self.tolerances))
+    }
+
+    pub fn first_failure(&self) -> Option<&StepReport> {
+        self.steps
+            .iter()
+            .find(|step| !step.within(&self.tolerances))
+    }
+}
+
+/// Seeds `other` with `reference`'s exact state, then steps both `steps`
+/// times and compares every field after each step.
+///
+/// Any two backends work, but the intended use is the CPU solver as the
+/// reference and a `GpuSimulation` (usually on a fallback adapter) as `other`,
+/// to check the WGSL passes still match their Rust counterparts.
+pub fn run(
+    reference: &mut impl Simulation,
+    other: &mut impl Simulation,
+    steps: u32,
+    tolerances: Tolerances,
+) -> ParityReport {
+    other.restore(&reference.snapshot());
+
+    let mut report = ParityReport {
+        tolerances,
+        steps: Vec::with_capacity(steps as usize),
+    };
+    for step in 1..=steps {
+        reference.step();
+        other.step();
+        report.steps.push(StepReport::compare(
+            step,
+            &reference.read_particles(),
+            &other.read_particles(),
+            reference.params().influence_radius,
+        ));
+    }
+    report
+}
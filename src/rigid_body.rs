@@ -0,0 +1,492 @@
+use crate::boundary::SAMPLES_PER_INFLUENCE_RADIUS;
+use crate::collider;
+use crate::constants::SimulationParams;
+use crate::cpu::kernels::smoothing_kernel;
+use crate::gpu::particle::GpuBoundaryParticle;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+
+// the uniform the shaders read is fixed size, see GpuBodies
+pub const MAX_BODIES: usize = 16;
+pub const MAX_BODY_POINTS: usize = 64;
+// surface particles of every body together, the GPU boundary buffer keeps
+// this much room after the walls
+pub const MAX_BODY_SAMPLES: usize = 8192;
+// GpuBoundaryParticle::body of the wall particles
+pub const NO_BODY: u32 = u32::MAX;
+
+// central difference step for the SDF gradient, in pixels, as in collider.rs
+const GRADIENT_STEP: f32 = 0.1;
+// segments of a circle's outline when it is drawn
+const CIRCLE_OUTLINE_SEGMENTS: usize = 32;
+
+// Bodies live in scene files next to the parameters and colliders, e.g.
+//
+//     [[bodies]]
+//     shape = "box"
+//     half_size = [60.0, 15.0]
+//     position = [850.0, 300.0]
+//     density = 0.05
+//
+// Density is in the same units as rest_density, so a body lighter than the
+// fluid floats and a heavier one sinks.
+
+/// The shape of a rigid body in its own frame, centred on its centre of mass.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum BodyShape {
+    Circle {
+        radius: f32,
+    },
+    Box {
+        half_size: [f32; 2],
+    },
+    /// A convex polygon, either winding, at least three points. Recentred on
+    /// its centroid when loaded.
+    Polygon {
+        points: Vec<[f32; 2]>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RigidBody {
+    #[serde(flatten)]
+    pub shape: BodyShape,
+    pub position: [f32; 2],
+    /// Radians, clockwise on screen since y points down.
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default)]
+    pub velocity: [f32; 2],
+    #[serde(default)]
+    pub angular_velocity: f32,
+    #[serde(default)]
+    pub density: f32,
+    /// Moves with its initial velocity and spin whatever pushes on it, e.g.
+    /// a paddle wheel. The fluid is still pushed by it.
+    #[serde(default)]
+    pub kinematic: bool,
+}
+
+// the part of a scene file this module reads, the rest is ignored
+#[derive(Default, Serialize, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    bodies: Vec<RigidBody>,
+}
+
+impl BodyShape {
+    fn area(&self) -> f32 {
+        match self {
+            BodyShape::Circle { radius } => PI * radius * radius,
+            BodyShape::Box { half_size } => 4.0 * half_size[0] * half_size[1],
+            BodyShape::Polygon { points } => {
+                let mut area = 0.0;
+                for_each_edge(points, |a, b| area += a.perp_dot(b));
+                (area * 0.5).abs()
+            }
+        }
+    }
+
+    // ∫ r² dA about the origin
+    fn second_moment(&self) -> f32 {
+        match self {
+            BodyShape::Circle { radius } => 0.5 * PI * radius.powi(4),
+            BodyShape::Box { half_size } => {
+                self.area() * (half_size[0] * half_size[0] + half_size[1] * half_size[1]) / 3.0
+            }
+            BodyShape::Polygon { points } => {
+                let mut moment = 0.0;
+                for_each_edge(points, |a, b| {
+                    moment += a.perp_dot(b) * (a.dot(a) + a.dot(b) + b.dot(b));
+                });
+                (moment / 12.0).abs()
+            }
+        }
+    }
+
+    // the corners in the body's frame, none for a circle
+    fn vertices(&self) -> Vec<Vec2> {
+        match self {
+            BodyShape::Circle { .. } => Vec::new(),
+            BodyShape::Box { half_size } => {
+                let [x, y] = *half_size;
+                vec![
+                    Vec2::new(-x, -y),
+                    Vec2::new(x, -y),
+                    Vec2::new(x, y),
+                    Vec2::new(-x, y),
+                ]
+            }
+            BodyShape::Polygon { points } => points.iter().map(|&p| Vec2::from(p)).collect(),
+        }
+    }
+
+    /// Signed distance from `local`, in the body's frame, to the surface.
+    pub fn signed_distance(&self, local: Vec2) -> f32 {
+        match self {
+            BodyShape::Circle { radius } => local.length() - radius,
+            BodyShape::Box { half_size } => {
+                let d = local.abs() - Vec2::from(*half_size);
+                d.max(Vec2::ZERO).length() + d.x.max(d.y).min(0.0)
+            }
+            BodyShape::Polygon { points } => collider::polygon_distance(points, local),
+        }
+    }
+
+    // points along the surface about `spacing` apart, in the body's frame
+    fn surface_samples(&self, spacing: f32) -> Vec<Vec2> {
+        if let BodyShape::Circle { radius } = self {
+            let count = ((2.0 * PI * radius / spacing).ceil() as usize).max(3);
+            return (0..count)
+                .map(|i| Vec2::from_angle(2.0 * PI * i as f32 / count as f32) * *radius)
+                .collect();
+        }
+        let vertices = self.vertices();
+        let mut samples = Vec::new();
+        for (i, &start) in vertices.iter().enumerate() {
+            let end = vertices[(i + 1) % vertices.len()];
+            let count = ((end - start).length() / spacing).ceil().max(1.0) as usize;
+            samples.extend((0..count).map(|k| start.lerp(end, k as f32 / count as f32)));
+        }
+        samples
+    }
+}
+
+impl RigidBody {
+    pub fn mass(&self) -> f32 {
+        self.density * self.shape.area()
+    }
+
+    /// Moment of inertia about the centre of mass.
+    pub fn inertia(&self) -> f32 {
+        self.density * self.shape.second_moment()
+    }
+
+    fn to_local(&self, pos: Vec2) -> Vec2 {
+        Vec2::from_angle(-self.angle).rotate(pos - Vec2::from(self.position))
+    }
+
+    fn to_world(&self, local: Vec2) -> Vec2 {
+        Vec2::from(self.position) + Vec2::from_angle(self.angle).rotate(local)
+    }
+
+    /// Signed distance from `pos` to the body's surface.
+    pub fn signed_distance(&self, pos: Vec2) -> f32 {
+        self.shape.signed_distance(self.to_local(pos))
+    }
+
+    /// Velocity of the body's material at `pos`, v + ω × r.
+    pub fn surface_velocity(&self, pos: Vec2) -> Vec2 {
+        Vec2::from(self.velocity) + self.angular_velocity * (pos - Vec2::from(self.position)).perp()
+    }
+
+    /// The body's outline in world space, for drawing.
+    pub fn outline(&self) -> Vec<Vec2> {
+        let local = match &self.shape {
+            BodyShape::Circle { radius } => (0..CIRCLE_OUTLINE_SEGMENTS)
+                .map(|i| {
+                    Vec2::from_angle(2.0 * PI * i as f32 / CIRCLE_OUTLINE_SEGMENTS as f32) * *radius
+                })
+                .collect(),
+            shape => shape.vertices(),
+        };
+        local.into_iter().map(|p| self.to_world(p)).collect()
+    }
+
+    /// Akinci boundary particles along the body's surface in its own frame,
+    /// each with the volume 1/Σ W of its own row like the walls. Rigid
+    /// motion does not change them, see `BoundaryParticles::set_bodies`.
+    pub fn local_boundary_particles(&self, params: &SimulationParams) -> Vec<(Vec2, f32)> {
+        let samples = self
+            .shape
+            .surface_samples(params.influence_radius / SAMPLES_PER_INFLUENCE_RADIUS);
+        samples
+            .iter()
+            .map(|&pos| {
+                // the sample itself is always counted, so the sum is never 0
+                let kernel_sum: f32 = samples
+                    .iter()
+                    .map(|&other| smoothing_kernel(pos, other, params))
+                    .sum();
+                (pos, 1.0 / kernel_sum)
+            })
+            .collect()
+    }
+
+    /// `local` moved to where the body is now.
+    pub fn place_boundary_particles(
+        &self,
+        index: u32,
+        local: &[(Vec2, f32)],
+    ) -> impl Iterator<Item = GpuBoundaryParticle> {
+        local.iter().map(move |&(pos, volume)| GpuBoundaryParticle {
+            pos: self.to_world(pos).into(),
+            volume,
+            body: index,
+            force: [0.0, 0.0],
+            _padding: [0.0, 0.0],
+        })
+    }
+
+    /// Advances the body by one step under gravity and `force`/`torque`, the
+    /// fluid's push on it, then keeps it inside the window.
+    pub fn integrate(&mut self, force: Vec2, torque: f32, params: &SimulationParams) {
+        let dt = params.dt;
+        if !self.kinematic {
+            let velocity =
+                Vec2::from(self.velocity) + (force / self.mass() + Vec2::from(params.gravity)) * dt;
+            self.velocity = velocity.into();
+            self.angular_velocity += torque / self.inertia() * dt;
+        }
+        self.position = (Vec2::from(self.position) + Vec2::from(self.velocity) * dt).into();
+        self.angle += self.angular_velocity * dt;
+        if !self.kinematic {
+            self.contain(params);
+        }
+    }
+
+    // Pushes the body back inside each wall it crossed and applies the
+    // contact impulse there, with the colliders' restitution and Coulomb
+    // friction
+    fn contain(&mut self, params: &SimulationParams) {
+        let walls = [
+            (Vec2::X, 0.0),
+            (Vec2::NEG_X, -params.width),
+            (Vec2::Y, 0.0),
+            (Vec2::NEG_Y, -params.height),
+        ];
+        for (normal, offset) in walls {
+            // the deepest point past the wall, n · p < offset
+            let deepest = match &self.shape {
+                BodyShape::Circle { radius } => Vec2::from(self.position) - normal * *radius,
+                shape => shape
+                    .vertices()
+                    .into_iter()
+                    .map(|v| self.to_world(v))
+                    .min_by(|a, b| a.dot(normal).total_cmp(&b.dot(normal)))
+                    .unwrap_or(Vec2::from(self.position)),
+            };
+            let depth = offset - deepest.dot(normal);
+            if depth <= 0.0 {
+                continue;
+            }
+            self.position = (Vec2::from(self.position) + normal * depth).into();
+            self.apply_contact(deepest + normal * depth, normal, params);
+        }
+    }
+
+    fn apply_contact(&mut self, point: Vec2, normal: Vec2, params: &SimulationParams) {
+        let inverse_mass = 1.0 / self.mass();
+        let inverse_inertia = 1.0 / self.inertia();
+        let r = point - Vec2::from(self.position);
+        let contact_velocity = self.surface_velocity(point);
+        let normal_speed = contact_velocity.dot(normal);
+        if normal_speed >= 0.0 {
+            return;
+        }
+        let effective =
+            |direction: Vec2| inverse_mass + r.perp_dot(direction).powi(2) * inverse_inertia;
+        let mut apply = |direction: Vec2, impulse: f32| {
+            let velocity = Vec2::from(self.velocity) + direction * impulse * inverse_mass;
+            self.velocity = velocity.into();
+            self.angular_velocity += r.perp_dot(direction) * impulse * inverse_inertia;
+        };
+
+        let impulse = -(1.0 + params.collider_restitution) * normal_speed / effective(normal);
+        apply(normal, impulse);
+
+        let tangent_velocity = contact_velocity - normal * normal_speed;
+        let tangent_speed = tangent_velocity.length();
+        if tangent_speed > 0.00001 {
+            let tangent = tangent_velocity / tangent_speed;
+            let friction =
+                (tangent_speed / effective(tangent)).min(params.collider_friction * impulse);
+            apply(tangent, -friction);
+        }
+    }
+
+    /// Every body of a scene file, none if it has no `[[bodies]]`.
+    pub fn load_scene(path: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Vec<Self>, String> {
+        let mut scene: Scene = toml::from_str(text).map_err(|e| e.to_string())?;
+        validate(&scene.bodies)?;
+        for body in &mut scene.bodies {
+            body.recentre();
+        }
+        Ok(scene.bodies)
+    }
+
+    /// The `[[bodies]]` tables for `bodies`, to append to a scene file.
+    pub fn to_toml(bodies: &[Self]) -> String {
+        // nothing rather than an empty `bodies = []`, which would land in
+        // whichever table comes before it
+        if bodies.is_empty() {
+            return String::new();
+        }
+        toml::to_string(&Scene {
+            bodies: bodies.to_vec(),
+        })
+        .expect("bodies always serialize")
+    }
+
+    // moves a polygon's points onto its centroid and the body's position
+    // with them, so it spins about its centre of mass
+    fn recentre(&mut self) {
+        let BodyShape::Polygon { points } = &mut self.shape else {
+            return;
+        };
+        let mut area = 0.0;
+        let mut centroid = Vec2::ZERO;
+        for_each_edge(points, |a, b| {
+            let cross = a.perp_dot(b);
+            area += cross;
+            centroid += (a + b) * cross;
+        });
+        if area.abs() < 0.00001 {
+            return;
+        }
+        let centroid = centroid / (3.0 * area);
+        for point in points.iter_mut() {
+            *point = (Vec2::from(*point) - centroid).into();
+        }
+        self.position = self.to_world(centroid).into();
+    }
+}
+
+fn for_each_edge(points: &[[f32; 2]], mut f: impl FnMut(Vec2, Vec2)) {
+    for (i, point) in points.iter().enumerate() {
+        f(
+            Vec2::from(*point),
+            Vec2::from(points[(i + 1) % points.len()]),
+        );
+    }
+}
+
+/// Checks that `bodies` fit in the GPU's fixed size uniform, that every
+/// polygon is convex and that every body that can be pushed has a mass.
+pub fn validate(bodies: &[RigidBody]) -> Result<(), String> {
+    if bodies.len() > MAX_BODIES {
+        return Err(format!(
+            "{} bodies, at most {} are supported",
+            bodies.len(),
+            MAX_BODIES
+        ));
+    }
+    let mut point_count = 0;
+    for body in bodies {
+        if !body.kinematic && body.density <= 0.0 {
+            return Err("a body that is not kinematic needs a density above 0".to_string());
+        }
+        match &body.shape {
+            BodyShape::Circle { radius } if *radius <= 0.0 => {
+                return Err("a circle body needs a radius above 0".to_string());
+            }
+            BodyShape::Box { half_size } if half_size[0] <= 0.0 || half_size[1] <= 0.0 => {
+                return Err("a box body needs half sizes above 0".to_string());
+            }
+            BodyShape::Polygon { points } => {
+                if points.len() < 3 {
+                    return Err(format!(
+                        "a polygon body needs at least 3 points, got {}",
+                        points.len()
+                    ));
+                }
+                let mut turns = (false, false);
+                for i in 0..points.len() {
+                    let a = Vec2::from(points[i]);
+                    let b = Vec2::from(points[(i + 1) % points.len()]);
+                    let c = Vec2::from(points[(i + 2) % points.len()]);
+                    let turn = (b - a).perp_dot(c - b);
+                    turns.0 |= turn > 0.0;
+                    turns.1 |= turn < 0.0;
+                }
+                if turns.0 && turns.1 {
+                    return Err("polygon bodies must be convex".to_string());
+                }
+                point_count += points.len();
+            }
+            _ => {}
+        }
+    }
+    if point_count > MAX_BODY_POINTS {
+        return Err(format!(
+            "{} polygon body points, at most {} are supported",
+            point_count, MAX_BODY_POINTS
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the fluid can push back on `bodies` under `params`. Only the
+/// SPH solver with boundary particles measures that push, the others would
+/// leave a body the fluid cannot move, so bodies that are not kinematic are
+/// refused with them.
+pub fn check_params(bodies: &[RigidBody], params: &SimulationParams) -> Result<(), String> {
+    if bodies.iter().any(|body| !body.kinematic) && !params.boundary_particles() {
+        return Err(
+            "bodies that are not kinematic need solver = \"sph\" and boundary = \"particles\""
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Advances every body by one step. `samples` are the boundary particles
+/// with the force the fluid put on each, their sum per body is its push.
+pub fn step(bodies: &mut [RigidBody], samples: &[GpuBoundaryParticle], params: &SimulationParams) {
+    let mut loads = vec![(Vec2::ZERO, 0.0); bodies.len()];
+    for sample in samples {
+        let Some(body) = bodies.get(sample.body as usize) else {
+            continue;
+        };
+        let force = Vec2::from(sample.force);
+        let r = Vec2::from(sample.pos) - Vec2::from(body.position);
+        let load = &mut loads[sample.body as usize];
+        load.0 += force;
+        load.1 += r.perp_dot(force);
+    }
+    for (body, (force, torque)) in bodies.iter_mut().zip(loads) {
+        body.integrate(force, torque, params);
+    }
+}
+
+/// Pushes a particle that overlaps a body back out along the body's SDF
+/// gradient, then applies `collider_restitution` and `collider_friction` to
+/// its velocity relative to the body's surface.
+pub fn collide(bodies: &[RigidBody], pos: &mut Vec2, vel: &mut Vec2, params: &SimulationParams) {
+    for body in bodies {
+        let distance = body.signed_distance(*pos);
+        if distance >= params.radius {
+            continue;
+        }
+        let gradient = Vec2::new(
+            body.signed_distance(*pos + Vec2::X * GRADIENT_STEP)
+                - body.signed_distance(*pos - Vec2::X * GRADIENT_STEP),
+            body.signed_distance(*pos + Vec2::Y * GRADIENT_STEP)
+                - body.signed_distance(*pos - Vec2::Y * GRADIENT_STEP),
+        );
+        let length = gradient.length();
+        if length < 0.00001 {
+            continue;
+        }
+        let normal = gradient / length;
+        let surface_velocity = body.surface_velocity(*pos);
+        *pos += normal * (params.radius - distance);
+
+        let relative = *vel - surface_velocity;
+        let normal_speed = relative.dot(normal);
+        if normal_speed < 0.0 {
+            let tangent_vel = relative - normal * normal_speed;
+            *vel = surface_velocity + tangent_vel * (1.0 - params.collider_friction)
+                - normal * normal_speed * params.collider_restitution;
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use crate::collider::Collider;
+use crate::constants::SimulationParams;
+use crate::material::{self, Material};
+use crate::rigid_body::{self, RigidBody};
+use std::io;
+use std::path::Path;
+
+/// Everything a scene file holds: the parameters as top-level keys and the
+/// `[[colliders]]`, `[[bodies]]` and `[[materials]]` tables after them.
+/// Snapshots embed the same TOML.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    pub params: SimulationParams,
+    pub colliders: Vec<Collider>,
+    pub bodies: Vec<RigidBody>,
+    pub materials: Vec<Material>,
+}
+
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_toml())
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let params =
+            SimulationParams::from_toml(text).map_err(|e| format!("invalid parameters: {}", e))?;
+        params
+            .validate()
+            .map_err(|e| format!("invalid parameters: {}", e))?;
+        let scene = Self {
+            params,
+            colliders: Collider::from_toml(text)
+                .map_err(|e| format!("invalid colliders: {}", e))?,
+            bodies: RigidBody::from_toml(text).map_err(|e| format!("invalid bodies: {}", e))?,
+            materials: Material::from_toml(text)
+                .map_err(|e| format!("invalid materials: {}", e))?,
+        };
+        material::check_solver(&scene.materials, &scene.params)
+            .map_err(|e| format!("invalid materials: {}", e))?;
+        rigid_body::check_params(&scene.bodies, &scene.params)
+            .map_err(|e| format!("invalid bodies: {}", e))?;
+        Ok(scene)
+    }
+
+    pub fn to_toml(&self) -> String {
+        to_toml(&self.params, &self.colliders, &self.bodies, &self.materials)
+    }
+}
+
+/// The scene file for these, without having to own them. The parameters come
+/// first: tables must follow the top-level keys.
+pub fn to_toml(
+    params: &SimulationParams,
+    colliders: &[Collider],
+    bodies: &[RigidBody],
+    materials: &[Material],
+) -> String {
+    params.to_toml()
+        + &Collider::to_toml(colliders)
+        + &RigidBody::to_toml(bodies)
+        + &Material::to_toml(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collider::{Operation, Shape};
+    use crate::constants::{Boundary, EquationOfState, ParamEnum, Solver};
+    use crate::rigid_body::BodyShape;
+
+    fn scene() -> Scene {
+        Scene {
+            params: SimulationParams {
+                no_particles: 1234,
+                gravity: [1.5, -300.0],
+                solver: Solver::Sph.to_u32(),
+                boundary: Boundary::Particles.to_u32(),
+                equation_of_state: EquationOfState::Tait.to_u32(),
+                ..Default::default()
+            },
+            colliders: vec![
+                Collider {
+                    shape: Shape::Box {
+                        center: [400.0, 300.0],
+                        half_size: [390.0, 290.0],
+                        angle: 0.0,
+                    },
+                    operation: Operation::Union,
+                    container: true,
+                    temperature: None,
+                },
+                Collider {
+                    shape: Shape::Circle {
+                        center: [200.0, 150.0],
+                        radius: 40.0,
+                    },
+                    operation: Operation::Subtract,
+                    container: false,
+                    temperature: Some(80.0),
+                },
+                Collider {
+                    shape: Shape::Polygon {
+                        points: vec![[0.0, 0.0], [50.0, 0.0], [25.0, 40.0]],
+                    },
+                    operation: Operation::Union,
+                    container: false,
+                    temperature: None,
+                },
+            ],
+            bodies: vec![
+                RigidBody {
+                    shape: BodyShape::Box {
+                        half_size: [30.0, 10.0],
+                    },
+                    position: [300.0, 100.0],
+                    angle: 0.5,
+                    velocity: [0.0, 0.0],
+                    angular_velocity: 0.0,
+                    density: 0.05,
+                    kinematic: false,
+                },
+                RigidBody {
+                    shape: BodyShape::Circle { radius: 20.0 },
+                    position: [500.0, 200.0],
+                    angle: 0.0,
+                    velocity: [10.0, 0.0],
+                    angular_velocity: 1.0,
+                    density: 0.0,
+                    kinematic: true,
+                },
+            ],
+            materials: vec![
+                Material::default(),
+                Material {
+                    mass: Some(0.6),
+                    rest_density: Some(0.054),
+                    viscosity: Some(2.0),
+                    colour: [0.9, 0.7, 0.1],
+                    share: 0.5,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let original = scene();
+        let loaded = Scene::from_toml(&original.to_toml()).unwrap();
+        assert_eq!(
+            bytemuck::bytes_of(&loaded.params),
+            bytemuck::bytes_of(&original.params)
+        );
+        assert_eq!(loaded.colliders, original.colliders);
+        assert_eq!(loaded.bodies, original.bodies);
+        assert_eq!(loaded.materials, original.materials);
+    }
+
+    #[test]
+    fn missing_keys_take_their_defaults() {
+        let loaded = Scene::from_toml("no_particles = 10").unwrap();
+        let expected = SimulationParams {
+            no_particles: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            bytemuck::bytes_of(&loaded.params),
+            bytemuck::bytes_of(&expected)
+        );
+        assert!(loaded.colliders.is_empty() && loaded.bodies.is_empty());
+        assert!(loaded.materials.is_empty());
+    }
+
+    #[test]
+    fn refuses_what_the_solver_cannot_run() {
+        let mut scene = scene();
+        scene.params.solver = Solver::Pcisph.to_u32();
+        let error = Scene::from_toml(&scene.to_toml()).unwrap_err();
+        assert!(error.starts_with("invalid bodies"), "{}", error);
+        scene.params.no_particles = 0;
+        let error = Scene::from_toml(&scene.to_toml()).unwrap_err();
+        assert!(error.starts_with("invalid parameters"), "{}", error);
+    }
+}
@@ -0,0 +1,281 @@
+use crate::collider::Collider;
+use crate::constants::SimulationParams;
+use crate::gpu::particle::GpuParticle;
+use crate::material::Material;
+use crate::rigid_body::RigidBody;
+use crate::scene::{self, Scene};
+use bytemuck::Zeroable;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// File layout. Header fields are little-endian, the particle blobs are copied
+// as-is, which is also little-endian on every target wgpu supports:
+//
+// magic            8 bytes  "SPHSNAP\0"
+// version          u32
+// particle_size    u32      size_of::<GpuParticle>() of the writer
+// particle_count   u32
+// params_len       u32
+// time             f64
+// params           params_len bytes, the same TOML as a scene file, colliders, bodies and materials included
+// particles        particle_count * particle_size bytes
+// predicted_pos    particle_count * 8 bytes
+//
+// Params are stored as TOML rather than the raw uniform so snapshots keep
+// loading after fields are added to SimulationParams (missing ones take their
+// defaults). The particle size is stored so a file written by a build with a
+// different GpuParticle layout is rejected instead of misread.
+// Bump VERSION whenever this layout or GpuParticle changes:
+//
+// 1  the raw SimulationParams uniform
+// 2  params as TOML
+// 3  GpuParticle.phase, materials in the TOML
+// 4  GpuParticle.temperature
+// 5  GpuParticle.accel, the last step's acceleration for leapfrog, Verlet
+//    and RK2
+const MAGIC: [u8; 8] = *b"SPHSNAP\0";
+pub const VERSION: u32 = 5;
+
+/// The full state of a run: enough to resume it bit-for-bit on either backend.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub params: SimulationParams,
+    pub colliders: Vec<Collider>,
+    pub bodies: Vec<RigidBody>,
+    pub materials: Vec<Material>,
+    pub time: f64,
+    pub particles: Vec<GpuParticle>,
+    pub predicted_pos: Vec<[f32; 2]>,
+}
+
+impl Snapshot {
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        if self.particles.len() != self.predicted_pos.len() {
+            return Err(invalid_data(format!(
+                "{} particles but {} predicted positions",
+                self.particles.len(),
+                self.predicted_pos.len()
+            )));
+        }
+        let params = scene::to_toml(&self.params, &self.colliders, &self.bodies, &self.materials);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(size_of::<GpuParticle>() as u32).to_le_bytes())?;
+        writer.write_all(&(self.particles.len() as u32).to_le_bytes())?;
+        writer.write_all(&(params.len() as u32).to_le_bytes())?;
+        writer.write_all(&self.time.to_le_bytes())?;
+        writer.write_all(params.as_bytes())?;
+        writer.write_all(bytemuck::cast_slice(&self.particles))?;
+        writer.write_all(bytemuck::cast_slice(&self.predicted_pos))?;
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not a simulation snapshot".to_string()));
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "snapshot version {} is not supported, expected {}",
+                version, VERSION
+            )));
+        }
+        let particle_size = read_u32(reader)? as usize;
+        if particle_size != size_of::<GpuParticle>() {
+            return Err(invalid_data(format!(
+                "snapshot has {} byte particles, this build uses {}",
+                particle_size,
+                size_of::<GpuParticle>()
+            )));
+        }
+        let particle_count = read_u32(reader)? as usize;
+        if particle_count == 0 {
+            return Err(invalid_data("snapshot has no particles".to_string()));
+        }
+        let params_len = read_u32(reader)? as usize;
+
+        let mut time = [0u8; 8];
+        reader.read_exact(&mut time)?;
+        let time = f64::from_le_bytes(time);
+
+        // The counts come from the file, so nothing is sized from them until
+        // the bytes they describe have actually been read: a corrupt header
+        // must not turn into a huge allocation.
+        let too_many = || invalid_data(format!("{} particles is too many", particle_count));
+        let particles_len = particle_count
+            .checked_mul(particle_size)
+            .ok_or_else(too_many)?;
+        let predicted_len = particle_count
+            .checked_mul(size_of::<[f32; 2]>())
+            .ok_or_else(too_many)?;
+        let expected = params_len
+            .checked_add(particles_len)
+            .and_then(|len| len.checked_add(predicted_len))
+            .ok_or_else(too_many)?;
+        let mut body = Vec::new();
+        reader
+            .by_ref()
+            .take(expected as u64)
+            .read_to_end(&mut body)?;
+        if body.len() < expected {
+            return Err(invalid_data(format!(
+                "snapshot is truncated: {} particles and {} bytes of parameters need {} bytes, found {}",
+                particle_count,
+                params_len,
+                expected,
+                body.len()
+            )));
+        }
+        let (params, body) = body.split_at(params_len);
+        let (particle_bytes, predicted_bytes) = body.split_at(particles_len);
+
+        let params = std::str::from_utf8(params)
+            .map_err(|_| invalid_data("snapshot parameters are not UTF-8".to_string()))?;
+        let Scene {
+            params,
+            colliders,
+            bodies,
+            materials,
+        } = Scene::from_toml(params).map_err(|e| invalid_data(format!("snapshot has {}", e)))?;
+
+        // copied rather than cast, the byte buffer is not aligned for them
+        let mut particles = vec![GpuParticle::zeroed(); particle_count];
+        bytemuck::cast_slice_mut(&mut particles).copy_from_slice(particle_bytes);
+        let mut predicted_pos = vec![[0.0f32; 2]; particle_count];
+        bytemuck::cast_slice_mut(&mut predicted_pos).copy_from_slice(predicted_bytes);
+
+        Ok(Self {
+            params,
+            colliders,
+            bodies,
+            materials,
+            time,
+            particles,
+            predicted_pos,
+        })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let particles = (0..3)
+            .map(|i| GpuParticle {
+                pos: [i as f32, 2.0 * i as f32],
+                vel: [0.5, -0.25],
+                density: 1.5,
+                phase: i,
+                ..GpuParticle::zeroed()
+            })
+            .collect();
+        Snapshot {
+            params: SimulationParams {
+                no_particles: 3,
+                gas_constant: 1234.5,
+                ..Default::default()
+            },
+            colliders: Vec::new(),
+            bodies: Vec::new(),
+            materials: vec![Material::default(); 3],
+            time: 1.25,
+            particles,
+            predicted_pos: vec![[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]],
+        }
+    }
+
+    fn bytes(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        snapshot.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    // the header field at `offset` replaced by `value`
+    fn patched(mut bytes: Vec<u8>, offset: usize, value: u32) -> Vec<u8> {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        Snapshot::read_from(&mut &bytes[..])
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn round_trips() {
+        let original = snapshot();
+        let loaded = Snapshot::read_from(&mut &bytes(&original)[..]).unwrap();
+        assert_eq!(
+            bytemuck::bytes_of(&loaded.params),
+            bytemuck::bytes_of(&original.params)
+        );
+        assert_eq!(loaded.materials, original.materials);
+        assert_eq!(loaded.time, original.time);
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&loaded.particles),
+            bytemuck::cast_slice::<_, u8>(&original.particles)
+        );
+        assert_eq!(loaded.predicted_pos, original.predicted_pos);
+    }
+
+    #[test]
+    fn rejects_another_version() {
+        let bytes = patched(bytes(&snapshot()), 8, VERSION + 1);
+        assert!(error(&bytes).contains("version"), "{}", error(&bytes));
+    }
+
+    #[test]
+    fn rejects_another_particle_size() {
+        let size = size_of::<GpuParticle>() as u32 + 4;
+        let bytes = patched(bytes(&snapshot()), 12, size);
+        assert!(
+            error(&bytes).contains("byte particles"),
+            "{}",
+            error(&bytes)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = bytes(&snapshot());
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(
+            error(truncated).contains("truncated"),
+            "{}",
+            error(truncated)
+        );
+        // a particle count far past what the file holds is not allocated for
+        let inflated = patched(bytes.clone(), 16, u32::MAX);
+        assert!(
+            error(&inflated).contains("truncated"),
+            "{}",
+            error(&inflated)
+        );
+    }
+}